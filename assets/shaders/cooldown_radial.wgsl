@@ -0,0 +1,21 @@
+#import bevy_ui::ui_vertex_output::UiVertexOutput
+
+@group(1) @binding(0) var<uniform> color: vec4<f32>;
+// fraction of the cooldown left, the darkened sweep starts at the top and shrinks clockwise
+@group(1) @binding(1) var<uniform> remaining: f32;
+
+const TAU: f32 = 6.28318530718;
+
+@fragment
+fn fragment(in: UiVertexOutput) -> @location(0) vec4<f32> {
+    let offset = in.uv - vec2<f32>(0.5);
+    if length(offset) > 0.5 {
+        discard;
+    }
+
+    let angle = fract(atan2(offset.x, -offset.y) / TAU + 1.0);
+    if angle < remaining {
+        return vec4<f32>(color.rgb * 0.25, color.a);
+    }
+    return color;
+}
@@ -619,7 +619,10 @@ impl<Ctx> NetcodeServer<Ctx> {
         if let Some(denied_reason) = self
             .cfg
             .connection_request_handler
-            .handle_request(crate::prelude::ClientId::Netcode(token.client_id))
+            .handle_request_with_user_data(
+                crate::prelude::ClientId::Netcode(token.client_id),
+                &token.user_data,
+            )
         {
             debug!("server denied connection request. handle_connection_request_fn returned false");
             self.send_to_addr(
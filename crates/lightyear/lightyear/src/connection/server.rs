@@ -8,6 +8,7 @@ use std::fmt::Debug;
 use std::sync::Arc;
 
 use crate::connection::id::ClientId;
+use crate::connection::netcode::USER_DATA_BYTES;
 #[cfg(all(feature = "steam", not(target_family = "wasm")))]
 use crate::connection::steam::{server::SteamConfig, steamworks_client::SteamworksClient};
 use crate::packet::packet_builder::RecvPayload;
@@ -36,6 +37,18 @@ pub trait ConnectionRequestHandler: Debug + Send + Sync {
     /// Returns None if the connection is accepted,
     /// Returns Some(reason) if the connection is denied.
     fn handle_request(&self, client_id: ClientId) -> Option<DeniedReason>;
+
+    /// Handle a connection request from a client that connected with a [`ConnectToken`](crate::prelude::ConnectToken).
+    /// `user_data` is the user data written in the token by the service that issued it.
+    ///
+    /// By default, the user data is ignored and [`handle_request`](Self::handle_request) is called.
+    fn handle_request_with_user_data(
+        &self,
+        client_id: ClientId,
+        user_data: &[u8; USER_DATA_BYTES],
+    ) -> Option<DeniedReason> {
+        self.handle_request(client_id)
+    }
 }
 
 /// By default, all connection requests are accepted by the server.
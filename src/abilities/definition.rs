@@ -0,0 +1,292 @@
+use std::time::Duration;
+
+use bevy::{asset::{io::Reader, ron, AssetLoader, AsyncReadExt, LoadContext}, prelude::*};
+use derive_more::derive::{Display, Error, From};
+use serde::{Deserialize, Serialize};
+
+use crate::{ability_framework::{ability_map::AbilityMap, cooldown::Cooldown, pool::AbilityCost, pools::{heat::{Heat, HeatPool}, life::{Life, LifePool}, mana::{Mana, ManaPool}}, status_effect::{StackingRule, StatusEffect}, AbilityBundle, AbilityCharge, ActivationMode}, player::PlayerActions, team::Targets};
+
+/// Designer-facing description of an ability, loaded from `assets/abilities/*.ability.ron`.
+#[derive(Asset, TypePath, Debug, Deserialize)]
+pub struct AbilityDefinition {
+    pub name: String,
+    /// Action the ability is bound to when a player spawns. Unbound abilities are not granted by default.
+    #[serde(default)]
+    pub binding: Option<PlayerActions>,
+    #[serde(default)]
+    pub mana_cost: f32,
+    #[serde(default)]
+    pub life_cost: f32,
+    /// Heat generated when the ability is used
+    #[serde(default)]
+    pub heat: f32,
+    /// Cooldown in seconds
+    pub cooldown: f32,
+    #[serde(default)]
+    pub activation: ActivationMode,
+    /// Limits how long `Charge` and `Channel` abilities can be held, required for `Channel`
+    #[serde(default)]
+    pub charge: Option<ChargeRules>,
+    #[serde(default)]
+    pub effects: Vec<AbilityEffect>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChargeRules {
+    /// Maximum charge or channel time in seconds
+    pub max: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Reflect)]
+pub enum AbilityEffect {
+    /// Impulse in the direction of movement
+    Dash {
+        /// Distance travelled as a multiple of the character's `MoveSpeed`
+        speed_multiplier: f32,
+        /// Additional distance for every second the ability is charged
+        charge_scaling: f32,
+    },
+    /// Fires a projectile towards the cursor
+    Projectile {
+        speed: f32,
+        radius: f32,
+        damage: f32,
+        /// Seconds before the projectile despawns if it hasn't hit anything
+        lifetime: f32,
+        /// Status effects applied to the player hit by the projectile
+        #[serde(default)]
+        on_hit: Vec<StatusEffect>,
+        /// Players the projectile can hit, relative to the caster's team
+        #[serde(default)]
+        targets: Targets,
+    },
+    /// Applies a status effect to the caster
+    Status(StatusEffect),
+}
+
+/// Effects applied when the ability is triggered. Replicated so that clients can predict them.
+#[derive(Component, Serialize, Deserialize, PartialEq, Clone, Reflect, Deref)]
+pub struct AbilityEffects(pub Vec<AbilityEffect>);
+
+/// Server-side link between a spawned ability and the definition it was built from, used for hot-reloading.
+#[derive(Component)]
+pub struct AbilityDefinitionHandle(pub Handle<AbilityDefinition>);
+
+impl AbilityDefinition {
+    /// Parts of the ability that don't change while it is used, safe to re-insert on a live ability
+    pub fn rules(&self) -> (ActivationMode, AbilityCost<ManaPool>, AbilityCost<LifePool>, AbilityCost<HeatPool>, AbilityEffects) {
+        (
+            self.activation,
+            AbilityCost(Mana(self.mana_cost)),
+            AbilityCost(Life(self.life_cost)),
+            AbilityCost(Heat(self.heat)),
+            AbilityEffects(self.effects.clone()),
+        )
+    }
+
+    /// Every status effect the ability can apply, to its caster or to the players it hits
+    fn status_effects(&self) -> impl Iterator<Item = &StatusEffect> {
+        self.effects.iter().flat_map(|effect| match effect {
+            AbilityEffect::Projectile { on_hit, .. } => on_hit.iter().collect(),
+            AbilityEffect::Status(status) => vec![status],
+            AbilityEffect::Dash { .. } => Vec::new(),
+        })
+    }
+
+    fn max_charge(&self) -> Duration {
+        Duration::from_secs_f32(self.charge.as_ref().map_or(0., |c| c.max))
+    }
+
+    pub fn bundle(&self) -> (AbilityBundle, AbilityCost<ManaPool>, AbilityCost<LifePool>, AbilityCost<HeatPool>, AbilityCharge, AbilityEffects) {
+        (
+            AbilityBundle::new(Duration::from_secs_f32(self.cooldown))
+                .with_activation(self.activation),
+            AbilityCost(Mana(self.mana_cost)),
+            AbilityCost(Life(self.life_cost)),
+            AbilityCost(Heat(self.heat)),
+            AbilityCharge::new(self.max_charge()),
+            AbilityEffects(self.effects.clone()),
+        )
+    }
+}
+
+impl AbilityDefinition {
+    fn validate(&self) -> Result<(), AbilityDefinitionLoaderError> {
+        // Cooldown::new does not accept a zero duration
+        if self.cooldown <= 0. {
+            return Err(AbilityDefinitionLoaderError::InvalidCooldown(self.cooldown));
+        }
+        if let Some(effect) = self.status_effects().find(|effect| effect.stacking == StackingRule::Stack { max: 0 }) {
+            return Err(AbilityDefinitionLoaderError::InvalidStacking(effect.name.clone()));
+        }
+        // a channel ends once its charge is full, without a max it would end on the tick it starts
+        let max_charge = self.charge.as_ref().map_or(0., |charge| charge.max);
+        if self.activation == ActivationMode::Channel && max_charge <= 0. {
+            return Err(AbilityDefinitionLoaderError::InvalidChannel(max_charge));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct AbilityDefinitionLoader;
+
+#[derive(Debug, Display, Error, From)]
+pub enum AbilityDefinitionLoaderError {
+    #[display("could not read ability definition: {_0}")]
+    Io(std::io::Error),
+    #[display("could not parse ability definition: {_0}")]
+    Ron(ron::error::SpannedError),
+    #[display("ability cooldown must be positive, got {_0}")]
+    #[from(ignore)]
+    InvalidCooldown(#[error(not(source))] f32),
+    #[display("status effect {_0} must allow at least one stack")]
+    #[from(ignore)]
+    InvalidStacking(#[error(not(source))] String),
+    #[display("channelled abilities need a positive charge max, got {_0}")]
+    #[from(ignore)]
+    InvalidChannel(#[error(not(source))] f32),
+}
+
+impl AssetLoader for AbilityDefinitionLoader {
+    type Asset = AbilityDefinition;
+    type Settings = ();
+    type Error = AbilityDefinitionLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<AbilityDefinition, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        let definition = ron::de::from_bytes::<AbilityDefinition>(&bytes)?;
+        definition.validate()?;
+
+        Ok(definition)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ability.ron"]
+    }
+}
+
+/// Re-applies costs, cooldowns and effects to every ability spawned from a definition that changed on disk.
+/// Cooldowns and charges in progress carry over, and abilities move to their new binding unless another
+/// ability already holds it. Abilities that were unbound were never spawned, they are only granted to
+/// players spawned from now on.
+/// The components are replicated so clients pick up the new values as well.
+pub fn reload_ability_definitions(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<AbilityDefinition>>,
+    definitions: Res<Assets<AbilityDefinition>>,
+    mut query: Query<(Entity, &AbilityDefinitionHandle, &mut Cooldown, &mut AbilityCharge)>,
+    mut player_query: Query<&mut AbilityMap<PlayerActions>>,
+) {
+    for event in events.read() {
+        let AssetEvent::Modified { id } = event else {
+            continue;
+        };
+
+        let Some(definition) = definitions.get(*id) else {
+            continue;
+        };
+
+        for (entity, handle, mut cooldown, mut charge) in query.iter_mut() {
+            if handle.0.id() != *id {
+                continue;
+            }
+
+            info!("Reloading ability {} on {entity:?}", definition.name);
+            commands.entity(entity).insert(definition.rules());
+            cooldown.set_duration(Duration::from_secs_f32(definition.cooldown));
+            charge.set_max(definition.max_charge());
+
+            for mut ability_map in player_query.iter_mut() {
+                let Some(action) = ability_map.action(entity).copied() else {
+                    continue;
+                };
+                if Some(action) == definition.binding {
+                    continue;
+                }
+
+                if let Some(binding) = definition.binding {
+                    if let Ok(bound) = ability_map.mapped(binding) {
+                        warn!("Not moving {} to {binding:?}, {bound:?} is already bound to it", definition.name);
+                        continue;
+                    }
+                }
+
+                ability_map.remove_binding(&action);
+                if let Some(binding) = definition.binding {
+                    ability_map.add_binding(binding, entity);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> AbilityDefinition {
+        ron::de::from_str(source).unwrap()
+    }
+
+    #[test]
+    fn channels_need_a_charge_max() {
+        let unlimited = parse("(name: \"Beam\", cooldown: 1., activation: Channel)");
+        assert!(matches!(unlimited.validate(), Err(AbilityDefinitionLoaderError::InvalidChannel(_))));
+
+        let instant = parse("(name: \"Beam\", cooldown: 1., activation: Channel, charge: Some((max: 0.)))");
+        assert!(matches!(instant.validate(), Err(AbilityDefinitionLoaderError::InvalidChannel(_))));
+
+        let beam = parse("(name: \"Beam\", cooldown: 1., activation: Channel, charge: Some((max: 2.)))");
+        assert!(beam.validate().is_ok());
+    }
+
+    #[test]
+    fn reloading_does_not_steal_a_binding() {
+        let mut app = App::new();
+        app.init_resource::<Assets<AbilityDefinition>>();
+        app.add_event::<AssetEvent<AbilityDefinition>>();
+        app.add_systems(Update, reload_ability_definitions);
+
+        let mut definitions = app.world_mut().resource_mut::<Assets<AbilityDefinition>>();
+        let dodge = definitions.add(parse("(name: \"Dodge\", binding: Some(Dodge), cooldown: 1.)"));
+        let attack = definitions.add(parse("(name: \"Attack\", binding: Some(PrimaryAttack), cooldown: 1.)"));
+
+        let mut spawn_ability = |handle: &Handle<AbilityDefinition>| {
+            app.world_mut().spawn((
+                AbilityDefinitionHandle(handle.clone()),
+                Cooldown::new(Duration::from_secs(1)),
+                AbilityCharge::new(Duration::ZERO),
+            )).id()
+        };
+        let dodge_entity = spawn_ability(&dodge);
+        let attack_entity = spawn_ability(&attack);
+
+        let mut ability_map = AbilityMap::new();
+        ability_map.add_binding(PlayerActions::Dodge, dodge_entity);
+        ability_map.add_binding(PlayerActions::PrimaryAttack, attack_entity);
+        let player = app.world_mut().spawn(ability_map).id();
+
+        app.world_mut().resource_mut::<Assets<AbilityDefinition>>().get_mut(&dodge).unwrap().binding = Some(PlayerActions::PrimaryAttack);
+        app.world_mut().send_event(AssetEvent::Modified { id: dodge.id() });
+        app.update();
+
+        let ability_map = app.world().get::<AbilityMap<PlayerActions>>(player).unwrap();
+        assert_eq!(ability_map.mapped(PlayerActions::Dodge).ok(), Some(dodge_entity));
+        assert_eq!(ability_map.mapped(PlayerActions::PrimaryAttack).ok(), Some(attack_entity));
+    }
+
+    #[test]
+    fn cooldowns_must_be_positive() {
+        let definition = parse("(name: \"Dash\", cooldown: 0.)");
+        assert!(matches!(definition.validate(), Err(AbilityDefinitionLoaderError::InvalidCooldown(_))));
+    }
+}
@@ -1,37 +1,71 @@
 use bevy::prelude::*;
-use serde::{Deserialize, Serialize};
+use definition::{AbilityDefinition, AbilityDefinitionLoader, AbilityEffect, AbilityEffects};
 
-use crate::{ability_framework::{Ability, AbilityCharge, TriggerAbility}, physics::CharacterQuery, player::MoveSpeed, shared::FixedSet};
+use crate::{ability_framework::{pipeline::AbilityPipelineSet, status_effect::StatusEffects, Ability, AbilityCharge, TriggerAbility}, physics::CharacterQuery, player::{MoveSpeed, PlayerId}, shared::FixedSet};
+
+pub mod definition;
 
 pub struct AbilitiesPlugin;
 
 impl Plugin for AbilitiesPlugin {
     fn build(&self, app: &mut App) {
+        app.init_asset::<AbilityDefinition>()
+            .init_asset_loader::<AbilityDefinitionLoader>();
+
+        app.register_type::<AbilityEffects>();
+
         app.add_systems(FixedUpdate, (
-                handle_dodge,
-            ).in_set(FixedSet::Main)
+                handle_dash,
+                handle_self_status,
+            )
+            .after(AbilityPipelineSet)
+            .in_set(FixedSet::Main)
         );
     }
 }
 
-#[derive(Component, Serialize, Deserialize, Clone, PartialEq)]
-pub struct Dodge;
-
-fn handle_dodge(
+fn handle_dash(
     mut events: EventReader<TriggerAbility>,
-    dash_query: Query<&AbilityCharge, (With<Dodge>, With<Ability>)>,
+    ability_query: Query<(&AbilityEffects, &AbilityCharge), With<Ability>>,
     mut character_query: Query<(CharacterQuery, &MoveSpeed)>,
 ) {
     for trigger in events.read() {
-        if let Ok(charge) = dash_query.get(trigger.ability) {
+        if let Ok((effects, charge)) = ability_query.get(trigger.ability) {
             if let Ok((mut character, speed)) = character_query.get_mut(trigger.source) {
-                let move_dir = character.linear_velocity.normalize_or_zero();
+                for effect in effects.iter() {
+                    let AbilityEffect::Dash { speed_multiplier, charge_scaling } = effect else {
+                        continue;
+                    };
 
-                let base_distance = speed.0 * 3.;
-                // multiply the base distance for every additional second the charge is held.
-                let charge_factor = charge.duration().as_secs_f32() + 1.;
-                character.external_impulse.apply_impulse(move_dir.normalize_or_zero() * base_distance * charge_factor);
+                    let move_dir = character.linear_velocity.normalize_or_zero();
+
+                    let base_distance = speed.0 * speed_multiplier;
+                    // scale the base distance for every additional second the charge is held.
+                    let charge_factor = charge.duration().as_secs_f32() * charge_scaling + 1.;
+                    character.external_impulse.apply_impulse(move_dir * base_distance * charge_factor);
+                }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+fn handle_self_status(
+    mut events: EventReader<TriggerAbility>,
+    ability_query: Query<&AbilityEffects, With<Ability>>,
+    mut status_query: Query<(&mut StatusEffects, Option<&PlayerId>)>,
+) {
+    for trigger in events.read() {
+        let Ok(effects) = ability_query.get(trigger.ability) else {
+            continue;
+        };
+        let Ok((mut status_effects, player_id)) = status_query.get_mut(trigger.source) else {
+            continue;
+        };
+
+        for effect in effects.iter() {
+            if let AbilityEffect::Status(status) = effect {
+                status_effects.apply(status, player_id.map(|player_id| player_id.0));
+            }
+        }
+    }
+}
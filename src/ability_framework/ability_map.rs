@@ -20,10 +20,23 @@ impl<A: Actionlike> AbilityMap<A> {
         self.bindings.insert(action, entity);
     }
 
+    pub fn remove_binding(&mut self, action: &A) -> Option<Entity> {
+        self.bindings.remove(action)
+    }
+
+    /// Action the ability is bound to, if any
+    pub fn action(&self, ability: Entity) -> Option<&A> {
+        self.bindings.iter().find(|(_, bound)| **bound == ability).map(|(action, _)| action)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&A, &Entity)> {
+        self.bindings.iter()
+    }
+
     pub fn mapped(&self, action: A) -> Result<Entity, CannotUseAbility> {
         match self.bindings.get(&action) {
             Some(ability) => {
-                Ok(ability.clone())
+                Ok(*ability)
             },
             None => Err(CannotUseAbility::AbilityNotBound)
         }
@@ -59,6 +59,19 @@ impl Cooldown {
         Ok(())
     }
 
+    pub fn duration(&self) -> Duration {
+        self.cd
+    }
+
+    /// Changes the cooldown duration, a cooldown in progress keeps its remaining time up to the new duration
+    pub fn set_duration(&mut self, cd: Duration) {
+        assert!(cd != Duration::ZERO);
+
+        let remaining = self.remaining().min(cd);
+        self.cd = cd;
+        self.elapsed = cd - remaining;
+    }
+
     pub fn remaining(&self) -> Duration {
         self.cd.saturating_sub(self.elapsed)
     }
@@ -2,15 +2,16 @@ use std::time::Duration;
 
 use bevy::{ecs::query::QueryData, prelude::*};
 use cooldown::Cooldown;
-use lightyear::prelude::client::Predicted;
-use pool::{predict_tick_pools_regen, tick_pools_regen, AbilityCost, Pool};
-use pools::{life::{Life, LifePool}, mana::{Mana, ManaPool}};
+use lightyear::prelude::client::{Confirmed, Interpolated, Predicted};
 use serde::{Deserialize, Serialize};
+use status_effect::{predict_tick_status_effects, tick_status_effects, StatusEffectSet};
 
 pub mod cooldown;
 pub mod pool;
 pub mod pools;
 pub mod ability_map;
+pub mod pipeline;
+pub mod status_effect;
 
 pub struct AbilityFrameworkServerPlugin;
 
@@ -18,10 +19,10 @@ impl Plugin for AbilityFrameworkServerPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app
         .add_systems(FixedUpdate, (
-            tick_pools_regen::<LifePool>,
-            tick_pools_regen::<ManaPool>,
             tick_ability_cds,
             tick_ability_charge,
+            tick_status_effects
+                .in_set(StatusEffectSet),
         ));
     }
 }
@@ -32,39 +33,96 @@ impl Plugin for AbilityFrameworkClientPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app
         .add_systems(FixedUpdate, (
-            predict_tick_pools_regen::<LifePool>,
-            predict_tick_pools_regen::<ManaPool>,
             predict_tick_ability_cds,
             predict_tick_ability_charge,
+            predict_tick_status_effects
+                .in_set(StatusEffectSet),
         ));
     }
 }
 
+/// Filter for entities simulated locally: everything on the server, pre-predicted or predicted entities on the client
+pub type Simulated = (Without<Confirmed>, Without<Interpolated>);
+
 #[derive(Component, Serialize, Deserialize, PartialEq, Clone, Reflect)]
 pub struct Ability;
 
 #[derive(Component, Serialize, Deserialize, PartialEq, Clone, Reflect)]
 pub struct PredictedAbility;
 
-#[derive(Component, Serialize, Deserialize, PartialEq, Clone, Default, Reflect)]
-pub struct AbilityCharge(pub Duration);
+#[derive(Component, Serialize, Deserialize, PartialEq, Clone, Reflect)]
+pub struct AbilityCharge {
+    elapsed: Duration,
+    /// Charge is capped at this duration, holding the ability any longer has no effect
+    max: Duration,
+}
 
 impl AbilityCharge {
+    pub fn new(max: Duration) -> Self {
+        Self {
+            elapsed: Duration::ZERO,
+            max,
+        }
+    }
+
     pub fn start(&mut self) {
-        self.0 = Duration::ZERO;
+        self.elapsed = Duration::ZERO;
+    }
+
+    pub fn tick(&mut self, delta_time: Duration) {
+        self.elapsed = self.elapsed.saturating_add(delta_time).min(self.max);
     }
 
     pub fn duration(&self) -> Duration {
-        self.0
+        self.elapsed
     }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Changes the cap, a charge in progress keeps its elapsed time up to the new cap
+    pub fn set_max(&mut self, max: Duration) {
+        self.max = max;
+        self.elapsed = self.elapsed.min(max);
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.elapsed >= self.max
+    }
+}
+
+impl Default for AbilityCharge {
+    fn default() -> Self {
+        Self::new(Duration::MAX)
+    }
+}
+
+/// How the bound action drives the ability, see `pipeline::AbilityPipelinePlugin`
+#[derive(Component, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug, Default, Reflect)]
+pub enum ActivationMode {
+    /// Triggers as soon as the action is pressed
+    #[default]
+    Instant,
+    /// Charges while the action is held and triggers on release, the charge is capped by `AbilityCharge::max`
+    Charge,
+    /// Triggers on press and stays active while the action is held, for at most `AbilityCharge::max`
+    Channel,
+    /// Every press switches the ability on or off, costs are paid when it is switched on
+    Toggle,
 }
 
+/// Whether a charged, channelled or toggled ability is currently in progress
+#[derive(Component, Serialize, Deserialize, PartialEq, Clone, Default, Reflect)]
+pub struct AbilityActive(pub bool);
+
+/// Costs are added separately as `AbilityCost<P>` components, one for each pool the ability draws from
 #[derive(Bundle)]
 pub struct AbilityBundle {
     ability: Ability,
-    mp_cost: AbilityCost<ManaPool>,
-    lp_cost: AbilityCost<LifePool>,
     cooldown: Cooldown,
+    activation: ActivationMode,
+    active: AbilityActive,
 }
 
 #[derive(QueryData)]
@@ -72,73 +130,42 @@ pub struct AbilityBundle {
 pub struct AbilityState {
     pub ability: &'static mut Ability,
     pub cooldown: &'static mut Cooldown,
-    pub mp_cost: Option<&'static mut AbilityCost<ManaPool>>,
-    pub lp_cost: Option<&'static mut AbilityCost<LifePool>>,
 }
 
 impl AbilityBundle {
-    pub fn new(mp_cost: f32, life_cost: f32, cooldown: Duration) -> Self {
+    pub fn new(cooldown: Duration) -> Self {
         Self {
             ability: Ability,
-            mp_cost: AbilityCost::<ManaPool>(Mana(mp_cost)),
-            lp_cost: AbilityCost::<LifePool>(Life(life_cost)),
             cooldown: Cooldown::from_secs(cooldown.as_secs_f32()),
+            activation: ActivationMode::default(),
+            active: AbilityActive::default(),
         }
     }
+
+    pub fn with_activation(mut self, activation: ActivationMode) -> Self {
+        self.activation = activation;
+        self
+    }
 }
 
 #[allow(unused)]
 impl AbilityStateReadOnlyItem<'_> {
-    pub fn ready(&self, mana: &ManaPool, life: &LifePool) -> Result<(), CannotUseAbility> {
-        self.cooldown.ready()?;
-
-        let maybe_mp_cost = self.mp_cost.as_deref();
-        if let Some(mana_cost) = maybe_mp_cost {
-            mana.available(mana_cost.0)?;
-        }
-
-        let maybe_lp_cost = self.lp_cost.as_deref();
-        if let Some(life_cost) = maybe_lp_cost {
-            life.available(life_cost.0)?;
-        }
-
-        Ok(())
+    /// Pool costs are checked separately by `pipeline::check_pool_costs`
+    pub fn ready(&self) -> Result<(), CannotUseAbility> {
+        self.cooldown.ready()
     }
 }
 
 impl AbilityStateItem<'_> {
-    pub fn ready(&self, mana: &ManaPool, life: &LifePool) -> Result<(), CannotUseAbility> {
-        self.cooldown.ready()?;
-
-        let maybe_mp_cost = self.mp_cost.as_deref();
-        if let Some(mana_cost) = maybe_mp_cost {
-            mana.available(mana_cost.0)?;
-        }
-
-        let maybe_lp_cost = self.lp_cost.as_deref();
-        if let Some(life_cost) = maybe_lp_cost {
-            life.available(life_cost.0)?;
-        }
-
-        Ok(())
+    /// Pool costs are checked separately by `pipeline::check_pool_costs`
+    pub fn ready(&self) -> Result<(), CannotUseAbility> {
+        self.cooldown.ready()
     }
 
-    pub fn trigger(&mut self, mana: &mut ManaPool, life: &mut LifePool) -> Result<(), CannotUseAbility> {
-        self.ready(mana, life)?;
-
-        self.cooldown.trigger()?;
-
-        let maybe_mp_cost = self.mp_cost.as_deref();
-        if let Some(mana_cost) = maybe_mp_cost {
-            mana.expend(mana_cost.0)?;
-        }
-
-        let maybe_lp_cost = self.lp_cost.as_deref();
-        if let Some(life_cost) = maybe_lp_cost {
-            life.expend(life_cost.0)?;
-        }
+    pub fn trigger(&mut self) -> Result<(), CannotUseAbility> {
+        self.ready()?;
 
-        Ok(())
+        self.cooldown.trigger()
     }
 }
 
@@ -150,10 +177,12 @@ pub struct TriggerAbility {
     pub ability: Entity,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Reflect)]
 pub enum CannotUseAbility {
     OnCooldown,
     ResourceMissing,
+    /// The caster's heat reached its maximum and abilities are locked until it vents
+    Overheated,
     AbilityNotBound,
 }
 
@@ -172,7 +201,7 @@ fn tick_ability_charge(
     mut query: Query<&mut AbilityCharge>
 ) {
     for mut charge in query.iter_mut() {
-        charge.0 += time.delta();
+        charge.tick(time.delta());
     }
 }
 
@@ -190,6 +219,6 @@ fn predict_tick_ability_charge(
     mut query: Query<&mut AbilityCharge, (With<PredictedAbility>, With<Predicted>)>
 ) {
     for mut charge in query.iter_mut() {
-        charge.0 += time.delta();
+        charge.tick(time.delta());
     }
 }
\ No newline at end of file
@@ -0,0 +1,284 @@
+use std::marker::PhantomData;
+
+use bevy::{ecs::{entity::MapEntities, query::QueryFilter}, prelude::*};
+use leafwing_input_manager::{prelude::ActionState, Actionlike};
+use lightyear::prelude::client::{Predicted, Rollback};
+use serde::{Deserialize, Serialize};
+
+use super::{ability_map::AbilityMap, pool::{AbilityCost, Pool}, AbilityActive, AbilityCharge, AbilityState, ActivationMode, CannotUseAbility, TriggerAbility};
+
+/// Turns the actions of every caster matching `Filter` into ability activations.
+/// The same plugin runs on the server and, with a prediction filter, on the client.
+pub struct AbilityPipelinePlugin<A: Actionlike, Filter: QueryFilter = ()> {
+    _marker: PhantomData<(A, Filter)>,
+}
+
+impl<A: Actionlike, Filter: QueryFilter> Default for AbilityPipelinePlugin<A, Filter> {
+    fn default() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<A, Filter> Plugin for AbilityPipelinePlugin<A, Filter>
+where
+    A: Actionlike,
+    Filter: QueryFilter + Send + Sync + 'static,
+{
+    fn build(&self, app: &mut App) {
+        // shared by every action type, so only added by the first pipeline
+        if !app.is_plugin_added::<AbilityResolutionPlugin>() {
+            app.add_plugins(AbilityResolutionPlugin);
+        }
+
+        app.add_systems(FixedUpdate,
+            collect_activations::<A, Filter>
+                .in_set(AbilityPipelineStep::Collect)
+        );
+    }
+}
+
+struct AbilityResolutionPlugin;
+
+impl Plugin for AbilityResolutionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TriggerAbility>()
+            .add_event::<EndAbility>()
+            .add_event::<AbilityFailed>()
+            .init_resource::<PendingActivations>();
+
+        app.configure_sets(FixedUpdate, (
+                AbilityPipelineStep::Collect,
+                AbilityPipelineStep::CheckCosts,
+                AbilityPipelineStep::Resolve,
+                AbilityPipelineStep::PayCosts,
+            )
+            .chain()
+            .in_set(AbilityPipelineSet)
+        );
+
+        app.add_systems(FixedUpdate,
+            resolve_activations
+                .in_set(AbilityPipelineStep::Resolve)
+        );
+    }
+}
+
+/// Systems reacting to `TriggerAbility` or `EndAbility` should run after this set
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AbilityPipelineSet;
+
+/// Each pool type registered with `PoolPlugin` adds its cost checks and payments to these steps
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AbilityPipelineStep {
+    /// Inputs are turned into `PendingActivations`
+    Collect,
+    /// Activations the caster can't afford are marked as failed
+    CheckCosts,
+    /// Cooldowns and activation state are updated and events are sent
+    Resolve,
+    /// Costs of the triggered abilities are taken from the caster's pools
+    PayCosts,
+}
+
+/// Sent when a channelled or toggled ability stops being active
+#[derive(Event)]
+#[allow(unused)]
+pub struct EndAbility {
+    pub source: Entity,
+    pub ability: Entity,
+}
+
+/// Sent when a player tries to use an ability which can't be used, for UI feedback
+#[derive(Event, Debug)]
+#[allow(unused)]
+pub struct AbilityFailed {
+    pub source: Entity,
+    pub ability: Entity,
+    pub reason: CannotUseAbility,
+    /// False for failures predicted by a client, the server confirms them with an `AbilityFailedMessage`
+    pub authoritative: bool,
+}
+
+/// Server to client copy of `AbilityFailed`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AbilityFailedMessage {
+    pub source: Entity,
+    pub ability: Entity,
+    pub reason: CannotUseAbility,
+}
+
+impl MapEntities for AbilityFailedMessage {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        self.source = entity_mapper.map_entity(self.source);
+        self.ability = entity_mapper.map_entity(self.ability);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivationKind {
+    /// A `Charge` ability starts charging, costs are checked but only paid on release
+    StartCharge,
+    Trigger,
+    /// A channelled or toggled ability stops, this can't fail
+    End,
+}
+
+#[derive(Debug, Clone)]
+pub struct Activation {
+    pub source: Entity,
+    pub ability: Entity,
+    pub kind: ActivationKind,
+    /// Whether the caster is predicted, in which case failures aren't authoritative
+    pub predicted: bool,
+    pub failed: Option<CannotUseAbility>,
+}
+
+/// Activations requested this tick, filled from inputs and drained once resolved
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct PendingActivations(Vec<Activation>);
+
+fn collect_activations<A: Actionlike, Filter: QueryFilter>(
+    mut pending: ResMut<PendingActivations>,
+    caster_query: Query<(Entity, &ActionState<A>, &AbilityMap<A>, Has<Predicted>), Filter>,
+    ability_query: Query<(&ActivationMode, &AbilityActive, &AbilityCharge), Filter>,
+) {
+    for (source, actions, map, predicted) in caster_query.iter() {
+        for (action, &ability) in map.iter() {
+            let Ok((mode, active, charge)) = ability_query.get(ability) else {
+                continue;
+            };
+
+            let pressed = actions.just_pressed(action);
+            let released = actions.just_released(action);
+
+            let kind = match mode {
+                ActivationMode::Instant if pressed => ActivationKind::Trigger,
+                ActivationMode::Charge if pressed => ActivationKind::StartCharge,
+                ActivationMode::Charge if released && active.0 => ActivationKind::Trigger,
+                ActivationMode::Channel if pressed => ActivationKind::Trigger,
+                ActivationMode::Channel if active.0 && (released || charge.is_full()) => ActivationKind::End,
+                ActivationMode::Toggle if pressed && active.0 => ActivationKind::End,
+                ActivationMode::Toggle if pressed => ActivationKind::Trigger,
+                _ => continue,
+            };
+
+            pending.push(Activation {
+                source,
+                ability,
+                kind,
+                predicted,
+                failed: None,
+            });
+        }
+    }
+}
+
+/// Marks activations as failed when the caster's `P` pool is locked or can't cover the ability's `AbilityCost<P>`
+pub fn check_pool_costs<P: Pool + Component>(
+    mut pending: ResMut<PendingActivations>,
+    pool_query: Query<&P>,
+    cost_query: Query<&AbilityCost<P>>,
+) {
+    for activation in pending.iter_mut() {
+        if activation.kind == ActivationKind::End || activation.failed.is_some() {
+            continue;
+        }
+
+        let pool = pool_query.get(activation.source).ok();
+        let available = match (pool, cost_query.get(activation.ability).ok()) {
+            (Some(pool), Some(cost)) => pool.locked().and_then(|_| pool.available(cost.0)),
+            (Some(pool), None) => pool.locked(),
+            (None, Some(_)) => Err(CannotUseAbility::ResourceMissing),
+            (None, None) => Ok(()),
+        };
+        activation.failed = available.err();
+    }
+}
+
+/// Takes the `AbilityCost<P>` of every triggered ability from the caster's `P` pool
+pub fn pay_pool_costs<P: Pool + Component>(
+    mut triggers: EventReader<TriggerAbility>,
+    mut pool_query: Query<&mut P>,
+    cost_query: Query<&AbilityCost<P>>,
+) {
+    for trigger in triggers.read() {
+        let Ok(cost) = cost_query.get(trigger.ability) else {
+            continue;
+        };
+        let Ok(mut pool) = pool_query.get_mut(trigger.source) else {
+            continue;
+        };
+
+        if let Err(e) = pool.expend(cost.0) {
+            warn!("Triggered ability {:?} could not pay its cost: {e:?}", trigger.ability);
+        }
+    }
+}
+
+fn resolve_activations(
+    mut pending: ResMut<PendingActivations>,
+    mut ability_query: Query<(AbilityState, &ActivationMode, &mut AbilityActive, &mut AbilityCharge)>,
+    mut triggers: EventWriter<TriggerAbility>,
+    mut ends: EventWriter<EndAbility>,
+    mut failures: EventWriter<AbilityFailed>,
+    rollback: Option<Res<Rollback>>,
+) {
+    // failures were already reported when the tick was first simulated
+    let resimulating = rollback.is_some_and(|rollback| rollback.is_rollback());
+
+    for activation in pending.drain(..) {
+        let Ok((mut ability, mode, mut active, mut charge)) = ability_query.get_mut(activation.ability) else {
+            continue;
+        };
+
+        let result = match activation.kind {
+            ActivationKind::End => {
+                active.0 = false;
+                ends.send(EndAbility {
+                    source: activation.source,
+                    ability: activation.ability,
+                });
+                Ok(())
+            },
+            ActivationKind::StartCharge => {
+                activation.failed.map_or_else(|| ability.ready(), Err).map(|_| {
+                    charge.start();
+                    active.0 = true;
+                })
+            },
+            ActivationKind::Trigger => {
+                // a released charge is over whether or not it succeeds
+                if *mode == ActivationMode::Charge {
+                    active.0 = false;
+                }
+
+                activation.failed.map_or_else(|| ability.trigger(), Err).map(|_| {
+                    if *mode == ActivationMode::Channel {
+                        charge.start();
+                    }
+                    if matches!(mode, ActivationMode::Channel | ActivationMode::Toggle) {
+                        active.0 = true;
+                    }
+
+                    triggers.send(TriggerAbility {
+                        source: activation.source,
+                        ability: activation.ability,
+                    });
+                })
+            },
+        };
+
+        if let Err(reason) = result {
+            if !(activation.predicted && resimulating) {
+                failures.send(AbilityFailed {
+                    source: activation.source,
+                    ability: activation.ability,
+                    reason,
+                    authoritative: !activation.predicted,
+                });
+            }
+        }
+    }
+}
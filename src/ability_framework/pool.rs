@@ -1,11 +1,56 @@
-use core::ops::{Add, AddAssign, Sub, SubAssign};
-use std::time::Duration;
+use core::ops::{Add, AddAssign, Mul, Sub, SubAssign};
+use std::{marker::PhantomData, time::Duration};
 
-use bevy::{prelude::{Component, Query, Res, With}, reflect::Reflect, time::Time};
-use lightyear::prelude::client::Predicted;
-use serde::{Deserialize, Serialize};
+use bevy::{prelude::*, reflect::{GetTypeRegistration, Typed}};
+use lightyear::prelude::{client::ComponentSyncMode, AppComponentExt, ChannelDirection};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use super::CannotUseAbility;
+use super::{pipeline::{check_pool_costs, pay_pool_costs, AbilityPipelineStep, PendingActivations}, status_effect::{apply_pool_status_effects, StatusAffectedPool, StatusEffectSet}, CannotUseAbility, Simulated};
+
+/// Registers a pool type: replication, reflection, regeneration, status effects and ability costs.
+/// Must be added on both the server and the client, in the same order, after the protocol.
+pub struct PoolPlugin<P: Pool> {
+    _marker: PhantomData<P>,
+}
+
+impl<P: Pool> Default for PoolPlugin<P> {
+    fn default() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<P> Plugin for PoolPlugin<P>
+where
+    P: StatusAffectedPool + Serialize + DeserializeOwned + PartialEq + Clone + GetTypeRegistration + Typed,
+    AbilityCost<P>: Component + Serialize + DeserializeOwned + PartialEq + Clone + GetTypeRegistration,
+{
+    fn build(&self, app: &mut App) {
+        // cost checks can run before any ability pipeline has been added
+        app.init_resource::<PendingActivations>();
+
+        app.register_type::<P>();
+        app.register_type::<AbilityCost<P>>();
+
+        // interpolated so remote players' pools can be displayed
+        app.register_component::<P>(ChannelDirection::ServerToClient)
+            .add_prediction(ComponentSyncMode::Full)
+            .add_interpolation(ComponentSyncMode::Simple);
+        app.register_component::<AbilityCost<P>>(ChannelDirection::ServerToClient)
+            .add_prediction(ComponentSyncMode::Simple);
+
+        app.add_systems(FixedUpdate, (
+            tick_pools_regen::<P>,
+            apply_pool_status_effects::<P>
+                .before(StatusEffectSet),
+            check_pool_costs::<P>
+                .in_set(AbilityPipelineStep::CheckCosts),
+            pay_pool_costs::<P>
+                .in_set(AbilityPipelineStep::PayCosts),
+        ));
+    }
+}
 
 pub struct MaxPoolLessThanMin;
 
@@ -18,6 +63,7 @@ pub trait Pool: Sized {
         + Sub<Output = Self::Quantity>
         + AddAssign
         + SubAssign
+        + Mul<f32, Output = Self::Quantity>
         + PartialEq
         + PartialOrd
         + Clone
@@ -30,6 +76,11 @@ pub trait Pool: Sized {
 
     fn current(&self) -> Self::Quantity;
 
+    /// Blocks every ability of the pool's owner, whether or not they have a cost in this pool
+    fn locked(&self) -> Result<(), CannotUseAbility> {
+        Ok(())
+    }
+
     fn available(&self, amount: Self::Quantity) -> Result<(), CannotUseAbility> {
         if self.current() >= amount {
             Ok(())
@@ -56,6 +107,16 @@ pub trait Pool: Sized {
         let new = self.current() + amount;
         self.set_current(new);
     }
+
+    /// Removes up to `amount` from the pool, unlike `expend` this can't fail and stops at `MIN`.
+    fn deplete(&mut self, amount: Self::Quantity) {
+        let new = self.current() - amount;
+        self.set_current(new);
+    }
+
+    fn is_depleted(&self) -> bool {
+        self.current() <= Self::MIN
+    }
 }
 
 #[allow(unused)]
@@ -93,7 +154,7 @@ impl Pool for NullPool {
 }
 
 pub fn tick_pools_regen<P: RegeneratingPool + Component>(
-    mut query: Query<&mut P>,
+    mut query: Query<&mut P, Simulated>,
     time: Res<Time>
 ) {
     let delta_time = time.delta();
@@ -102,14 +163,3 @@ pub fn tick_pools_regen<P: RegeneratingPool + Component>(
         pool.regenerate(delta_time);
     }
 }
-
-pub fn predict_tick_pools_regen<P: RegeneratingPool + Component>(
-    mut query: Query<&mut P, With<Predicted>>,
-    time: Res<Time>
-) {
-    let delta_time = time.delta();
-
-    for mut pool in query.iter_mut() {
-        pool.regenerate(delta_time);
-    }
-}
\ No newline at end of file
@@ -1,179 +1,244 @@
-pub mod life {
-    use core::ops::{Div, Mul};
-    use std::{fmt::Display, time::Duration};
+/// Declares a `$quantity` newtype over `f32` and a `$pool` component storing it, with the `Pool` and
+/// `RegeneratingPool` implementations shared by every pool that drains when paying costs.
+/// Register the pool with `PoolPlugin::<$pool>` and implement `StatusAffectedPool` for it.
+///
+/// `define_pool!(quantity $quantity)` only declares the quantity, for pools with their own rules.
+macro_rules! define_pool {
+    (quantity $quantity:ident) => {
+        #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default, ::derive_more::derive::Add, ::derive_more::derive::Sub, ::derive_more::derive::AddAssign, ::derive_more::derive::SubAssign, ::serde::Serialize, ::serde::Deserialize, ::bevy::reflect::Reflect)]
+        pub struct $quantity(pub f32);
+
+        impl ::core::ops::Mul<f32> for $quantity {
+            type Output = $quantity;
+
+            fn mul(self, rhs: f32) -> $quantity {
+                $quantity(self.0 * rhs)
+            }
+        }
 
-    use bevy::{prelude::Component, reflect::Reflect};
-    use derive_more::derive::{Add, AddAssign, Sub, SubAssign};
-    use serde::{Deserialize, Serialize};
+        impl ::core::ops::Mul<$quantity> for f32 {
+            type Output = $quantity;
 
-    use crate::ability_framework::pool::{MaxPoolLessThanMin, Pool, RegeneratingPool};
+            fn mul(self, rhs: $quantity) -> $quantity {
+                $quantity(self * rhs.0)
+            }
+        }
 
-    #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default, Add, Sub, AddAssign, SubAssign, Serialize, Deserialize, Reflect)]
-    pub struct Life(pub f32);
+        impl ::core::ops::Div<f32> for $quantity {
+            type Output = $quantity;
 
-    #[derive(Component, Serialize, Deserialize, PartialEq, Clone, Reflect)]
-    pub struct LifePool {
-        current: Life,
-        max: Life,
-        pub regen_per_second: Life,
-    }
+            fn div(self, rhs: f32) -> $quantity {
+                $quantity(self.0 / rhs)
+            }
+        }
 
-    impl LifePool {
-        pub fn new(current: Life, max: Life, regen_per_second: Life) -> Self {
-            assert!(current <= max);
-            assert!(current >= LifePool::MIN);
-            assert!(max >= LifePool::MIN);
+        impl ::core::ops::Div<$quantity> for $quantity {
+            type Output = f32;
 
-            Self {
-                current,
-                max,
-                regen_per_second,
+            fn div(self, rhs: $quantity) -> f32 {
+                self.0 / rhs.0
             }
         }
-    }
 
-    impl Pool for LifePool {
-        type Quantity = Life;
-    
-        const MIN: Life = Life(0.);
-    
-        fn current(&self) -> Self::Quantity {
-            self.current
-        }
-    
-        fn set_current(&mut self, new_quantity: Self::Quantity) -> Self::Quantity {
-            let actual = Life(new_quantity.0.clamp(0., self.max.0));
-            self.current = actual;
-            self.current
+        impl ::std::fmt::Display for $quantity {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                write!(f, "{}", self.0)
+            }
         }
-    
-        fn max(&self) -> Self::Quantity {
-            self.max
+    };
+    ($quantity:ident, $pool:ident) => {
+        define_pool!(quantity $quantity);
+
+        #[derive(::bevy::prelude::Component, ::serde::Serialize, ::serde::Deserialize, PartialEq, Clone, ::bevy::reflect::Reflect)]
+        pub struct $pool {
+            current: $quantity,
+            max: $quantity,
+            pub regen_per_second: $quantity,
         }
 
-        fn set_max(&mut self, new_max: Self::Quantity) -> Result<(), MaxPoolLessThanMin> {
-            if new_max < Self::MIN {
-                Err(MaxPoolLessThanMin)
-            } else {
-                self.max = new_max;
-                self.set_current(self.current);
-                Ok(())
+        impl $pool {
+            pub fn new(current: $quantity, max: $quantity, regen_per_second: $quantity) -> Self {
+                assert!(current <= max);
+                assert!(current >= <$pool as $crate::ability_framework::pool::Pool>::MIN);
+                assert!(max >= <$pool as $crate::ability_framework::pool::Pool>::MIN);
+
+                Self {
+                    current,
+                    max,
+                    regen_per_second,
+                }
             }
         }
-    }
-        
 
-    impl RegeneratingPool for LifePool {
-        fn regen_per_second(&self) -> Self::Quantity {
-            self.regen_per_second
-        }
-    
-        fn set_regen_per_second(&mut self, new_regen_per_second: Self::Quantity) {
-            self.regen_per_second = new_regen_per_second;
-        }
-    
-        fn regenerate(&mut self, delta_time: Duration) {
-            self.set_current(self.current + self.regen_per_second * delta_time.as_secs_f32());
-        }
-    }
+        impl $crate::ability_framework::pool::Pool for $pool {
+            type Quantity = $quantity;
 
+            const MIN: $quantity = $quantity(0.);
 
+            fn current(&self) -> Self::Quantity {
+                self.current
+            }
 
+            fn set_current(&mut self, new_quantity: Self::Quantity) -> Self::Quantity {
+                let actual = $quantity(new_quantity.0.clamp(0., self.max.0));
+                self.current = actual;
+                self.current
+            }
 
-    impl Mul<f32> for Life {
-        type Output = Life;
+            fn max(&self) -> Self::Quantity {
+                self.max
+            }
 
-        fn mul(self, rhs: f32) -> Life {
-            Life(self.0 * rhs)
+            fn set_max(&mut self, new_max: Self::Quantity) -> Result<(), $crate::ability_framework::pool::MaxPoolLessThanMin> {
+                if new_max < Self::MIN {
+                    Err($crate::ability_framework::pool::MaxPoolLessThanMin)
+                } else {
+                    self.max = new_max;
+                    self.set_current(self.current);
+                    Ok(())
+                }
+            }
         }
-    }
 
-    impl Mul<Life> for f32 {
-        type Output = Life;
+        impl $crate::ability_framework::pool::RegeneratingPool for $pool {
+            fn regen_per_second(&self) -> Self::Quantity {
+                self.regen_per_second
+            }
 
-        fn mul(self, rhs: Life) -> Life {
-            Life(self * rhs.0)
-        }
-    }
+            fn set_regen_per_second(&mut self, new_regen_per_second: Self::Quantity) {
+                self.regen_per_second = new_regen_per_second;
+            }
 
-    impl Div<f32> for Life {
-        type Output = Life;
+            fn regenerate(&mut self, delta_time: ::std::time::Duration) {
+                use $crate::ability_framework::pool::Pool;
 
-        fn div(self, rhs: f32) -> Life {
-            Life(self.0 / rhs)
+                self.set_current(self.current + self.regen_per_second * delta_time.as_secs_f32());
+            }
         }
-    }
-
-    impl Div<Life> for Life {
-        type Output = f32;
 
-        fn div(self, rhs: Life) -> f32 {
-            self.0 / rhs.0
+        impl ::std::fmt::Display for $pool {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                write!(f, "{} / {}", self.current, self.max)
+            }
         }
-    }
+    };
+}
 
-    impl Display for Life {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "{}", self.0)
+pub mod life {
+    use crate::ability_framework::status_effect::{StatusAffectedPool, StatusModifier};
+
+    define_pool!(Life, LifePool);
+
+    impl StatusAffectedPool for LifePool {
+        fn rate_of_change(modifier: &StatusModifier) -> Option<Life> {
+            match modifier {
+                // predicted like healing, the server also credits the damage through `combat::damage_over_time`
+                StatusModifier::LifePerSecond(rate) => Some(Life(*rate)),
+                _ => None,
+            }
         }
     }
+}
 
-    impl Display for LifePool {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "{} / {}", self.current, self.max)
+pub mod mana {
+    use crate::ability_framework::status_effect::{StatusAffectedPool, StatusModifier};
+
+    define_pool!(Mana, ManaPool);
+
+    impl StatusAffectedPool for ManaPool {
+        fn rate_of_change(modifier: &StatusModifier) -> Option<Mana> {
+            match modifier {
+                StatusModifier::ManaPerSecond(rate) => Some(Mana(*rate)),
+                _ => None,
+            }
         }
     }
 }
 
-pub mod mana {
-    use core::ops::{Div, Mul};
+pub mod heat {
     use std::{fmt::Display, time::Duration};
 
     use bevy::{prelude::Component, reflect::Reflect};
-    use derive_more::derive::{Add, AddAssign, Sub, SubAssign};
     use serde::{Deserialize, Serialize};
 
-    use crate::ability_framework::pool::{MaxPoolLessThanMin, Pool, RegeneratingPool};
+    use crate::ability_framework::{pool::{MaxPoolLessThanMin, Pool, RegeneratingPool}, status_effect::{StatusAffectedPool, StatusModifier}, CannotUseAbility};
 
-    #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default, Add, Sub, AddAssign, SubAssign, Serialize, Deserialize, Reflect)]
-    pub struct Mana(pub f32);
+    define_pool!(quantity Heat);
 
+    /// Heat is gained by using abilities and vents over time. Reaching `max` overheats the player,
+    /// locking every ability until `lockout` has elapsed.
+    /// The lockout is part of the pool so that it is predicted and rolled back along with the heat.
     #[derive(Component, Serialize, Deserialize, PartialEq, Clone, Reflect)]
-    pub struct ManaPool {
-        current: Mana,
-        max: Mana,
-        pub regen_per_second: Mana,
+    pub struct HeatPool {
+        current: Heat,
+        max: Heat,
+        pub vent_per_second: Heat,
+        lockout: Duration,
+        lockout_remaining: Duration,
     }
 
-    impl ManaPool {
-        pub fn new(current: Mana, max: Mana, regen_per_second: Mana) -> Self {
+    impl HeatPool {
+        pub fn new(current: Heat, max: Heat, vent_per_second: Heat, lockout: Duration) -> Self {
             assert!(current <= max);
-            assert!(current >= ManaPool::MIN);
-            assert!(max >= ManaPool::MIN);
+            assert!(current >= HeatPool::MIN);
+            assert!(max >= HeatPool::MIN);
 
             Self {
                 current,
                 max,
-                regen_per_second,
+                vent_per_second,
+                lockout,
+                lockout_remaining: Duration::ZERO,
             }
         }
+
+        pub fn is_overheated(&self) -> bool {
+            !self.lockout_remaining.is_zero()
+        }
+
+        #[allow(unused)]
+        pub fn lockout_remaining(&self) -> Duration {
+            self.lockout_remaining
+        }
+
+        /// Clears all heat and any ongoing lockout
+        pub fn reset(&mut self) {
+            self.current = Self::MIN;
+            self.lockout_remaining = Duration::ZERO;
+        }
     }
 
-    impl Pool for ManaPool {
-        type Quantity = Mana;
-    
-        const MIN: Mana = Mana(0.);
-    
+    impl Pool for HeatPool {
+        type Quantity = Heat;
+
+        const MIN: Heat = Heat(0.);
+
         fn current(&self) -> Self::Quantity {
             self.current
         }
-    
+
+        fn locked(&self) -> Result<(), CannotUseAbility> {
+            if self.is_overheated() {
+                Err(CannotUseAbility::Overheated)
+            } else {
+                Ok(())
+            }
+        }
+
+        /// Any amount of heat can be added while the pool isn't overheated, going over `max` triggers the lockout
+        fn available(&self, _amount: Self::Quantity) -> Result<(), CannotUseAbility> {
+            self.locked()
+        }
+
         fn set_current(&mut self, new_quantity: Self::Quantity) -> Self::Quantity {
-            let actual = Mana(new_quantity.0.clamp(0., self.max.0));
-            self.current = actual;
+            self.current = Heat(new_quantity.0.clamp(0., self.max.0));
+
+            if self.current >= self.max && !self.is_overheated() {
+                self.lockout_remaining = self.lockout;
+            }
             self.current
         }
-    
+
         fn max(&self) -> Self::Quantity {
             self.max
         }
@@ -187,67 +252,43 @@ pub mod mana {
                 Ok(())
             }
         }
-    }
-        
-
-    impl RegeneratingPool for ManaPool {
-        fn regen_per_second(&self) -> Self::Quantity {
-            self.regen_per_second
-        }
-    
-        fn set_regen_per_second(&mut self, new_regen_per_second: Self::Quantity) {
-            self.regen_per_second = new_regen_per_second;
-        }
-    
-        fn regenerate(&mut self, delta_time: Duration) {
-            self.set_current(self.current + self.regen_per_second * delta_time.as_secs_f32());
-        }
-    }
-
 
+        /// Paying a heat cost adds heat instead of removing it
+        fn expend(&mut self, amount: Self::Quantity) -> Result<(), CannotUseAbility> {
+            self.available(amount)?;
 
-
-    impl Mul<f32> for Mana {
-        type Output = Mana;
-
-        fn mul(self, rhs: f32) -> Mana {
-            Mana(self.0 * rhs)
+            self.set_current(self.current + amount);
+            Ok(())
         }
     }
 
-    impl Mul<Mana> for f32 {
-        type Output = Mana;
-
-        fn mul(self, rhs: Mana) -> Mana {
-            Mana(self * rhs.0)
+    impl RegeneratingPool for HeatPool {
+        fn regen_per_second(&self) -> Self::Quantity {
+            Heat(-self.vent_per_second.0)
         }
-    }
-
-    impl Div<f32> for Mana {
-        type Output = Mana;
 
-        fn div(self, rhs: f32) -> Mana {
-            Mana(self.0 / rhs)
+        fn set_regen_per_second(&mut self, new_regen_per_second: Self::Quantity) {
+            self.vent_per_second = Heat(-new_regen_per_second.0);
         }
-    }
-
-    impl Div<Mana> for Mana {
-        type Output = f32;
 
-        fn div(self, rhs: Mana) -> f32 {
-            self.0 / rhs.0
+        fn regenerate(&mut self, delta_time: Duration) {
+            self.lockout_remaining = self.lockout_remaining.saturating_sub(delta_time);
+            self.set_current(self.current - self.vent_per_second * delta_time.as_secs_f32());
         }
     }
 
-    impl Display for Mana {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "{}", self.0)
+    impl StatusAffectedPool for HeatPool {
+        fn rate_of_change(modifier: &StatusModifier) -> Option<Heat> {
+            match modifier {
+                StatusModifier::HeatPerSecond(rate) => Some(Heat(*rate)),
+                _ => None,
+            }
         }
     }
 
-    impl Display for ManaPool {
+    impl Display for HeatPool {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             write!(f, "{} / {}", self.current, self.max)
         }
     }
-}
\ No newline at end of file
+}
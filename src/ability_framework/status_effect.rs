@@ -0,0 +1,150 @@
+use bevy::prelude::*;
+use lightyear::prelude::{client::Predicted, ClientId};
+use serde::{Deserialize, Serialize};
+
+use super::{pool::RegeneratingPool, Simulated};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Reflect)]
+pub enum StatusModifier {
+    /// Multiplies the movement speed, values below 1 slow the target down
+    MoveSpeedMultiplier(f32),
+    /// Life gained per second, negative values deal damage over time
+    LifePerSecond(f32),
+    /// Mana gained per second on top of the pool's regular regeneration
+    ManaPerSecond(f32),
+    /// Heat gained per second, negative values help venting
+    HeatPerSecond(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Reflect)]
+pub enum StackingRule {
+    /// Re-applying the effect only resets its duration
+    Refresh,
+    /// Re-applying the effect adds a stack, up to `max`, and resets its duration
+    Stack { max: u8 },
+    /// Every application is tracked separately with its own duration
+    Independent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Reflect)]
+pub struct StatusEffect {
+    /// Effects with the same name are combined according to the stacking rule
+    pub name: String,
+    pub modifier: StatusModifier,
+    /// Duration in fixed update ticks
+    pub duration_ticks: u16,
+    pub stacking: StackingRule,
+    /// Runtime state, left out of definition files
+    #[serde(default = "StatusEffect::initial_stacks")]
+    pub stacks: u8,
+    #[serde(default)]
+    pub remaining_ticks: u16,
+    /// Client who applied the effect, credited with the damage it deals
+    #[serde(default)]
+    pub source: Option<ClientId>,
+}
+
+impl StatusEffect {
+    fn initial_stacks() -> u8 {
+        1
+    }
+
+    pub fn strength(&self) -> f32 {
+        self.stacks as f32
+    }
+}
+
+/// All status effects currently applied to an entity.
+/// Replicated with `ComponentSyncMode::Full` so that prediction history can roll them back.
+#[derive(Component, Serialize, Deserialize, PartialEq, Clone, Default, Reflect, Deref)]
+pub struct StatusEffects(Vec<StatusEffect>);
+
+impl StatusEffects {
+    pub fn apply(&mut self, effect: &StatusEffect, source: Option<ClientId>) {
+        let existing = self.0.iter_mut().find(|e| e.name == effect.name);
+
+        match (effect.stacking, existing) {
+            (StackingRule::Refresh, Some(existing)) => {
+                existing.remaining_ticks = effect.duration_ticks;
+                existing.source = source;
+            },
+            (StackingRule::Stack { max }, Some(existing)) => {
+                existing.stacks = existing.stacks.saturating_add(1).min(max);
+                existing.remaining_ticks = effect.duration_ticks;
+                existing.source = source;
+            },
+            _ => {
+                self.0.push(StatusEffect {
+                    stacks: 1,
+                    remaining_ticks: effect.duration_ticks,
+                    source,
+                    ..effect.clone()
+                });
+            },
+        }
+    }
+
+    /// Advance all effects by a single tick, removing the ones which expired
+    pub fn tick(&mut self) {
+        for effect in self.0.iter_mut() {
+            effect.remaining_ticks = effect.remaining_ticks.saturating_sub(1);
+        }
+        self.0.retain(|e| e.remaining_ticks > 0);
+    }
+
+    pub fn move_speed_multiplier(&self) -> f32 {
+        self.0.iter().fold(1., |multiplier, effect| match effect.modifier {
+            StatusModifier::MoveSpeedMultiplier(m) => multiplier * m.powf(effect.strength()),
+            _ => multiplier,
+        })
+    }
+}
+
+/// Pools which can be changed over time by status effects
+pub trait StatusAffectedPool: RegeneratingPool + Component {
+    /// Change per second caused by a single stack of `modifier`, if it affects this pool
+    fn rate_of_change(_modifier: &StatusModifier) -> Option<Self::Quantity> {
+        None
+    }
+}
+
+/// Status effects are ticked in this set, pools apply their modifiers before it
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StatusEffectSet;
+
+pub fn tick_status_effects(
+    mut query: Query<&mut StatusEffects>,
+) {
+    for mut effects in query.iter_mut() {
+        if !effects.is_empty() {
+            effects.tick();
+        }
+    }
+}
+
+pub fn predict_tick_status_effects(
+    mut query: Query<&mut StatusEffects, With<Predicted>>,
+) {
+    for mut effects in query.iter_mut() {
+        if !effects.is_empty() {
+            effects.tick();
+        }
+    }
+}
+
+pub fn apply_pool_status_effects<P: StatusAffectedPool>(
+    mut query: Query<(&mut P, &StatusEffects), Simulated>,
+    time: Res<Time>,
+) {
+    let delta_seconds = time.delta_seconds();
+
+    for (mut pool, effects) in query.iter_mut() {
+        for effect in effects.iter() {
+            if let Some(rate) = P::rate_of_change(&effect.modifier) {
+                // set_current clamps to the pool bounds so this handles both gains and losses
+                let new = pool.current() + rate * (effect.strength() * delta_seconds);
+                pool.set_current(new);
+            }
+        }
+    }
+}
@@ -1,44 +1,300 @@
-use bevy::prelude::*;
+use avian3d::prelude::{LinearVelocity, Position};
+use bevy::{asset::{io::Reader, ron, AssetLoader, AsyncReadExt, LoadContext}, prelude::*};
+use derive_more::derive::{Display, Error, From};
+use lightyear::prelude::client::{Confirmed, Predicted};
+use serde::Deserialize;
 
-#[derive(Component)]
-pub struct FaceCamera;
+use crate::{ability_framework::{pool::Pool, pools::life::{Life, LifePool}}, assets::PlayerAssets, combat::Dead, player::PlayerId, projectile::Projectile};
 
 #[derive(Component)]
-pub struct Animation {
-    pub frames: Vec<usize>,
-    pub current: usize,
-    pub timer: Timer,
-}
+pub struct FaceCamera;
 
 pub struct OverheatAnimationPlugin;
 
 impl Plugin for OverheatAnimationPlugin {
     fn build(&self, app: &mut App) {
+        app.init_asset::<PlayerAnimations>()
+            .init_asset_loader::<PlayerAnimationsLoader>();
+
         app.add_systems(
             Update, (
                 face_camera,
-                animate_sprites,
+                (
+                    trigger_cast_animations,
+                    animate_players,
+                ).chain(),
             )
         );
     }
 }
 
-fn animate_sprites(
+/// Frames of every animation state of the players, loaded from `assets/players/*.anim.ron`.
+/// Frames are columns of the atlas, the row is picked from the direction the player faces.
+#[derive(Asset, TypePath, Debug, Deserialize)]
+pub struct PlayerAnimations {
+    /// Columns of the atlas, the index of a frame is `row * columns + column`
+    pub columns: usize,
+    pub rows: FacingRows,
+    /// Horizontal speed above which players run
+    pub run_speed: f32,
+    /// Horizontal speed above which players are dodging rather than running
+    pub dodge_speed: f32,
+    pub idle: SpriteClip,
+    pub run: SpriteClip,
+    pub dodge: SpriteClip,
+    pub cast: SpriteClip,
+    pub hit: SpriteClip,
+    /// Dead players are hidden once it is over
+    pub death: SpriteClip,
+}
+
+/// Atlas row of each direction, as seen from the camera
+#[derive(Debug, Deserialize)]
+pub struct FacingRows {
+    pub down: usize,
+    pub up: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpriteClip {
+    pub frames: Vec<usize>,
+    pub frame_seconds: f32,
+    /// Clips that don't loop hold their last frame
+    #[serde(default)]
+    pub looping: bool,
+}
+
+impl PlayerAnimations {
+    fn clip(&self, state: AnimationState) -> &SpriteClip {
+        match state {
+            AnimationState::Idle => &self.idle,
+            AnimationState::Run => &self.run,
+            AnimationState::Dodge => &self.dodge,
+            AnimationState::Cast => &self.cast,
+            AnimationState::Hit => &self.hit,
+            AnimationState::Death => &self.death,
+        }
+    }
+
+    fn row(&self, facing: Facing) -> usize {
+        match facing {
+            Facing::Down => self.rows.down,
+            Facing::Up => self.rows.up,
+            Facing::Left => self.rows.left,
+            Facing::Right => self.rows.right,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AnimationState {
+    #[default]
+    Idle,
+    Run,
+    Dodge,
+    Cast,
+    Hit,
+    Death,
+}
+
+impl AnimationState {
+    /// Played once to the end, unless interrupted by a state of the same or higher priority
+    fn is_one_shot(&self) -> bool {
+        matches!(self, Self::Dodge | Self::Cast | Self::Hit)
+    }
+
+    fn priority(&self) -> u8 {
+        match self {
+            Self::Idle | Self::Run => 0,
+            Self::Cast => 1,
+            Self::Dodge => 2,
+            Self::Hit => 3,
+            Self::Death => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Facing {
+    #[default]
+    Down,
+    Up,
+    Left,
+    Right,
+}
+
+/// Animation state machine of a predicted or interpolated player, drawn on its `sprite` child
+#[derive(Component)]
+pub struct PlayerAnimator {
+    sprite: Entity,
+    state: AnimationState,
+    facing: Facing,
+    /// Index in the frames of the current clip
+    frame: usize,
+    elapsed: f32,
+    finished: bool,
+    /// Set when the player fired, consumed on the next update
+    cast: bool,
+    last_position: Option<Vec3>,
+    last_life: Option<Life>,
+}
+
+impl PlayerAnimator {
+    pub fn new(sprite: Entity) -> Self {
+        Self {
+            sprite,
+            state: AnimationState::Idle,
+            facing: Facing::Down,
+            frame: 0,
+            elapsed: 0.,
+            finished: false,
+            cast: false,
+            last_position: None,
+            last_life: None,
+        }
+    }
+
+    pub fn death_finished(&self) -> bool {
+        self.state == AnimationState::Death && self.finished
+    }
+
+    fn restart(&mut self, state: AnimationState) {
+        self.state = state;
+        self.frame = 0;
+        self.elapsed = 0.;
+        self.finished = false;
+    }
+
+    fn set_state(&mut self, state: AnimationState) {
+        if self.state != state {
+            self.restart(state);
+        }
+    }
+
+    fn can_play(&self, state: AnimationState) -> bool {
+        self.finished || !self.state.is_one_shot() || state.priority() >= self.state.priority()
+    }
+
+    fn advance(&mut self, clip: &SpriteClip, delta: f32) {
+        if clip.frame_seconds <= 0. {
+            return;
+        }
+
+        self.elapsed += delta;
+        while self.elapsed >= clip.frame_seconds && !self.finished {
+            self.elapsed -= clip.frame_seconds;
+            if self.frame + 1 < clip.frames.len() {
+                self.frame += 1;
+            } else if clip.looping {
+                self.frame = 0;
+            } else {
+                self.finished = true;
+            }
+        }
+    }
+}
+
+/// Direction in which `velocity` moves on screen
+fn facing(velocity: Vec3, camera: &GlobalTransform) -> Facing {
+    let right = camera.right().with_y(0.).normalize_or_zero();
+    let forward = camera.forward().with_y(0.).normalize_or_zero();
+    let x = velocity.dot(right);
+    let y = velocity.dot(forward);
+
+    if x.abs() > y.abs() {
+        if x > 0. { Facing::Right } else { Facing::Left }
+    } else if y > 0. {
+        Facing::Up
+    } else {
+        Facing::Down
+    }
+}
+
+/// Pre-spawned and interpolated projectiles appear once, the confirmed copies are ignored
+fn trigger_cast_animations(
+    projectile_query: Query<&Projectile, (Added<Projectile>, Without<Confirmed>)>,
+    mut player_query: Query<(&PlayerId, &mut PlayerAnimator)>,
+) {
+    for projectile in projectile_query.iter() {
+        for (player_id, mut animator) in player_query.iter_mut() {
+            if player_id.0 == projectile.owner {
+                animator.cast = true;
+            }
+        }
+    }
+}
+
+fn animate_players(
     time: Res<Time>,
-    mut query: Query<(&mut Animation, &mut TextureAtlas)>
+    animations: Res<Assets<PlayerAnimations>>,
+    player_assets: Option<Res<PlayerAssets>>,
+    camera_query: Query<&GlobalTransform, With<Camera3d>>,
+    mut player_query: Query<(&mut PlayerAnimator, &Position, Option<&LinearVelocity>, Option<&LifePool>, Has<Predicted>, Has<Dead>)>,
+    mut atlas_query: Query<&mut TextureAtlas>,
 ) {
-    for (mut animation, mut atlas) in query.iter_mut() {
-        animation.timer.tick(time.delta());
-        if animation.timer.just_finished() {
-            atlas.index = animation.frames[animation.current];
-            animation.current += 1;
-            animation.current %= animation.frames.len();
+    let Some(animations) = player_assets.and_then(|assets| animations.get(&assets.player_animations)) else {
+        return;
+    };
+    let Ok(camera) = camera_query.get_single() else {
+        return;
+    };
+    let delta = time.delta_seconds();
+
+    for (mut animator, position, linear_velocity, life, predicted, dead) in player_query.iter_mut() {
+        // only predicted players have their velocity, interpolated ones are estimated from their movement
+        let velocity = match (predicted, linear_velocity, animator.last_position) {
+            (true, Some(linear_velocity), _) => linear_velocity.0,
+            (_, _, Some(last)) if delta > 0. => (position.0 - last) / delta,
+            _ => Vec3::ZERO,
+        };
+        let velocity = velocity.with_y(0.);
+        animator.last_position = Some(position.0);
+
+        let hit = life.is_some_and(|life| animator.last_life.is_some_and(|last| life.current() < last));
+        animator.last_life = life.map(|life| life.current());
+        let cast = std::mem::take(&mut animator.cast);
+
+        let speed = velocity.length();
+        if speed > animations.run_speed {
+            animator.facing = facing(velocity, camera);
+        }
+
+        if dead {
+            animator.set_state(AnimationState::Death);
+        } else {
+            if animator.state == AnimationState::Death {
+                animator.restart(AnimationState::Idle);
+            }
+
+            if hit && animator.can_play(AnimationState::Hit) {
+                animator.restart(AnimationState::Hit);
+            } else if speed > animations.dodge_speed && animator.state != AnimationState::Dodge && animator.can_play(AnimationState::Dodge) {
+                animator.restart(AnimationState::Dodge);
+            } else if cast && animator.can_play(AnimationState::Cast) {
+                animator.restart(AnimationState::Cast);
+            }
+
+            if !animator.state.is_one_shot() || animator.finished {
+                animator.set_state(if speed > animations.run_speed { AnimationState::Run } else { AnimationState::Idle });
+            }
+        }
+
+        let clip = animations.clip(animator.state);
+        animator.advance(clip, delta);
+
+        let Some(column) = clip.frames.get(animator.frame) else {
+            continue;
+        };
+        if let Ok(mut atlas) = atlas_query.get_mut(animator.sprite) {
+            atlas.index = animations.row(animator.facing) * animations.columns + column;
         }
     }
 }
 
 fn face_camera(
-    cam_query: Query<&Transform, With<Camera>>, 
+    cam_query: Query<&Transform, With<Camera>>,
     mut query: Query<&mut Transform, (With<FaceCamera>, Without<Camera>)>
 ) {
     if cam_query.is_empty() { return; }
@@ -48,3 +304,124 @@ fn face_camera(
         transform.rotation = cam_transform.rotation;
     }
 }
+
+#[derive(Default)]
+pub struct PlayerAnimationsLoader;
+
+#[derive(Debug, Display, Error, From)]
+pub enum PlayerAnimationsLoaderError {
+    #[display("could not read player animations: {_0}")]
+    Io(std::io::Error),
+    #[display("could not parse player animations: {_0}")]
+    Ron(ron::error::SpannedError),
+    #[display("every animation needs frames within the atlas columns and a positive frame time")]
+    InvalidClip,
+}
+
+impl AssetLoader for PlayerAnimationsLoader {
+    type Asset = PlayerAnimations;
+    type Settings = ();
+    type Error = PlayerAnimationsLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<PlayerAnimations, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        let animations = ron::de::from_bytes::<PlayerAnimations>(&bytes)?;
+        let states = [AnimationState::Idle, AnimationState::Run, AnimationState::Dodge, AnimationState::Cast, AnimationState::Hit, AnimationState::Death];
+        for state in states {
+            let clip = animations.clip(state);
+            if clip.frames.is_empty() || clip.frames.iter().any(|column| *column >= animations.columns) || clip.frame_seconds <= 0. {
+                return Err(PlayerAnimationsLoaderError::InvalidClip);
+            }
+        }
+
+        Ok(animations)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["anim.ron"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(frames: usize, looping: bool) -> SpriteClip {
+        SpriteClip {
+            frames: (0..frames).collect(),
+            frame_seconds: 0.1,
+            looping,
+        }
+    }
+
+    fn animator(state: AnimationState) -> PlayerAnimator {
+        let mut animator = PlayerAnimator::new(Entity::PLACEHOLDER);
+        animator.restart(state);
+        animator
+    }
+
+    #[test]
+    fn one_shots_are_only_interrupted_by_higher_priorities() {
+        let cast = animator(AnimationState::Cast);
+        assert!(!cast.can_play(AnimationState::Run));
+        assert!(cast.can_play(AnimationState::Cast));
+        assert!(cast.can_play(AnimationState::Hit));
+
+        let hit = animator(AnimationState::Hit);
+        assert!(!hit.can_play(AnimationState::Dodge));
+        assert!(hit.can_play(AnimationState::Death));
+
+        // looping states give way to anything
+        assert!(animator(AnimationState::Run).can_play(AnimationState::Idle));
+    }
+
+    #[test]
+    fn finished_one_shots_give_way() {
+        let mut dodge = animator(AnimationState::Dodge);
+        dodge.advance(&clip(2, false), 0.25);
+        assert!(dodge.finished);
+        assert!(dodge.can_play(AnimationState::Idle));
+    }
+
+    #[test]
+    fn clips_advance_one_frame_per_frame_duration() {
+        let mut run = animator(AnimationState::Run);
+        let clip = clip(3, true);
+
+        run.advance(&clip, 0.05);
+        assert_eq!(run.frame, 0);
+        run.advance(&clip, 0.1);
+        assert_eq!(run.frame, 1);
+        // long frames skip ahead and looping clips wrap around
+        run.advance(&clip, 0.2);
+        assert_eq!(run.frame, 0);
+        assert!(!run.finished);
+    }
+
+    #[test]
+    fn clips_that_dont_loop_hold_their_last_frame() {
+        let mut death = animator(AnimationState::Death);
+        death.advance(&clip(3, false), 10.);
+
+        assert_eq!(death.frame, 2);
+        assert!(death.death_finished());
+    }
+
+    #[test]
+    fn clips_without_duration_stand_still() {
+        let mut idle = animator(AnimationState::Idle);
+        let still = SpriteClip {
+            frame_seconds: 0.,
+            ..clip(3, true)
+        };
+        idle.advance(&still, 1.);
+        assert_eq!(idle.frame, 0);
+    }
+}
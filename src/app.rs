@@ -1,20 +1,36 @@
-use std::time::Duration;
+use std::{path::{Path, PathBuf}, time::Duration};
 
 use bevy::prelude::*;
 use bevy::state::app::StatesPlugin;
 use bevy::{app::App, log::{Level, LogPlugin}, utils::default, DefaultPlugins};
-use bevy_inspector_egui::quick::WorldInspectorPlugin;
-use clap::Parser;
+use bevy_inspector_egui::quick::{FilterQueryInspectorPlugin, WorldInspectorPlugin};
+use clap::{Parser, Subcommand};
 use lightyear::client::config::ClientConfig;
 use lightyear::prelude::*;
 use lightyear::prelude::{client, server};
+use lightyear::prelude::client::{Confirmed, Predicted};
 use lightyear::server::config::ServerConfig;
 
-use crate::settings::{build_server_netcode_config, get_client_net_config, get_server_net_configs, Settings};
+use crate::auth::{load_private_key, start_in_process_service, Accounts, AuthError, AuthService, TokenIssuer};
+use crate::replay::{Replay, ReplayError, ReplayPlaybackPlugin};
+use crate::settings::{build_server_netcode_config, get_client_net_config, get_server_net_configs, Settings, SettingsOverrides};
+use crate::shared::OverheatSharedPlugin;
+use crate::spectator::SpectatorCameraPlugin;
 use crate::{FIXED_TIMESTEP_HZ, REPLICATION_INTERVAL};
 
 #[derive(Parser, PartialEq, Debug)]
-pub enum Cli {
+pub struct Cli {
+    /// Settings file, the settings embedded in the binary are used when omitted
+    #[arg(long, env = "OVERHEAT_SETTINGS", global = true)]
+    pub settings: Option<PathBuf>,
+    #[command(flatten)]
+    pub overrides: SettingsOverrides,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, PartialEq, Debug)]
+pub enum Command {
     /// Client and server run in the same application. Server is also a client
     HostServer {
         #[arg(short, long, default_value = None)]
@@ -22,11 +38,16 @@ pub enum Cli {
     },
     /// Dedicated server
     Server,
-    /// Regular client
-    Client {
-        #[arg(short, long, default_value = None)]
-        client_id: Option<u64>
-    }
+    /// Regular client, its id is assigned by the auth service
+    Client,
+    /// Client watching a match without playing, use `--match` to pick which one
+    Spectate,
+    /// Standalone auth service issuing connect tokens for the server
+    Auth,
+    /// Plays back a round recorded by the server, offline
+    Replay {
+        path: PathBuf,
+    },
 }
 
 fn cli() -> Cli {
@@ -50,30 +71,38 @@ pub enum Apps {
 }
 
 impl Apps {
-    pub fn new(settings: &Settings, cli: Cli) -> Self {
-        match cli {
-            Cli::HostServer { client_id } => {
+    pub fn new(settings: &Settings, command: Command) -> Result<Self, AuthError> {
+        let apps = match command {
+            Command::HostServer { client_id } => {
+                let private_key = load_private_key(&settings.server.private_key)?;
+                start_in_process_service(settings, private_key)?;
+
                 let client_net_config = client::NetConfig::Local {
                     id: client_id.unwrap_or(settings.client.client_id),
                 };
-                let (app, client_config, server_config) = combined_app(settings, vec![], client_net_config);
+                let (app, client_config, server_config) = combined_app(settings, private_key, vec![], client_net_config);
                 Apps::HostServer {
                     app,
                     client_config,
                     server_config
                 }
             },
-            Cli::Server => {
-                let (app, config) = server_app(settings, vec![]);
+            Command::Server => {
+                let private_key = load_private_key(&settings.server.private_key)?;
+                start_in_process_service(settings, private_key)?;
+
+                let (app, config) = server_app(settings, private_key, vec![]);
                 Apps::Server { app, config }
             },
-            Cli::Client { client_id } => {
-                let client_id = client_id.unwrap_or(settings.client.client_id);
-                let net_config = get_client_net_config(&settings, client_id);
+            Command::Client | Command::Spectate => {
+                let net_config = get_client_net_config(settings)?;
                 let (app, config) = client_app(settings, net_config);
                 Apps::Client { app, config }
             },
-        }
+            Command::Auth => unreachable!("the auth service runs without an app, see `run_auth_service`"),
+            Command::Replay { .. } => unreachable!("replays run without networking, see `run_replay`"),
+        };
+        Ok(apps)
     }
 
     pub fn with_server_replication_send_interval(mut self, replication_interval: Duration) -> Self {
@@ -167,8 +196,59 @@ impl Apps {
     }
 }
 
+/// Runs the auth service on the current thread until the process is stopped
+pub fn run_auth_service(settings: &Settings) -> Result<(), AuthError> {
+    let Some(auth) = &settings.auth else {
+        return Err(AuthError::NotConfigured);
+    };
+
+    // only needed to set up logging
+    App::new().add_plugins(LogPlugin {
+        level: Level::INFO,
+        ..default()
+    });
+
+    let private_key = load_private_key(&settings.server.private_key)?;
+    let accounts = Accounts::load(auth.accounts.clone())?;
+    AuthService::bind(auth.service_addr, TokenIssuer::new(settings, auth, private_key), accounts)?.run();
+    Ok(())
+}
+
+/// Plays a recorded round until the window is closed
+pub fn run_replay(settings: &Settings, path: &Path) -> Result<(), ReplayError> {
+    let replay = Replay::load(path)?;
+
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins.build().set(LogPlugin {
+        level: Level::INFO,
+        filter: "wgpu=off,bevy_render=info,bevy_ecs=warn".into(),
+        ..default()
+    }));
+    if settings.client.inspector {
+        app.add_plugins(WorldInspectorPlugin::new());
+    }
+
+    // the client never connects, its plugins provide the protocol registry and visual interpolation
+    let mut shared = shared_config(Mode::Separate);
+    shared.tick.tick_duration = replay.tick_duration;
+    app.add_plugins(client::ClientPlugins {
+        config: ClientConfig {
+            shared,
+            net: client::NetConfig::Local {
+                id: settings.client.client_id,
+            },
+            ..default()
+        },
+    });
+    app.add_plugins((OverheatSharedPlugin, ReplayPlaybackPlugin { replay }, SpectatorCameraPlugin));
+
+    app.run();
+    Ok(())
+}
+
 fn combined_app(
     settings: &Settings,
+    private_key: Key,
     extra_transport_configs: Vec<server::ServerTransport>,
     client_net_config: client::NetConfig,
 ) -> (App, ClientConfig, ServerConfig) {
@@ -177,14 +257,15 @@ fn combined_app(
         level: Level::INFO,
         filter: "wgpu=error,bevy_render=info,bevy_ecs=warn".to_string(),
         ..default()
-    }));
+    }).set(server_asset_plugin()));
     if settings.client.inspector {
         app.add_plugins(WorldInspectorPlugin::new());
+        add_client_inspectors(&mut app);
     }
 
-    let mut net_configs = get_server_net_configs(&settings);
+    let mut net_configs = get_server_net_configs(settings, private_key);
     let extra_net_configs = extra_transport_configs.into_iter().map(|c| {
-        build_server_netcode_config(settings.server.conditioner.as_ref(), &settings.shared, c)
+        build_server_netcode_config(settings.server.conditioner.as_ref(), &settings.shared, private_key, c)
     });
 
     net_configs.extend(extra_net_configs);
@@ -210,13 +291,14 @@ fn combined_app(
 
 fn server_app(
     settings: &Settings,
+    private_key: Key,
     extra_transport_configs: Vec<server::ServerTransport>
 ) -> (App, ServerConfig) {
     let mut app = App::new();
     if !settings.server.headless {
-        app.add_plugins(DefaultPlugins.build().disable::<LogPlugin>());
+        app.add_plugins(DefaultPlugins.build().disable::<LogPlugin>().set(server_asset_plugin()));
     } else {
-        app.add_plugins((MinimalPlugins, StatesPlugin));
+        app.add_plugins((MinimalPlugins, StatesPlugin, server_asset_plugin()));
     }
 
     app.add_plugins(LogPlugin {
@@ -229,9 +311,9 @@ fn server_app(
         app.add_plugins(WorldInspectorPlugin::new());
     }
 
-    let mut net_configs = get_server_net_configs(&settings);
+    let mut net_configs = get_server_net_configs(settings, private_key);
     let extra_net_configs = extra_transport_configs.into_iter().map(|c| {
-        build_server_netcode_config(settings.server.conditioner.as_ref(), &settings.shared, c)
+        build_server_netcode_config(settings.server.conditioner.as_ref(), &settings.shared, private_key, c)
     });
     net_configs.extend(extra_net_configs);
     let server_config = ServerConfig {
@@ -264,6 +346,7 @@ fn client_app(
 
     if settings.client.inspector {
         app.add_plugins(WorldInspectorPlugin::new());
+        add_client_inspectors(&mut app);
     }
     let client_config = ClientConfig {
         shared: shared_config(Mode::Separate),
@@ -277,7 +360,20 @@ fn client_app(
     (app, client_config)
 }
 
-fn shared_config(mode: Mode) -> SharedConfig {
+fn add_client_inspectors(app: &mut App) {
+    app.add_plugins(FilterQueryInspectorPlugin::<With<Predicted>>::default());
+    app.add_plugins(FilterQueryInspectorPlugin::<With<Confirmed>>::default());
+}
+
+/// The server watches the asset folder so that gameplay definitions can be tuned while it is running.
+fn server_asset_plugin() -> AssetPlugin {
+    AssetPlugin {
+        watch_for_changes_override: Some(true),
+        ..default()
+    }
+}
+
+pub(crate) fn shared_config(mode: Mode) -> SharedConfig {
     SharedConfig {
         server_replication_send_interval: REPLICATION_INTERVAL,
         tick: TickConfig {
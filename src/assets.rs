@@ -1,6 +1,8 @@
 use bevy::prelude::*;
 use bevy_asset_loader::asset_collection::AssetCollection;
 
+use crate::{abilities::definition::AbilityDefinition, animation::PlayerAnimations, level::LevelDefinition};
+
 
 #[derive(AssetCollection, Resource)]
 pub struct PlayerAssets {
@@ -14,4 +16,19 @@ pub struct PlayerAssets {
     #[asset(path = "players/player.png")]
     #[asset(image(sampler = nearest))]
     pub player_tileset: Handle<Image>,
-}
\ No newline at end of file
+
+    #[asset(path = "players/player.anim.ron")]
+    pub player_animations: Handle<PlayerAnimations>,
+}
+
+#[derive(AssetCollection, Resource)]
+pub struct AbilityAssets {
+    #[asset(path = "abilities", collection(typed))]
+    pub definitions: Vec<Handle<AbilityDefinition>>,
+}
+
+#[derive(AssetCollection, Resource)]
+pub struct LevelAssets {
+    #[asset(path = "levels/arena.level.ron")]
+    pub level: Handle<LevelDefinition>,
+}
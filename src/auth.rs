@@ -0,0 +1,407 @@
+use std::{collections::{HashMap, HashSet}, fs::OpenOptions, io::{BufRead, BufReader, ErrorKind, Read, Write}, net::{SocketAddr, TcpListener, TcpStream}, path::{Path, PathBuf}, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}, thread::JoinHandle, time::{Duration, Instant}};
+
+use bevy::{asset::ron, log::{error, info, warn}};
+use derive_more::derive::{Display, Error, From};
+use lightyear::{connection::{netcode::{CONNECT_TOKEN_BYTES, USER_DATA_BYTES}, server::{ConnectionRequestHandler, DeniedReason}}, prelude::{client::Authentication, generate_key, ClientId, ConnectToken, Key}};
+
+use crate::settings::{AuthSettings, ServerTransports, Settings};
+
+/// How long a client waits for the auth service before giving up, also the time the service gives
+/// a client to send its whole request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Requests served at the same time, further connections are closed right away
+const MAX_CONCURRENT_REQUESTS: usize = 64;
+/// Longest secret the auth service reads, clients generate 64 hex characters
+const MAX_SECRET_BYTES: usize = 128;
+
+#[derive(Debug, Display, Error, From)]
+pub enum AuthError {
+    #[display("auth service io error: {_0}")]
+    #[from]
+    Io(std::io::Error),
+    #[display("invalid username `{_0}`, it must be between 1 and {} bytes", AuthUserData::MAX_USERNAME_BYTES)]
+    InvalidUsername(#[error(not(source))] String),
+    #[display("could not generate connect token: {_0}")]
+    TokenGeneration(#[error(not(source))] String),
+    #[display("auth service sent an invalid connect token: {_0}")]
+    InvalidToken(#[error(not(source))] String),
+    #[display("no auth settings, add `auth: Some(AuthSettings(...))` to the settings file")]
+    NotConfigured,
+    #[display("private key file {} must hold exactly 32 bytes", _0.display())]
+    InvalidPrivateKey(#[error(not(source))] PathBuf),
+    #[display("the secret must be between 1 and {MAX_SECRET_BYTES} bytes")]
+    InvalidSecret,
+    #[display("{_0} was claimed with another secret")]
+    SecretMismatch(#[error(not(source))] String),
+    #[display("could not read or write accounts: {_0}")]
+    Accounts(#[error(not(source))] String),
+}
+
+/// Reads the 32 bytes at `path`, or fills a new file with random bytes on first use
+fn load_or_create_key(path: &Path) -> Result<Key, AuthError> {
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(mut file) => {
+            let key = generate_key();
+            file.write_all(&key)?;
+            info!("Generated a new key in {}", path.display());
+            return Ok(key);
+        },
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {},
+        Err(e) => return Err(e.into()),
+    }
+
+    std::fs::read(path)?
+        .try_into()
+        .map_err(|_| AuthError::InvalidPrivateKey(path.to_path_buf()))
+}
+
+/// Key the server and the auth service sign and check connect tokens with. It never leaves
+/// them, clients only ever see the tokens.
+pub fn load_private_key(path: &Path) -> Result<Key, AuthError> {
+    load_or_create_key(path)
+}
+
+/// Secret a client proves its username with, created the first time the client connects
+pub fn load_secret(path: &Path) -> Result<String, AuthError> {
+    load_or_create_key(path).map(|secret| secret.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+/// Usernames claimed on the auth service. The first client asking for a username claims it with its
+/// secret, only the same secret gets tokens for that username afterwards.
+#[derive(Default)]
+pub struct Accounts {
+    /// Claims are forgotten when the service stops if there is no file
+    path: Option<PathBuf>,
+    /// Username to the hash of its secret
+    claims: HashMap<String, String>,
+}
+
+impl Accounts {
+    pub fn load(path: Option<PathBuf>) -> Result<Self, AuthError> {
+        let claims = match &path {
+            Some(path) if path.exists() => {
+                let text = std::fs::read_to_string(path)?;
+                ron::de::from_str(&text).map_err(|e| AuthError::Accounts(e.to_string()))?
+            },
+            _ => HashMap::new(),
+        };
+
+        Ok(Self { path, claims })
+    }
+
+    pub fn verify(&mut self, username: &str, secret: &str) -> Result<(), AuthError> {
+        if secret.is_empty() || secret.len() > MAX_SECRET_BYTES {
+            return Err(AuthError::InvalidSecret);
+        }
+
+        let hash = blake3::hash(secret.as_bytes()).to_hex().to_string();
+        match self.claims.get(username) {
+            Some(claim) if *claim == hash => Ok(()),
+            Some(_) => Err(AuthError::SecretMismatch(username.to_string())),
+            None => {
+                self.claims.insert(username.to_string(), hash);
+                self.save()?;
+                info!("{username} claimed their username");
+                Ok(())
+            },
+        }
+    }
+
+    fn save(&self) -> Result<(), AuthError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let text = ron::ser::to_string_pretty(&self.claims, ron::ser::PrettyConfig::default())
+            .map_err(|e| AuthError::Accounts(e.to_string()))?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// Identity of a player, written by the auth service in the user data of their connect token.
+/// Encoded as a length byte followed by the utf-8 username.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthUserData {
+    pub username: String,
+}
+
+impl AuthUserData {
+    pub const MAX_USERNAME_BYTES: usize = USER_DATA_BYTES - 1;
+
+    pub fn new(username: &str) -> Result<Self, AuthError> {
+        let username = username.trim();
+        if username.is_empty() || username.len() > Self::MAX_USERNAME_BYTES {
+            return Err(AuthError::InvalidUsername(username.to_string()));
+        }
+
+        Ok(Self {
+            username: username.to_string(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; USER_DATA_BYTES] {
+        let mut bytes = [0; USER_DATA_BYTES];
+        let username = self.username.as_bytes();
+
+        bytes[0] = username.len() as u8;
+        bytes[1..=username.len()].copy_from_slice(username);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; USER_DATA_BYTES]) -> Option<Self> {
+        let len = bytes[0] as usize;
+        if len == 0 {
+            return None;
+        }
+
+        let username = std::str::from_utf8(&bytes[1..=len]).ok()?;
+        Some(Self {
+            username: username.to_string(),
+        })
+    }
+}
+
+/// Generates connect tokens for the game server, shares its private key
+#[derive(Clone)]
+pub struct TokenIssuer {
+    pub protocol_id: u64,
+    pub private_key: Key,
+    pub server_addresses: Vec<SocketAddr>,
+    pub expire_seconds: i32,
+}
+
+impl TokenIssuer {
+    pub fn new(settings: &Settings, auth: &AuthSettings, private_key: Key) -> Self {
+        // clients connect to the same address for every udp transport of the server
+        let server_addresses = settings
+            .server
+            .transports
+            .iter()
+            .filter_map(|transport| match transport {
+                ServerTransports::Udp { local_port } => Some(SocketAddr::new(settings.client.server_addr.into(), *local_port)),
+                ServerTransports::Steam { .. } => None,
+            })
+            .collect();
+
+        Self {
+            protocol_id: settings.shared.protocol_id,
+            private_key,
+            server_addresses,
+            expire_seconds: auth.token_expire_seconds,
+        }
+    }
+
+    /// Client ids are derived from the username so a player keeps the same id across reconnects.
+    /// 64-bit FNV-1a, unlike std's hashers it gives the same id on every build and platform.
+    pub fn client_id(username: &str) -> u64 {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        username
+            .bytes()
+            .fold(OFFSET_BASIS, |hash, byte| (hash ^ byte as u64).wrapping_mul(PRIME))
+    }
+
+    pub fn issue(&self, user: &AuthUserData) -> Result<ConnectToken, AuthError> {
+        ConnectToken::build(
+            self.server_addresses.as_slice(),
+            self.protocol_id,
+            Self::client_id(&user.username),
+            self.private_key,
+        )
+        .expire_seconds(self.expire_seconds)
+        .user_data(user.to_bytes())
+        .generate()
+        .map_err(|e| AuthError::TokenGeneration(e.to_string()))
+    }
+}
+
+/// Hands out connect tokens over TCP. A client sends its username and its secret, each followed by
+/// a newline, and receives a serialized `ConnectToken`. The connection is closed if the request is
+/// invalid or the username belongs to another secret.
+pub struct AuthService {
+    listener: TcpListener,
+    issuer: Arc<TokenIssuer>,
+    accounts: Arc<Mutex<Accounts>>,
+    /// Requests being served
+    pending: Arc<AtomicUsize>,
+}
+
+impl AuthService {
+    pub fn bind(addr: SocketAddr, issuer: TokenIssuer, accounts: Accounts) -> Result<Self, AuthError> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            issuer: Arc::new(issuer),
+            accounts: Arc::new(Mutex::new(accounts)),
+            pending: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, AuthError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections on the current thread forever, each request is served on its own thread
+    /// so that a slow client can't hold up the others
+    pub fn run(self) {
+        info!("Auth service listening on {:?}", self.local_addr());
+
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Could not accept an auth request: {e}");
+                    continue;
+                },
+            };
+            if self.pending.fetch_add(1, Ordering::AcqRel) >= MAX_CONCURRENT_REQUESTS {
+                self.pending.fetch_sub(1, Ordering::AcqRel);
+                warn!("Too many auth requests, closing the connection from {:?}", stream.peer_addr());
+                continue;
+            }
+
+            let issuer = self.issuer.clone();
+            let accounts = self.accounts.clone();
+            let pending = self.pending.clone();
+            let spawned = std::thread::Builder::new()
+                .name("auth-request".to_string())
+                .spawn(move || {
+                    if let Err(e) = handle_token_request(stream, &issuer, &accounts) {
+                        warn!("Auth request failed: {e}");
+                    }
+                    pending.fetch_sub(1, Ordering::AcqRel);
+                });
+            if let Err(e) = spawned {
+                self.pending.fetch_sub(1, Ordering::AcqRel);
+                error!("Could not spawn a thread for an auth request: {e}");
+            }
+        }
+    }
+
+    /// Serves requests on a background thread, used when the game server runs the service in-process
+    pub fn spawn(self) -> JoinHandle<()> {
+        std::thread::Builder::new()
+            .name("auth-service".to_string())
+            .spawn(move || self.run())
+            .expect("failed to spawn the auth service thread")
+    }
+}
+
+/// Reads from a stream until `deadline`, however slowly the bytes trickle in
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+fn handle_token_request(stream: TcpStream, issuer: &TokenIssuer, accounts: &Mutex<Accounts>) -> Result<(), AuthError> {
+    let deadline = DeadlineReader {
+        stream: &stream,
+        deadline: Instant::now() + REQUEST_TIMEOUT,
+    };
+
+    let mut reader = BufReader::new(deadline).take((AuthUserData::MAX_USERNAME_BYTES + MAX_SECRET_BYTES) as u64 + 2);
+    let mut username = String::new();
+    reader.read_line(&mut username)?;
+    let mut secret = String::new();
+    reader.read_line(&mut secret)?;
+
+    let user = AuthUserData::new(&username)?;
+    // a panic while verifying leaves the claims as they were, they are still usable
+    accounts
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .verify(&user.username, secret.trim())?;
+    let token = issuer.issue(&user)?.try_into_bytes()?;
+
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    (&stream).write_all(&token)?;
+    info!("Issued connect token to {}", user.username);
+    Ok(())
+}
+
+/// Requests a connect token for `username` from the auth service at `addr`
+pub fn request_token(addr: SocketAddr, username: &str, secret: &str) -> Result<ConnectToken, AuthError> {
+    let user = AuthUserData::new(username)?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, REQUEST_TIMEOUT)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.write_all(format!("{}\n{secret}\n", user.username).as_bytes())?;
+
+    let mut bytes = [0; CONNECT_TOKEN_BYTES];
+    stream.read_exact(&mut bytes)?;
+
+    ConnectToken::try_from_bytes(&bytes).map_err(|e| AuthError::InvalidToken(e.to_string()))
+}
+
+/// Remote clients can only connect with a token from the auth service, they never know the server's key
+pub fn client_authentication(settings: &Settings) -> Result<Authentication, AuthError> {
+    let auth = settings.auth.as_ref().ok_or(AuthError::NotConfigured)?;
+    let secret = load_secret(&settings.client.secret)?;
+
+    request_token(auth.service_addr, &settings.client.username, &secret).map(Authentication::Token)
+}
+
+/// Starts the in-process auth service if the settings ask for one
+pub fn start_in_process_service(settings: &Settings, private_key: Key) -> Result<(), AuthError> {
+    let Some(auth) = settings.auth.as_ref().filter(|auth| auth.in_process) else {
+        return Ok(());
+    };
+
+    let accounts = Accounts::load(auth.accounts.clone())?;
+    AuthService::bind(auth.service_addr, TokenIssuer::new(settings, auth, private_key), accounts)?.spawn();
+    Ok(())
+}
+
+/// Accepts netcode clients based on the username in their connect token
+#[derive(Debug)]
+pub struct AuthConnectionRequestHandler {
+    /// Only these users can connect, unless empty
+    allow: HashSet<String>,
+    ban: HashSet<String>,
+}
+
+impl AuthConnectionRequestHandler {
+    pub fn new(auth: &AuthSettings) -> Self {
+        Self {
+            allow: auth.allow.iter().cloned().collect(),
+            ban: auth.ban.iter().cloned().collect(),
+        }
+    }
+}
+
+impl ConnectionRequestHandler for AuthConnectionRequestHandler {
+    /// Only reached by transports without connect tokens, which authenticate clients themselves
+    fn handle_request(&self, _client_id: ClientId) -> Option<DeniedReason> {
+        None
+    }
+
+    fn handle_request_with_user_data(&self, client_id: ClientId, user_data: &[u8; USER_DATA_BYTES]) -> Option<DeniedReason> {
+        let Some(user) = AuthUserData::from_bytes(user_data) else {
+            error!("Client {client_id:?} connected without a username in its token");
+            return Some(DeniedReason::InvalidToken);
+        };
+
+        if self.ban.contains(&user.username) {
+            info!("Denied connection from banned user {}", user.username);
+            return Some(DeniedReason::Banned);
+        }
+        if !self.allow.is_empty() && !self.allow.contains(&user.username) {
+            info!("Denied connection from {}, not on the allow list", user.username);
+            return Some(DeniedReason::Custom(format!("{} is not allowed on this server", user.username)));
+        }
+
+        None
+    }
+}
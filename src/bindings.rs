@@ -0,0 +1,482 @@
+use std::path::{Path, PathBuf};
+
+use bevy::{asset::ron, input::{gamepad::GamepadButton, InputSystem}, prelude::*, tasks::IoTaskPool};
+use leafwing_input_manager::{plugin::InputManagerSystem, prelude::{ActionState, GamepadStick, InputMap, KeyboardVirtualDPad, WithDualAxisProcessingPipelineExt}};
+use serde::{Deserialize, Serialize};
+
+use crate::player::PlayerActions;
+
+/// Stick movement below this is ignored
+const STICK_DEADZONE: f32 = 0.15;
+const FONT_SIZE: f32 = 20.;
+const SELECTED_COLOR: Color = Color::srgb(1., 0.85, 0.3);
+/// Opens the chat, can't be rebound
+pub const CHAT_KEY: KeyCode = KeyCode::Enter;
+/// Opens the team chat, can't be rebound
+pub const TEAM_CHAT_KEY: KeyCode = KeyCode::KeyT;
+/// Keys with a fixed use, pressing them also opens a menu or the chat so no action can take them
+const RESERVED_KEYS: [KeyCode; 5] = [KeyCode::F1, KeyCode::Escape, KeyCode::Tab, CHAT_KEY, TEAM_CHAT_KEY];
+/// Furthest the right stick places the cursor
+const MAX_AIM_DISTANCE: f32 = 50.;
+
+/// Player bindings, loaded at startup and saved whenever they are changed in the menu opened with F1.
+/// Gamepad sticks can't be rebound: the left one moves and the right one aims.
+pub struct BindingsPlugin {
+    /// File the bindings are loaded from and saved to, they are only kept in memory when `None`
+    pub path: Option<PathBuf>,
+}
+
+impl Plugin for BindingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Bindings>();
+        app.init_resource::<BindingsMenu>();
+        // the chat is usable without a player, the bindings are also kept in resources for it
+        app.init_resource::<InputMap<PlayerActions>>();
+        app.init_resource::<ActionState<PlayerActions>>();
+        app.insert_resource(BindingsFile {
+            path: self.path.clone(),
+            saved: None,
+        });
+
+        app.add_systems(Startup, (load_bindings, init_bindings_menu));
+        // the menu takes the inputs before the player's actions are updated from them
+        app.add_systems(PreUpdate, navigate_bindings_menu.after(InputSystem).before(InputManagerSystem::Update));
+        app.add_systems(Update, (
+            apply_bindings,
+            save_bindings,
+            update_bindings_menu,
+        ));
+    }
+}
+
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Bindings {
+    pub move_up: KeyCode,
+    pub move_down: KeyCode,
+    pub move_left: KeyCode,
+    pub move_right: KeyCode,
+    pub dodge: Vec<Binding>,
+    pub primary_attack: Vec<Binding>,
+    /// How far from the player the right stick places the cursor
+    pub aim_distance: f32,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self {
+            move_up: KeyCode::KeyW,
+            move_down: KeyCode::KeyS,
+            move_left: KeyCode::KeyA,
+            move_right: KeyCode::KeyD,
+            dodge: vec![Binding::Key(KeyCode::Space), Binding::Gamepad(GamepadButtonType::South)],
+            primary_attack: vec![Binding::Mouse(MouseButton::Left), Binding::Gamepad(GamepadButtonType::West)],
+            aim_distance: 6.,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButtonType),
+}
+
+impl Binding {
+    fn label(&self) -> String {
+        match self {
+            Self::Key(key) => format!("{key:?}"),
+            Self::Mouse(button) => format!("Mouse {button:?}"),
+            Self::Gamepad(button) => format!("Gamepad {button:?}"),
+        }
+    }
+}
+
+/// Rows of the bindings menu
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BindingSlot {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Dodge,
+    PrimaryAttack,
+}
+
+impl BindingSlot {
+    const ALL: [Self; 6] = [Self::MoveUp, Self::MoveDown, Self::MoveLeft, Self::MoveRight, Self::Dodge, Self::PrimaryAttack];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::MoveUp => "Move up",
+            Self::MoveDown => "Move down",
+            Self::MoveLeft => "Move left",
+            Self::MoveRight => "Move right",
+            Self::Dodge => "Dodge",
+            Self::PrimaryAttack => "Primary attack",
+        }
+    }
+}
+
+impl Bindings {
+    pub fn input_map(&self) -> InputMap<PlayerActions> {
+        let mut input_map = InputMap::default()
+            .with_dual_axis(
+                PlayerActions::Move, KeyboardVirtualDPad::new(self.move_up, self.move_down, self.move_left, self.move_right)
+                    .inverted_y()
+            )
+            .with_dual_axis(
+                PlayerActions::Move, GamepadStick::LEFT
+                    .with_circle_deadzone(STICK_DEADZONE)
+                    .inverted_y()
+            )
+            .with_dual_axis(
+                PlayerActions::Aim, GamepadStick::RIGHT
+                    .with_circle_deadzone(STICK_DEADZONE)
+                    .inverted_y()
+            )
+            .with(PlayerActions::Chat, CHAT_KEY)
+            .with(PlayerActions::TeamChat, TEAM_CHAT_KEY);
+
+        for (action, bindings) in [(PlayerActions::Dodge, &self.dodge), (PlayerActions::PrimaryAttack, &self.primary_attack)] {
+            for binding in bindings {
+                match *binding {
+                    Binding::Key(key) => input_map.insert(action, key),
+                    Binding::Mouse(button) => input_map.insert(action, button),
+                    Binding::Gamepad(button) => input_map.insert(action, button),
+                };
+            }
+        }
+        input_map
+    }
+
+    fn describe(&self, slot: BindingSlot) -> String {
+        let bindings = match slot {
+            BindingSlot::MoveUp => return Binding::Key(self.move_up).label(),
+            BindingSlot::MoveDown => return Binding::Key(self.move_down).label(),
+            BindingSlot::MoveLeft => return Binding::Key(self.move_left).label(),
+            BindingSlot::MoveRight => return Binding::Key(self.move_right).label(),
+            BindingSlot::Dodge => &self.dodge,
+            BindingSlot::PrimaryAttack => &self.primary_attack,
+        };
+
+        if bindings.is_empty() {
+            return "unbound".to_string();
+        }
+        bindings.iter().map(Binding::label).collect::<Vec<_>>().join(", ")
+    }
+
+    /// Replaces the binding of the same device, keyboard and mouse counting as one. Movement only
+    /// takes keys and reserved keys are refused, returns whether `binding` was accepted.
+    fn bind(&mut self, slot: BindingSlot, binding: Binding) -> bool {
+        if matches!(binding, Binding::Key(key) if RESERVED_KEYS.contains(&key)) {
+            return false;
+        }
+
+        let bindings = match (slot, binding) {
+            (BindingSlot::MoveUp, Binding::Key(key)) => {
+                self.move_up = key;
+                return true;
+            },
+            (BindingSlot::MoveDown, Binding::Key(key)) => {
+                self.move_down = key;
+                return true;
+            },
+            (BindingSlot::MoveLeft, Binding::Key(key)) => {
+                self.move_left = key;
+                return true;
+            },
+            (BindingSlot::MoveRight, Binding::Key(key)) => {
+                self.move_right = key;
+                return true;
+            },
+            (BindingSlot::MoveUp | BindingSlot::MoveDown | BindingSlot::MoveLeft | BindingSlot::MoveRight, _) => return false,
+            (BindingSlot::Dodge, _) => &mut self.dodge,
+            (BindingSlot::PrimaryAttack, _) => &mut self.primary_attack,
+        };
+
+        let gamepad = matches!(binding, Binding::Gamepad(_));
+        bindings.retain(|existing| matches!(existing, Binding::Gamepad(_)) != gamepad);
+        bindings.push(binding);
+        true
+    }
+
+    fn clear(&mut self, slot: BindingSlot) {
+        match slot {
+            BindingSlot::Dodge => self.dodge.clear(),
+            BindingSlot::PrimaryAttack => self.primary_attack.clear(),
+            // the player could no longer move
+            _ => {},
+        }
+    }
+}
+
+#[derive(Resource)]
+struct BindingsFile {
+    path: Option<PathBuf>,
+    /// Last bindings read from or written to the file
+    saved: Option<Bindings>,
+}
+
+#[derive(Resource, Default)]
+pub struct BindingsMenu {
+    open: bool,
+    selected: usize,
+    /// The next key or button pressed is bound to the selected row
+    listening: bool,
+}
+
+#[derive(Component)]
+struct BindingsMenuText;
+
+fn read_bindings(path: &Path) -> Option<Bindings> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        // saved once the defaults are in place
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Could not read bindings {}, using the defaults: {e}", path.display());
+            return None;
+        },
+    };
+
+    match ron::de::from_str::<Bindings>(&text) {
+        Ok(mut bindings) => {
+            bindings.aim_distance = clamp_aim_distance(bindings.aim_distance);
+            Some(bindings)
+        },
+        Err(e) => {
+            warn!("Could not parse bindings {}, using the defaults: {e}", path.display());
+            None
+        },
+    }
+}
+
+/// Edited files may hold anything, a NaN distance would put the cursor nowhere
+fn clamp_aim_distance(distance: f32) -> f32 {
+    if distance.is_nan() {
+        return Bindings::default().aim_distance;
+    }
+    distance.clamp(0., MAX_AIM_DISTANCE)
+}
+
+fn load_bindings(
+    mut bindings: ResMut<Bindings>,
+    mut file: ResMut<BindingsFile>,
+) {
+    let Some(loaded) = file.path.as_deref().and_then(read_bindings) else {
+        return;
+    };
+
+    info!("Loaded bindings");
+    *bindings = loaded.clone();
+    file.saved = Some(loaded);
+}
+
+fn save_bindings(
+    bindings: Res<Bindings>,
+    mut file: ResMut<BindingsFile>,
+) {
+    if !bindings.is_changed() || file.saved.as_ref() == Some(&*bindings) {
+        return;
+    }
+    let Some(path) = file.path.clone() else {
+        return;
+    };
+
+    let text = match ron::ser::to_string_pretty(&*bindings, default()) {
+        Ok(text) => text,
+        Err(e) => {
+            error!("Could not serialize bindings: {e}");
+            return;
+        },
+    };
+    file.saved = Some(bindings.clone());
+
+    IoTaskPool::get()
+        .spawn(async move {
+            match std::fs::write(&path, text) {
+                Ok(()) => info!("Saved bindings {}", path.display()),
+                Err(e) => error!("Could not save bindings {}: {e}", path.display()),
+            }
+        })
+        .detach();
+}
+
+/// Rebinding takes effect right away, also on the player of a round in progress
+fn apply_bindings(
+    bindings: Res<Bindings>,
+    mut global_input_map: ResMut<InputMap<PlayerActions>>,
+    mut query: Query<&mut InputMap<PlayerActions>>,
+) {
+    if !bindings.is_changed() {
+        return;
+    }
+
+    *global_input_map = bindings.input_map();
+    for mut input_map in query.iter_mut() {
+        *input_map = bindings.input_map();
+    }
+}
+
+fn init_bindings_menu(
+    mut commands: Commands,
+) {
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        ..default()
+    })
+    .with_children(|menu| {
+        menu.spawn((
+            TextBundle {
+                visibility: Visibility::Hidden,
+                background_color: Color::srgba(0., 0., 0., 0.8).into(),
+                style: Style {
+                    padding: UiRect::all(Val::Px(16.)),
+                    ..default()
+                },
+                ..default()
+            },
+            BindingsMenuText,
+        ));
+    });
+}
+
+/// Ordered after the chat, which takes the keys typed while it is open, F1 included
+pub fn navigate_bindings_menu(
+    mut menu: ResMut<BindingsMenu>,
+    mut bindings: ResMut<Bindings>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut mouse_buttons: ResMut<ButtonInput<MouseButton>>,
+    mut gamepad_buttons: ResMut<ButtonInput<GamepadButton>>,
+) {
+    if keys.just_pressed(KeyCode::F1) {
+        menu.open = !menu.open;
+        menu.listening = false;
+    }
+    if !menu.open {
+        return;
+    }
+
+    let slot = BindingSlot::ALL[menu.selected];
+    if menu.listening {
+        let pressed = keys.get_just_pressed().next().copied().map(Binding::Key)
+            .or_else(|| mouse_buttons.get_just_pressed().next().copied().map(Binding::Mouse))
+            .or_else(|| gamepad_buttons.get_just_pressed().next().map(|button| Binding::Gamepad(button.button_type)));
+
+        match pressed {
+            Some(Binding::Key(KeyCode::Escape)) => menu.listening = false,
+            Some(binding) if bindings.bind(slot, binding) => menu.listening = false,
+            _ => {},
+        }
+    } else if keys.just_pressed(KeyCode::ArrowUp) {
+        menu.selected = (menu.selected + BindingSlot::ALL.len() - 1) % BindingSlot::ALL.len();
+    } else if keys.just_pressed(KeyCode::ArrowDown) {
+        menu.selected = (menu.selected + 1) % BindingSlot::ALL.len();
+    } else if keys.just_pressed(KeyCode::Enter) {
+        menu.listening = true;
+    } else if keys.just_pressed(KeyCode::Delete) {
+        bindings.clear(slot);
+    } else if keys.just_pressed(KeyCode::Escape) {
+        menu.open = false;
+    }
+
+    // nothing pressed in the menu reaches the player
+    keys.reset_all();
+    mouse_buttons.reset_all();
+    gamepad_buttons.reset_all();
+}
+
+fn update_bindings_menu(
+    menu: Res<BindingsMenu>,
+    bindings: Res<Bindings>,
+    mut text_query: Query<(&mut Text, &mut Visibility), With<BindingsMenuText>>,
+) {
+    if !menu.is_changed() && !bindings.is_changed() {
+        return;
+    }
+    let Ok((mut text, mut visibility)) = text_query.get_single_mut() else {
+        return;
+    };
+
+    *visibility = if menu.open { Visibility::Visible } else { Visibility::Hidden };
+
+    let style = |color: Color| TextStyle {
+        font_size: FONT_SIZE,
+        color,
+        ..default()
+    };
+    let mut sections = vec![TextSection::new(
+        "Controls - Up/Down to select, Enter to rebind, Delete to clear, F1 to close\n\n",
+        style(Color::WHITE),
+    )];
+    for (index, slot) in BindingSlot::ALL.iter().enumerate() {
+        let selected = index == menu.selected;
+        let value = if selected && menu.listening {
+            "press a key or button, Escape to cancel".to_string()
+        } else {
+            bindings.describe(*slot)
+        };
+        let color = if selected { SELECTED_COLOR } else { Color::WHITE };
+        sections.push(TextSection::new(format!("{}: {value}\n", slot.label()), style(color)));
+    }
+    text.sections = sections;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings_replace_the_same_device() {
+        let mut bindings = Bindings::default();
+
+        assert!(bindings.bind(BindingSlot::Dodge, Binding::Key(KeyCode::ShiftLeft)));
+        assert_eq!(bindings.dodge, vec![Binding::Gamepad(GamepadButtonType::South), Binding::Key(KeyCode::ShiftLeft)]);
+
+        // keyboard and mouse count as one device
+        assert!(bindings.bind(BindingSlot::Dodge, Binding::Mouse(MouseButton::Right)));
+        assert!(bindings.bind(BindingSlot::Dodge, Binding::Gamepad(GamepadButtonType::East)));
+        assert_eq!(bindings.dodge, vec![Binding::Mouse(MouseButton::Right), Binding::Gamepad(GamepadButtonType::East)]);
+    }
+
+    #[test]
+    fn movement_only_takes_keys() {
+        let mut bindings = Bindings::default();
+
+        assert!(bindings.bind(BindingSlot::MoveUp, Binding::Key(KeyCode::ArrowUp)));
+        assert_eq!(bindings.move_up, KeyCode::ArrowUp);
+        assert!(!bindings.bind(BindingSlot::MoveLeft, Binding::Mouse(MouseButton::Left)));
+        assert!(!bindings.bind(BindingSlot::MoveRight, Binding::Gamepad(GamepadButtonType::DPadRight)));
+        assert_eq!(bindings.move_left, KeyCode::KeyA);
+        assert_eq!(bindings.move_right, KeyCode::KeyD);
+    }
+
+    #[test]
+    fn reserved_keys_are_refused() {
+        let mut bindings = Bindings::default();
+
+        for key in RESERVED_KEYS {
+            for slot in BindingSlot::ALL {
+                assert!(!bindings.bind(slot, Binding::Key(key)), "{key:?} was bound to {slot:?}");
+            }
+        }
+        assert_eq!(bindings, Bindings::default());
+    }
+
+    #[test]
+    fn aim_distance_is_clamped() {
+        assert_eq!(clamp_aim_distance(8.), 8.);
+        assert_eq!(clamp_aim_distance(-3.), 0.);
+        assert_eq!(clamp_aim_distance(f32::INFINITY), MAX_AIM_DISTANCE);
+        assert_eq!(clamp_aim_distance(f32::NAN), Bindings::default().aim_distance);
+    }
+}
@@ -0,0 +1,141 @@
+use avian3d::prelude::Position;
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+use lightyear::prelude::ClientId;
+
+use crate::{ability_framework::{pool::Pool, pools::life::LifePool}, combat::{Dead, SpawnPoints}, lobby::{spawn_match, InMatch, MatchId, MatchRoster, RosterEntry}, player::{move_input_towards, PlayerActions, PlayerId}, settings::LobbySettings, shared::GameState, team::Team};
+
+/// Players within this distance are chased
+const CHASE_RANGE: f32 = 30.;
+/// Bots fire at their target within this distance
+const ATTACK_RANGE: f32 = 15.;
+/// Fraction of life under which bots run away from their target
+const FLEE_LIFE: f32 = 0.3;
+/// Distance at which a wander goal counts as reached
+const WANDER_REACHED: f32 = 2.;
+
+/// Adds server-controlled players to the matches, they are readied automatically and play like any client
+pub struct BotServerPlugin {
+    pub count: usize,
+}
+
+impl Plugin for BotServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(BotCount(self.count));
+
+        app.add_systems(OnEnter(GameState::Game), open_bot_matches);
+        // same schedule where client inputs are applied to their players
+        app.add_systems(FixedPreUpdate, drive_bots);
+    }
+}
+
+#[derive(Resource)]
+struct BotCount(usize);
+
+/// Bots take the client ids at the top of the local range, away from host-server clients
+pub fn bot_client_id(index: usize) -> ClientId {
+    ClientId::Local(u64::MAX - index as u64)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub enum BotBehaviour {
+    /// Walks between the level's spawn points
+    #[default]
+    Wander,
+    /// Moves towards the nearest enemy and fires once in range
+    Chase,
+    /// Runs away from the nearest enemy, dodging when possible
+    Flee,
+}
+
+/// Server-side brain of a bot player, replacing the inputs a client would send
+#[derive(Component, Debug, Default)]
+pub struct Bot {
+    pub behaviour: BotBehaviour,
+    pub target: Option<Entity>,
+    /// Where projectiles are fired, in place of a cursor
+    pub aim: Vec3,
+    wander_goal: usize,
+}
+
+/// Fills matches with bots, as many per match as the lobby allows
+fn open_bot_matches(
+    mut commands: Commands,
+    count: Res<BotCount>,
+    settings: Res<LobbySettings>,
+) {
+    let ids: Vec<ClientId> = (0..count.0).map(bot_client_id).collect();
+
+    for (match_id, chunk) in (0..settings.max_matches).zip(ids.chunks(settings.max_players_per_match)) {
+        let match_id = MatchId(match_id);
+        let rules = settings.team_rules(match_id);
+        let mut roster = MatchRoster::default();
+        for client_id in chunk.iter().copied() {
+            roster.join(RosterEntry::bot(client_id), &rules);
+        }
+        spawn_match(&mut commands, match_id, roster, rules);
+    }
+}
+
+fn drive_bots(
+    spawn_points: Res<SpawnPoints>,
+    mut bot_query: Query<(Entity, &mut Bot, &mut ActionState<PlayerActions>, &Position, &LifePool, &InMatch, Option<&Team>), Without<Dead>>,
+    player_query: Query<(Entity, &Position, &InMatch, Option<&Team>), (With<PlayerId>, Without<Dead>)>,
+) {
+    for (entity, mut bot, mut actions, position, life, in_match, team) in bot_query.iter_mut() {
+        // teammates are left alone
+        let nearest = player_query
+            .iter()
+            .filter(|(other, _, other_match, other_team)| *other != entity && *other_match == in_match && !Team::allied(team.copied(), other_team.copied()))
+            .map(|(other, other_position, ..)| (other, other_position.0, other_position.0.distance(position.0)))
+            .filter(|(.., distance)| *distance <= CHASE_RANGE)
+            .min_by(|(.., a), (.., b)| a.total_cmp(b));
+
+        bot.target = nearest.map(|(target, ..)| target);
+        bot.behaviour = match nearest {
+            Some(_) if life.current() / life.max() < FLEE_LIFE => BotBehaviour::Flee,
+            Some(_) => BotBehaviour::Chase,
+            None => BotBehaviour::Wander,
+        };
+
+        let mut fire = false;
+        let mut dodge = false;
+        let direction = match (bot.behaviour, nearest) {
+            (BotBehaviour::Chase, Some((_, target_position, distance))) => {
+                bot.aim = target_position;
+                fire = distance <= ATTACK_RANGE;
+                target_position - position.0
+            },
+            (BotBehaviour::Flee, Some((_, target_position, _))) => {
+                dodge = true;
+                position.0 - target_position
+            },
+            _ => {
+                let points = &spawn_points.points;
+                if points.is_empty() {
+                    Vec3::ZERO
+                } else {
+                    let mut goal = points[(entity.index() as usize + bot.wander_goal) % points.len()];
+                    if goal.distance(position.0) < WANDER_REACHED {
+                        bot.wander_goal += 1;
+                        goal = points[(entity.index() as usize + bot.wander_goal) % points.len()];
+                    }
+                    goal - position.0
+                }
+            },
+        };
+
+        actions.set_axis_pair(&PlayerActions::Move, move_input_towards(direction));
+        // abilities trigger on presses, so held buttons are released every other tick
+        press_repeatedly(&mut actions, PlayerActions::PrimaryAttack, fire);
+        press_repeatedly(&mut actions, PlayerActions::Dodge, dodge);
+    }
+}
+
+fn press_repeatedly(actions: &mut ActionState<PlayerActions>, action: PlayerActions, active: bool) {
+    if active && !actions.pressed(&action) {
+        actions.press(&action);
+    } else {
+        actions.release(&action);
+    }
+}
@@ -0,0 +1,205 @@
+use avian3d::prelude::PhysicsSet;
+use bevy::prelude::*;
+use lightyear::prelude::client::{InterpolationSet, Rollback};
+use lightyear::shared::replication::components::Controlled;
+
+use crate::{abilities::definition::{AbilityEffect, AbilityEffects}, ability_framework::{pipeline::AbilityPipelineSet, pool::Pool, pools::life::{Life, LifePool}, TriggerAbility}, level::LevelShape, player::CursorPosition, rendering::{LocalPlayer, CAMERA_OFFSET}, settings::CameraSettings};
+
+/// Smoothly follows the local player, leaning towards the cursor and staying over the level.
+/// Damage and dodges add trauma which shakes the camera until it wears off.
+pub struct CameraControllerPlugin;
+
+impl Plugin for CameraControllerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CameraSettings>();
+
+        app.add_systems(Update, (
+            init_camera_controller,
+            add_damage_trauma,
+        ));
+        // dodges are re-triggered while re-simulating, only the first simulation of a tick shakes the camera
+        app.add_systems(FixedUpdate, add_dodge_trauma.after(AbilityPipelineSet));
+        // players are drawn at their visually interpolated position, synced to their `Transform` in post update
+        app.add_systems(PostUpdate, follow_local_player
+            .after(InterpolationSet::VisualInterpolation)
+            .after(PhysicsSet::Sync)
+            .before(TransformSystem::TransformPropagate)
+        );
+    }
+}
+
+/// Speed of the shake noise, higher is more jittery
+const SHAKE_FREQUENCY: f32 = 25.;
+
+#[derive(Component)]
+pub struct CameraController {
+    /// Point on the ground the camera looks at, before shaking
+    focus: Option<Vec3>,
+    /// Player being followed, the camera jumps to a new one instead of panning across the level
+    followed: Option<Entity>,
+    /// Rotation of the camera when it isn't shaking
+    rotation: Quat,
+    /// Between 0 and 1, the shake grows with its square
+    trauma: f32,
+    last_life: Option<Life>,
+}
+
+impl CameraController {
+    pub fn add_trauma(&mut self, trauma: f32) {
+        self.trauma = (self.trauma + trauma).min(1.);
+    }
+}
+
+fn init_camera_controller(
+    mut commands: Commands,
+    query: Query<(Entity, &Transform), (With<Camera3d>, Without<CameraController>)>,
+) {
+    for (entity, transform) in query.iter() {
+        commands.entity(entity).insert(CameraController {
+            focus: None,
+            followed: None,
+            rotation: transform.rotation,
+            trauma: 0.,
+            last_life: None,
+        });
+    }
+}
+
+fn add_damage_trauma(
+    settings: Res<CameraSettings>,
+    player_query: Query<&LifePool, (LocalPlayer, Changed<LifePool>)>,
+    mut camera_query: Query<&mut CameraController>,
+) {
+    let Ok(life) = player_query.get_single() else {
+        return;
+    };
+
+    for mut controller in camera_query.iter_mut() {
+        if controller.last_life.is_some_and(|last| life.current() < last) {
+            controller.add_trauma(settings.damage_trauma);
+        }
+        controller.last_life = Some(life.current());
+    }
+}
+
+fn add_dodge_trauma(
+    settings: Res<CameraSettings>,
+    rollback: Option<Res<Rollback>>,
+    mut triggers: EventReader<TriggerAbility>,
+    player_query: Query<(), LocalPlayer>,
+    ability_query: Query<&AbilityEffects>,
+    mut camera_query: Query<&mut CameraController>,
+) {
+    if rollback.is_some_and(|rollback| rollback.is_rollback()) {
+        triggers.clear();
+        return;
+    }
+
+    for trigger in triggers.read() {
+        if !player_query.contains(trigger.source) {
+            continue;
+        }
+        let Ok(effects) = ability_query.get(trigger.ability) else {
+            continue;
+        };
+        if !effects.0.iter().any(|effect| matches!(effect, AbilityEffect::Dash { .. })) {
+            continue;
+        }
+
+        for mut controller in camera_query.iter_mut() {
+            controller.add_trauma(settings.dodge_trauma);
+        }
+    }
+}
+
+/// Smooth noise between -1 and 1, `seed` decorrelates the axes
+fn shake_noise(time: f32, seed: f32) -> f32 {
+    let t = (time + seed) * SHAKE_FREQUENCY;
+    (t.sin() * 0.6 + (t * 1.73 + 0.5).sin() * 0.4).clamp(-1., 1.)
+}
+
+/// The camera stays `inset` inside the level edges, or centered on levels smaller than that
+fn clamp_to_level(point: Vec2, level: Rect, inset: f32) -> Vec2 {
+    let inset = Vec2::splat(inset).min(level.half_size());
+    point.clamp(level.min + inset, level.max - inset)
+}
+
+fn follow_local_player(
+    time: Res<Time>,
+    settings: Res<CameraSettings>,
+    player_query: Query<(Entity, &Transform), (LocalPlayer, Without<CameraController>)>,
+    cursor_query: Query<&CursorPosition, With<Controlled>>,
+    shape_query: Query<&LevelShape>,
+    mut camera_query: Query<(&mut Transform, &mut CameraController)>,
+) {
+    let Ok((player, player_transform)) = player_query.get_single() else {
+        // spectators move the camera themselves, only undo the shake
+        for (mut camera, mut controller) in camera_query.iter_mut() {
+            camera.rotation = controller.rotation;
+            controller.followed = None;
+            controller.trauma = 0.;
+        }
+        return;
+    };
+    let delta = time.delta_seconds();
+
+    let mut target = player_transform.translation.with_y(0.);
+    if let Ok(cursor) = cursor_query.get_single() {
+        let look_ahead = (cursor.0.with_y(0.) - target) * settings.look_ahead;
+        target += look_ahead.clamp_length_max(settings.max_look_ahead);
+    }
+
+    let level = shape_query
+        .iter()
+        .map(|shape| shape.footprint())
+        .reduce(|level, footprint| level.union(footprint));
+    if let Some(level) = level {
+        let clamped = clamp_to_level(target.xz(), level, settings.bounds_inset);
+        target = Vec3::new(clamped.x, target.y, clamped.y);
+    }
+
+    for (mut camera, mut controller) in camera_query.iter_mut() {
+        let focus = match controller.focus {
+            Some(focus) if controller.followed == Some(player) && settings.damping > 0. => {
+                target.lerp(focus, (-settings.damping * delta).exp())
+            },
+            _ => target,
+        };
+        controller.focus = Some(focus);
+        controller.followed = Some(player);
+
+        let shake = controller.trauma * controller.trauma;
+        let elapsed = time.elapsed_seconds();
+        let offset = Vec2::new(shake_noise(elapsed, 0.), shake_noise(elapsed, 17.)) * settings.max_shake_offset * shake;
+        let roll = shake_noise(elapsed, 41.) * settings.max_shake_roll * shake;
+
+        let rotation = controller.rotation;
+        camera.translation = focus + CAMERA_OFFSET + rotation * offset.extend(0.);
+        camera.rotation = rotation * Quat::from_rotation_z(roll);
+
+        controller.trauma = (controller.trauma - settings.trauma_decay * delta).max(0.);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_stay_inset_in_the_level() {
+        let level = Rect::new(-10., -10., 10., 10.);
+
+        assert_eq!(clamp_to_level(Vec2::new(2., -3.), level, 4.), Vec2::new(2., -3.));
+        assert_eq!(clamp_to_level(Vec2::new(9., -20.), level, 4.), Vec2::new(6., -6.));
+        assert_eq!(clamp_to_level(Vec2::new(-7., 0.), level, 0.), Vec2::new(-7., 0.));
+    }
+
+    #[test]
+    fn small_levels_are_centered() {
+        let level = Rect::new(0., 0., 6., 20.);
+
+        // too narrow for the inset on x, wide enough on z
+        assert_eq!(clamp_to_level(Vec2::new(-5., 30.), level, 4.), Vec2::new(3., 16.));
+        assert_eq!(clamp_to_level(Vec2::new(5., 1.), level, 4.), Vec2::new(3., 4.));
+    }
+}
@@ -0,0 +1,498 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::{input::{keyboard::{Key, KeyboardInput}, ButtonState, InputSystem}, prelude::*, utils::HashMap};
+use leafwing_input_manager::{plugin::InputManagerSystem, prelude::ActionState};
+use lightyear::prelude::{client::{self, ClientConnection, NetClient}, server::ConnectionManager, ClientId, NetworkTarget};
+use lightyear::server::events::{DisconnectEvent, MessageEvent};
+use serde::{Deserialize, Serialize};
+
+use crate::{bindings::navigate_bindings_menu, lobby::MatchRoster, player::PlayerActions, protocol::ChatChannel, settings::ChatSettings};
+
+/// Lines kept in the chat box
+const MAX_LINES: usize = 8;
+
+const TEXT_COLOR: Color = Color::WHITE;
+const TEAM_COLOR: Color = Color::srgb(0.5, 0.75, 1.);
+const WHISPER_COLOR: Color = Color::srgb(1., 0.6, 0.9);
+const EMOTE_COLOR: Color = Color::srgb(1., 0.85, 0.4);
+const NOTICE_COLOR: Color = Color::srgb(0.7, 0.7, 0.7);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChatTarget {
+    /// Everyone in the sender's match, spectators included
+    All,
+    /// The sender's teammates, or the other spectators of the match when sent by a spectator
+    Team,
+    /// A single client of the sender's match, by the raw value of its `ClientId`
+    Whisper(u64),
+}
+
+/// Sent by a client, the server checks it and relays it to `target`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SendChatMessage {
+    pub target: ChatTarget,
+    /// Describes an action of the sender, like `/me waves`
+    pub emote: bool,
+    pub text: String,
+}
+
+/// Chat line received by a client, `from` is `None` for notices from the server
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChatMessage {
+    pub from: Option<ClientId>,
+    pub target: ChatTarget,
+    pub emote: bool,
+    pub text: String,
+}
+
+pub fn display_name(client_id: ClientId) -> String {
+    format!("Player {}", client_id.to_bits())
+}
+
+/// Relays chat messages between the clients of a match
+pub struct ChatServerPlugin {
+    pub settings: ChatSettings,
+}
+
+impl Plugin for ChatServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.settings.clone());
+        app.init_resource::<ChatRateLimits>();
+
+        app.add_systems(Update, (
+            relay_chat_messages,
+            forget_disconnected_senders,
+        ));
+    }
+}
+
+/// When each client sent its recent messages
+#[derive(Resource, Default)]
+struct ChatRateLimits {
+    sent: HashMap<ClientId, VecDeque<Duration>>,
+}
+
+impl ChatRateLimits {
+    /// Allows at most `burst` messages within any window of `window_seconds`
+    fn allow(&mut self, client_id: ClientId, now: Duration, settings: &ChatSettings) -> bool {
+        let window = Duration::from_secs_f32(settings.window_seconds);
+        let sent = self.sent.entry(client_id).or_default();
+
+        while sent.front().is_some_and(|time| now.saturating_sub(*time) >= window) {
+            sent.pop_front();
+        }
+        if sent.len() >= settings.burst as usize {
+            return false;
+        }
+
+        sent.push_back(now);
+        true
+    }
+}
+
+fn relay_chat_messages(
+    time: Res<Time<Real>>,
+    settings: Res<ChatSettings>,
+    mut limits: ResMut<ChatRateLimits>,
+    mut connection: ResMut<ConnectionManager>,
+    mut messages: EventReader<MessageEvent<SendChatMessage>>,
+    match_query: Query<&MatchRoster>,
+) {
+    for event in messages.read() {
+        let sender = *event.context();
+        let request = event.message();
+
+        // control characters would break the chat box layout
+        let text: String = request.text.trim().chars().filter(|c| !c.is_control()).collect();
+        if text.is_empty() {
+            continue;
+        }
+        // rejected messages count too, otherwise floods of long messages would each get a notice
+        if !limits.allow(sender, time.elapsed(), &settings) {
+            send_notice(&mut connection, sender, "You are sending messages too quickly".to_string());
+            continue;
+        }
+        if text.chars().count() > settings.max_length {
+            send_notice(&mut connection, sender, format!("Messages are limited to {} characters", settings.max_length));
+            continue;
+        }
+
+        let Some(roster) = match_query.iter().find(|roster| roster.get(sender).is_some() || roster.is_spectating(sender)) else {
+            send_notice(&mut connection, sender, "Join a match to chat".to_string());
+            continue;
+        };
+        let spectating = roster.is_spectating(sender);
+
+        let recipients = match request.target {
+            ChatTarget::All => roster.human_players().into_iter().chain(roster.spectators.iter().copied()).collect(),
+            ChatTarget::Team if spectating => roster.spectators.clone(),
+            ChatTarget::Team => {
+                if roster.team_of(sender).is_none() {
+                    send_notice(&mut connection, sender, "There are no teams in this match".to_string());
+                    continue;
+                }
+                roster.teammates(sender)
+            },
+            ChatTarget::Whisper(id) => {
+                let target = roster
+                    .human_players()
+                    .into_iter()
+                    .chain(roster.spectators.iter().copied())
+                    .find(|client_id| client_id.to_bits() == id);
+                let Some(target) = target else {
+                    send_notice(&mut connection, sender, format!("There is no player {id} in your match"));
+                    continue;
+                };
+                // the sender sees its own whisper
+                if target == sender { vec![sender] } else { vec![target, sender] }
+            },
+        };
+
+        let mut message = ChatMessage {
+            from: Some(sender),
+            target: request.target,
+            emote: request.emote,
+            text,
+        };
+        if let Err(e) = connection.send_message_to_target::<ChatChannel, _>(&mut message, NetworkTarget::Only(recipients)) {
+            error!("Could not relay chat message from {sender:?}: {e:?}");
+        }
+    }
+}
+
+fn send_notice(connection: &mut ConnectionManager, client_id: ClientId, text: String) {
+    let mut message = ChatMessage {
+        from: None,
+        target: ChatTarget::Whisper(client_id.to_bits()),
+        emote: false,
+        text,
+    };
+    if let Err(e) = connection.send_message::<ChatChannel, _>(client_id, &mut message) {
+        error!("Could not send chat notice to {client_id:?}: {e:?}");
+    }
+}
+
+fn forget_disconnected_senders(
+    mut limits: ResMut<ChatRateLimits>,
+    mut events: EventReader<DisconnectEvent>,
+) {
+    for event in events.read() {
+        limits.sent.remove(&event.client_id);
+    }
+}
+
+/// Chat box showing the last messages, with a line to type in when open.
+/// Opened by the chat actions of the global `ActionState<PlayerActions>` kept by `BindingsPlugin`.
+pub struct ChatClientPlugin;
+
+impl Plugin for ChatClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChatInput>();
+        app.init_resource::<ChatLog>();
+
+        app.add_systems(Startup, init_chat_box);
+        // typing in the chat must not toggle or navigate the bindings menu
+        app.add_systems(PreUpdate, type_chat_message
+            .after(InputSystem)
+            .before(navigate_bindings_menu)
+            .before(InputManagerSystem::Update)
+        );
+        app.add_systems(Update, (
+            open_chat,
+            receive_chat_messages,
+            (
+                update_chat_log,
+                update_chat_input,
+            ),
+        ).chain());
+    }
+}
+
+/// Message being typed, the chat is open while `target` is set
+#[derive(Resource, Default)]
+struct ChatInput {
+    target: Option<ChatTarget>,
+    text: String,
+}
+
+#[derive(Resource, Default)]
+struct ChatLog {
+    lines: VecDeque<(String, Color)>,
+}
+
+impl ChatLog {
+    fn push(&mut self, line: String, color: Color) {
+        self.lines.push_back((line, color));
+        if self.lines.len() > MAX_LINES {
+            self.lines.pop_front();
+        }
+    }
+}
+
+#[derive(Component)]
+struct ChatLogText;
+
+#[derive(Component)]
+struct ChatInputText;
+
+fn init_chat_box(
+    mut commands: Commands,
+) {
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            left: Val::Px(16.),
+            bottom: Val::Px(160.),
+            width: Val::Px(420.),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(4.),
+            ..default()
+        },
+        ..default()
+    })
+    .with_children(|chat| {
+        chat.spawn((TextBundle::default(), ChatLogText));
+        chat.spawn((
+            TextBundle {
+                visibility: Visibility::Hidden,
+                background_color: Color::srgba(0., 0., 0., 0.6).into(),
+                ..default()
+            },
+            ChatInputText,
+        ));
+    });
+}
+
+fn open_chat(
+    actions: Res<ActionState<PlayerActions>>,
+    mut input: ResMut<ChatInput>,
+) {
+    if input.target.is_some() {
+        return;
+    }
+
+    if actions.just_pressed(&PlayerActions::Chat) {
+        input.target = Some(ChatTarget::All);
+    } else if actions.just_pressed(&PlayerActions::TeamChat) {
+        input.target = Some(ChatTarget::Team);
+    }
+}
+
+/// Takes over the keyboard while the chat is open
+fn type_chat_message(
+    mut keyboard: EventReader<KeyboardInput>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut mouse_buttons: ResMut<ButtonInput<MouseButton>>,
+    mut input: ResMut<ChatInput>,
+    mut log: ResMut<ChatLog>,
+    mut manager: ResMut<client::ConnectionManager>,
+) {
+    // read even while closed, otherwise the key opening the chat would be typed
+    let events: Vec<KeyboardInput> = keyboard.read().cloned().collect();
+    let Some(target) = input.target else {
+        return;
+    };
+
+    for event in events.iter().filter(|event| event.state == ButtonState::Pressed) {
+        match &event.logical_key {
+            Key::Enter => {
+                let text = std::mem::take(&mut input.text);
+                input.target = None;
+
+                match parse_chat_input(target, &text) {
+                    Ok(Some(mut message)) => {
+                        if let Err(e) = manager.send_message::<ChatChannel, _>(&mut message) {
+                            error!("Could not send chat message: {e:?}");
+                        }
+                    },
+                    Ok(None) => {},
+                    Err(usage) => log.push(usage.to_string(), NOTICE_COLOR),
+                }
+                break;
+            },
+            Key::Escape => {
+                input.text.clear();
+                input.target = None;
+                break;
+            },
+            Key::Backspace => {
+                input.text.pop();
+            },
+            Key::Space => input.text.push(' '),
+            Key::Character(characters) => input.text.push_str(characters),
+            _ => {},
+        }
+    }
+
+    // typed keys must not move the player or trigger other bindings, and clicking doesn't attack
+    keys.reset_all();
+    mouse_buttons.reset_all();
+}
+
+/// `/me <action>` sends an emote and `/w <player> <message>` whispers to a player, by id
+fn parse_chat_input(target: ChatTarget, text: &str) -> Result<Option<SendChatMessage>, &'static str> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+
+    let (target, emote, text) = if let Some(action) = text.strip_prefix("/me ") {
+        (target, true, action)
+    } else if let Some(whisper) = text.strip_prefix("/w ") {
+        let usage = "Usage: /w <player id> <message>";
+        let (id, message) = whisper.trim_start().split_once(' ').ok_or(usage)?;
+        (ChatTarget::Whisper(id.parse().map_err(|_| usage)?), false, message)
+    } else {
+        (target, false, text)
+    };
+
+    Ok(Some(SendChatMessage {
+        target,
+        emote,
+        text: text.trim().to_string(),
+    }))
+}
+
+fn receive_chat_messages(
+    connection: Option<Res<ClientConnection>>,
+    mut log: ResMut<ChatLog>,
+    mut messages: EventReader<client::MessageEvent<ChatMessage>>,
+) {
+    let own_id = connection.map(|connection| connection.id());
+
+    for event in messages.read() {
+        let message = event.message();
+        let Some(from) = message.from else {
+            log.push(message.text.clone(), NOTICE_COLOR);
+            continue;
+        };
+
+        let name = display_name(from);
+        let (line, color) = match message.target {
+            _ if message.emote => (format!("* {name} {}", message.text), EMOTE_COLOR),
+            ChatTarget::All => (format!("{name}: {}", message.text), TEXT_COLOR),
+            ChatTarget::Team => (format!("[team] {name}: {}", message.text), TEAM_COLOR),
+            ChatTarget::Whisper(to) if Some(from) == own_id => (format!("[to Player {to}] {}", message.text), WHISPER_COLOR),
+            ChatTarget::Whisper(_) => (format!("[whisper] {name}: {}", message.text), WHISPER_COLOR),
+        };
+        log.push(line, color);
+    }
+}
+
+fn update_chat_log(
+    log: Res<ChatLog>,
+    mut text_query: Query<&mut Text, With<ChatLogText>>,
+) {
+    if !log.is_changed() {
+        return;
+    }
+    let Ok(mut text) = text_query.get_single_mut() else {
+        return;
+    };
+
+    text.sections = log
+        .lines
+        .iter()
+        .map(|(line, color)| TextSection::new(
+            format!("{line}\n"),
+            TextStyle {
+                font_size: 18.,
+                color: *color,
+                ..default()
+            },
+        ))
+        .collect();
+}
+
+fn update_chat_input(
+    input: Res<ChatInput>,
+    mut text_query: Query<(&mut Text, &mut Visibility), With<ChatInputText>>,
+) {
+    if !input.is_changed() {
+        return;
+    }
+    let Ok((mut text, mut visibility)) = text_query.get_single_mut() else {
+        return;
+    };
+
+    let Some(target) = input.target else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+
+    let prefix = match target {
+        ChatTarget::All => "[all]",
+        ChatTarget::Team => "[team]",
+        ChatTarget::Whisper(_) => "[whisper]",
+    };
+    *visibility = Visibility::Inherited;
+    *text = Text::from_section(
+        format!("{prefix} {}_", input.text),
+        TextStyle {
+            font_size: 18.,
+            color: TEXT_COLOR,
+            ..default()
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> ChatSettings {
+        ChatSettings {
+            max_length: 200,
+            burst: 2,
+            window_seconds: 5.,
+        }
+    }
+
+    fn message(target: ChatTarget, emote: bool, text: &str) -> Option<SendChatMessage> {
+        Some(SendChatMessage {
+            target,
+            emote,
+            text: text.to_string(),
+        })
+    }
+
+    #[test]
+    fn rate_limit_allows_bursts_within_the_window() {
+        let settings = settings();
+        let mut limits = ChatRateLimits::default();
+        let alice = ClientId::Netcode(1);
+        let bob = ClientId::Netcode(2);
+
+        assert!(limits.allow(alice, Duration::from_secs(0), &settings));
+        assert!(limits.allow(alice, Duration::from_secs(1), &settings));
+        assert!(!limits.allow(alice, Duration::from_secs(2), &settings));
+        // every client has its own budget
+        assert!(limits.allow(bob, Duration::from_secs(2), &settings));
+
+        // the first message leaves the window, the rejected one was never counted
+        assert!(limits.allow(alice, Duration::from_secs(5), &settings));
+        assert!(!limits.allow(alice, Duration::from_secs(5), &settings));
+        assert!(limits.allow(alice, Duration::from_secs(6), &settings));
+    }
+
+    #[test]
+    fn plain_messages_keep_the_open_target() {
+        assert_eq!(parse_chat_input(ChatTarget::Team, "  gg  "), Ok(message(ChatTarget::Team, false, "gg")));
+        assert_eq!(parse_chat_input(ChatTarget::All, "   "), Ok(None));
+        assert_eq!(parse_chat_input(ChatTarget::All, "/mean"), Ok(message(ChatTarget::All, false, "/mean")));
+    }
+
+    #[test]
+    fn commands_are_parsed() {
+        assert_eq!(parse_chat_input(ChatTarget::Team, "/me waves"), Ok(message(ChatTarget::Team, true, "waves")));
+        assert_eq!(parse_chat_input(ChatTarget::All, "/w 42 hello there"), Ok(message(ChatTarget::Whisper(42), false, "hello there")));
+        assert_eq!(parse_chat_input(ChatTarget::All, "/w   42 hi"), Ok(message(ChatTarget::Whisper(42), false, "hi")));
+    }
+
+    #[test]
+    fn malformed_whispers_return_the_usage() {
+        assert!(parse_chat_input(ChatTarget::All, "/w 42").is_err());
+        assert!(parse_chat_input(ChatTarget::All, "/w alice hi").is_err());
+        assert!(parse_chat_input(ChatTarget::All, "/w -1 hi").is_err());
+    }
+}
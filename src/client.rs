@@ -1,21 +1,39 @@
-use std::time::Duration;
+use std::{path::PathBuf, time::Duration};
 
+use avian3d::prelude::Position;
 use bevy::prelude::*;
-use bevy_inspector_egui::quick::FilterQueryInspectorPlugin;
-use leafwing_input_manager::prelude::{ActionState, InputMap, KeyboardVirtualDPad, WithDualAxisProcessingPipelineExt};
-use lightyear::{prelude::{client::{ClientCommands, Confirmed, Interpolated, Predicted, PredictionSet, Replicate}, MainSet}, shared::replication::components::Controlled};
+use leafwing_input_manager::{prelude::ActionState, InputManagerBundle};
+use lightyear::{prelude::{client::{ClientCommands, Confirmed, Interpolated, Predicted, PredictionDespawnCommandsExt, PredictionSet, Replicate, Rollback}, HasAuthority, MainSet, PreSpawnedPlayerObject, TickManager}, shared::replication::components::Controlled};
 use lightyear::client::events::*;
 
-use crate::{ability_framework::{ability_map::AbilityMap, pools::{life::LifePool, mana::ManaPool}, AbilityCharge, AbilityFrameworkClientPlugin, AbilityState, PredictedAbility, TriggerAbility}, physics::{CharacterQuery, PhysicsBundle}, player::{shared_player_movement, CursorBundle, CursorPosition, MoveSpeed, PlayerActions, PlayerBundle, PlayerId}, shared::FixedSet};
+use crate::{abilities::definition::{AbilityEffect, AbilityEffects}, ability_framework::{pipeline::{AbilityFailed, AbilityFailedMessage, AbilityPipelinePlugin, AbilityPipelineSet}, status_effect::StatusEffects, AbilityFrameworkClientPlugin, PredictedAbility, TriggerAbility}, bindings::{Bindings, BindingsPlugin}, chat::ChatClientPlugin, combat::Dead, lobby::{LobbyClientPlugin, MatchId}, physics::{CharacterQuery, PhysicsBundle}, player::{aim_direction, shared_player_movement, CursorBundle, CursorPosition, MoveSpeed, PlayerActions, PlayerId}, projectile::{projectile_hash, ProjectileBundle, ProjectileHit, ProjectileLifetime, SimulatedProjectile}, scoreboard::ScoreboardClientPlugin, settings::CameraSettings, shared::FixedSet, spectator::{Spectator, SpectatorClientPlugin}, team::{Team, TeamRules}};
 
-pub struct OverheatClientPlugin;
+pub struct OverheatClientPlugin {
+    pub preferred_match: Option<MatchId>,
+    /// Watch the match without a player
+    pub spectate: bool,
+    /// File the player's bindings are kept in, if any
+    pub bindings: Option<PathBuf>,
+    pub camera: CameraSettings,
+}
 
 impl Plugin for OverheatClientPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(FilterQueryInspectorPlugin::<With<Predicted>>::default());
-        app.add_plugins(FilterQueryInspectorPlugin::<With<Confirmed>>::default());
-
         app.add_plugins(AbilityFrameworkClientPlugin);
+        app.add_plugins(LobbyClientPlugin {
+            preferred_match: self.preferred_match,
+            spectate: self.spectate,
+        });
+        app.add_plugins(ChatClientPlugin);
+        app.add_plugins(BindingsPlugin {
+            path: self.bindings.clone(),
+        });
+        app.insert_resource(self.camera.clone());
+        app.add_plugins(ScoreboardClientPlugin);
+        if self.spectate {
+            app.add_plugins(SpectatorClientPlugin);
+        }
+        app.add_plugins(AbilityPipelinePlugin::<PlayerActions, (With<Predicted>, Without<Dead>)>::default());
         app.add_systems(Startup, init);
         app.add_systems(
             PreUpdate,
@@ -25,17 +43,20 @@ impl Plugin for OverheatClientPlugin {
         );
         app.add_systems(FixedUpdate, (
                 predicted_player_movement,
-                start_charging_predicted_abilities,
-                trigger_predicted_abilities,
+                spawn_predicted_projectiles
+                    .after(AbilityPipelineSet),
+                despawn_predicted_projectiles,
             )
             .in_set(FixedSet::Main)
         );
         app.add_systems(
             Update, (
-                finalize_remote_player_spawn,
+                finalize_player_spawn,
+                init_local_player,
                 handle_predicted_spawn,
                 handle_interpolated_spawn,
                 cursor_movement,
+                receive_ability_failures,
             )
         );
     }
@@ -50,7 +71,7 @@ fn init(
 fn handle_connection(
     mut commands: Commands,
     mut connection_event: EventReader<ConnectEvent>,
-    cam_query: Query<Entity, With<Camera3d>>,
+    spectator: Option<Res<Spectator>>,
 ) {
     for event in connection_event.read() {
         let client_id = event.client_id();
@@ -63,26 +84,10 @@ fn handle_connection(
             },
         ));
 
-        let player = commands.spawn((
-            PlayerBundle::new(
-                client_id,
-                Vec2::new(0., 0.),
-                InputMap::new([
-                    (PlayerActions::Dodge, KeyCode::Space),
-                ])
-                .with_multiple([
-                    (PlayerActions::PrimaryAttack, MouseButton::Left),
-                ])
-                .with_dual_axis(
-                    PlayerActions::Move, KeyboardVirtualDPad::WASD
-                        .inverted_y()
-                ),
-            ),
-        )).id();
-
-        let camera_entity = cam_query.single();
-        commands.entity(player).add_child(camera_entity);
-
+        // spectators have no player to aim with
+        if spectator.is_some() {
+            continue;
+        }
         commands.spawn(CursorBundle {
             position: CursorPosition(Vec3::ZERO),
             replicate: Replicate::default(),
@@ -90,20 +95,29 @@ fn handle_connection(
     }
 }
 
+/// The server spawns players when a round starts, the client adds its inputs to the one it controls
+fn init_local_player(
+    mut commands: Commands,
+    bindings: Res<Bindings>,
+    query: Query<Entity, (With<PlayerId>, With<Controlled>, Added<Predicted>)>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).insert(InputManagerBundle::<PlayerActions> {
+            action_state: ActionState::default(),
+            input_map: bindings.input_map(),
+        });
+    }
+}
+
 /// Blueprint pattern: when the player is replicated from the server, it will only contain
 /// the components which are always replicated. We need to add a few components that we don't
 /// need to be replicated all the time, for example the physics data which is constant and
 /// shouldn't be constantly replicated.
-fn finalize_remote_player_spawn(
+fn finalize_player_spawn(
     mut commands: Commands,
-    mut query: Query<(Entity, Has<Controlled>), (With<PlayerId>, Or<(Added<Interpolated>, Added<Predicted>)>)>,
+    query: Query<Entity, (With<PlayerId>, Or<(Added<Interpolated>, Added<Predicted>)>)>,
 ) {
-    for (entity, locally_controlled) in query.iter_mut() {
-        //only need to do this for remote players:
-        if locally_controlled {
-            continue;
-        }
-
+    for entity in query.iter() {
         commands.entity(entity).insert(SpatialBundle::default());
 
         commands.entity(entity).insert(PhysicsBundle::player());
@@ -112,10 +126,10 @@ fn finalize_remote_player_spawn(
 
 fn predicted_player_movement(
     time: Res<Time>,
-    mut query: Query<(CharacterQuery, &MoveSpeed, &ActionState<PlayerActions>), With<Predicted>>,
+    mut query: Query<(CharacterQuery, &MoveSpeed, &ActionState<PlayerActions>, &StatusEffects), (With<Predicted>, Without<Dead>)>,
 ) {
-    for (mut character, move_speed, action_state) in &mut query {
-        shared_player_movement(&time, move_speed, action_state, &mut character);
+    for (mut character, move_speed, action_state, status_effects) in &mut query {
+        shared_player_movement(&time, move_speed, status_effects, action_state, &mut character);
     }
 }
 
@@ -136,15 +150,39 @@ fn handle_interpolated_spawn(
 
 }
 
+/// The cursor follows the mouse, or the right stick once it is used. Aiming with the stick keeps the
+/// cursor at the same distance from the player until the mouse moves again.
 fn cursor_movement(
+    mut mouse_motion: EventReader<CursorMoved>,
+    mut stick_aim: Local<Option<Vec3>>,
+    bindings: Res<Bindings>,
     window_query: Query<&Window>,
     camera_query: Query<(&GlobalTransform, &Camera)>,
-    mut cusor_query: Query<&mut CursorPosition, With<Controlled>>,
+    player_query: Query<(&Position, &ActionState<PlayerActions>), (With<Predicted>, With<Controlled>)>,
+    mut cusor_query: Query<&mut CursorPosition, With<HasAuthority>>,
 ) {
+    if mouse_motion.read().count() > 0 {
+        *stick_aim = None;
+    }
+
+    if let Ok((position, action_state)) = player_query.get_single() {
+        let aim = action_state.axis_pair(&PlayerActions::Aim);
+        if aim != Vec2::ZERO {
+            *stick_aim = Some(aim_direction(aim));
+        }
+
+        if let Some(direction) = *stick_aim {
+            for mut cursor_pos in cusor_query.iter_mut() {
+                cursor_pos.set_if_neq(CursorPosition(position.0.with_y(0.) + direction * bindings.aim_distance));
+            }
+            return;
+        }
+    }
+
     if let Ok(window) = window_query.get_single() {
         if let Some(cursor_pos) = window.cursor_position() {
             if let Ok((cam_transform, cam)) = camera_query.get_single() {
-                if let Some(ray) = cam.viewport_to_world(&cam_transform, cursor_pos) {
+                if let Some(ray) = cam.viewport_to_world(cam_transform, cursor_pos) {
                     let t = ray.intersect_plane(Vec3::splat(0.), InfinitePlane3d::new(Vec3::new(0.0, 1., 0.)));
 
                     if let Some(t) = t {
@@ -158,44 +196,89 @@ fn cursor_movement(
     }
 }
 
-fn start_charging_predicted_abilities(
-    action_query: Query<(&ActionState<PlayerActions>, &AbilityMap<PlayerActions>, &LifePool, &ManaPool), With<Predicted>>,
-    mut ability_query: Query<(AbilityState, &mut AbilityCharge), (With<PredictedAbility>, With<Predicted>)>,
+/// Pre-spawns the locally controlled player's projectiles so they appear without waiting for the server.
+/// Remote players' projectiles are received through replication.
+fn spawn_predicted_projectiles(
+    mut commands: Commands,
+    tick_manager: Res<TickManager>,
+    rollback: Res<Rollback>,
+    mut triggers: EventReader<TriggerAbility>,
+    ability_query: Query<&AbilityEffects, (With<PredictedAbility>, With<Predicted>)>,
+    player_query: Query<(&PlayerId, &Position, Option<&Team>), (With<Predicted>, With<Controlled>)>,
+    cursor_query: Query<&CursorPosition, With<HasAuthority>>,
+    rules_query: Query<&TeamRules>,
 ) {
-    for (actions, map, life, mana) in action_query.iter() {
-        for pressed in actions.get_just_pressed() {
-            if let Ok(ability_entity) = map.mapped(pressed) {
-                if let Ok((ability_state, mut charge)) = ability_query.get_mut(ability_entity) {
-                    if ability_state.ready(mana, life).is_ok() {
-                        charge.start();
-                    }
-                }
-            }
+    // clients only receive the match they joined
+    let friendly_fire = rules_query.get_single().is_ok_and(|rules| rules.friendly_fire);
+
+    // abilities are re-triggered while re-simulating, use the tick being rolled back to so the hash still matches
+    let tick = tick_manager.tick_or_rollback_tick(&rollback);
+
+    for trigger in triggers.read() {
+        let Ok(effects) = ability_query.get(trigger.ability) else {
+            continue;
+        };
+        let Ok((player_id, position, team)) = player_query.get(trigger.source) else {
+            continue;
+        };
+        let Ok(cursor) = cursor_query.get_single() else {
+            continue;
+        };
+
+        for (slot, effect) in effects.iter().enumerate() {
+            let AbilityEffect::Projectile { speed, radius, damage, lifetime, on_hit, targets } = effect else {
+                continue;
+            };
+
+            commands.spawn((
+                ProjectileBundle::new(player_id.0, position.0, cursor.0, *speed, *radius, *damage, Duration::from_secs_f32(*lifetime))
+                    .with_on_hit(on_hit.clone())
+                    .with_targets(team.copied(), targets.with_friendly_fire(friendly_fire)),
+                PreSpawnedPlayerObject::new(projectile_hash(player_id.0, tick, slot)),
+            ));
         }
     }
 }
 
-fn trigger_predicted_abilities(
-    mut action_query: Query<(Entity, &ActionState<PlayerActions>, &AbilityMap<PlayerActions>, &mut LifePool, &mut ManaPool), With<Predicted>>,
-    mut triggers: EventWriter<TriggerAbility>,
-    mut ability_query: Query<AbilityState, (With<PredictedAbility>, With<Predicted>)>,
+fn despawn_predicted_projectiles(
+    mut commands: Commands,
+    mut hits: EventReader<ProjectileHit>,
+    query: Query<(Entity, &ProjectileLifetime, Has<Predicted>), SimulatedProjectile>,
 ) {
-    for (entity, actions, map, mut life, mut mana) in action_query.iter_mut() {
-        for pressed in actions.get_just_released() {
-            if let Ok(ability_entity) = map.mapped(pressed) {
-                if let Ok(mut ability) = ability_query.get_mut(ability_entity) {
-
-                    match ability.trigger(&mut mana, &mut life) {
-                        Ok(()) => {
-                            triggers.send(TriggerAbility {
-                                source: entity,
-                                ability: ability_entity,
-                            });
-                        },
-                        Err(_) => {},
-                    }
-                }
+    let hit_projectiles: Vec<Entity> = hits.read().map(|hit| hit.projectile).collect();
+
+    for (entity, lifetime, predicted) in query.iter() {
+        if lifetime.0.is_zero() || hit_projectiles.contains(&entity) {
+            if predicted {
+                // keep the entity around so the despawn can be undone if a rollback disagrees
+                commands.entity(entity).prediction_despawn();
+            } else {
+                // pre-spawned projectile that hasn't been matched with the server's one yet
+                commands.entity(entity).despawn_recursive();
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Surfaces the server's rejections as `AbilityFailed` events on the predicted entities, next to the locally predicted ones
+fn receive_ability_failures(
+    mut messages: EventReader<MessageEvent<AbilityFailedMessage>>,
+    mut failures: EventWriter<AbilityFailed>,
+    confirmed_query: Query<&Confirmed>,
+) {
+    for event in messages.read() {
+        let message = event.message();
+        let predicted = |entity: Entity| {
+            confirmed_query.get(entity).ok()
+                .and_then(|confirmed| confirmed.predicted)
+                .unwrap_or(entity)
+        };
+
+        failures.send(AbilityFailed {
+            source: predicted(message.source),
+            ability: predicted(message.ability),
+            reason: message.reason,
+            authoritative: true,
+        });
+    }
+}
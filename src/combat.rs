@@ -0,0 +1,208 @@
+use std::time::Duration;
+
+use avian3d::prelude::{AngularVelocity, LinearVelocity, Position};
+use bevy::{ecs::query::QueryData, prelude::*};
+use lightyear::prelude::ClientId;
+use serde::{Deserialize, Serialize};
+
+use crate::{ability_framework::{pool::Pool, pools::{heat::HeatPool, life::{Life, LifePool}, mana::ManaPool}, status_effect::{StatusEffectSet, StatusEffects, StatusModifier}}, player::PlayerId, projectile::ProjectileHit, shared::FixedSet};
+
+pub const RESPAWN_TIME: Duration = Duration::from_secs(3);
+
+pub struct CombatServerPlugin;
+
+impl Plugin for CombatServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DamageEvent>()
+            .init_resource::<SpawnPoints>()
+            .add_systems(FixedUpdate, (
+                    projectile_damage,
+                    // before the last tick of an effect expires
+                    damage_over_time
+                        .before(StatusEffectSet),
+                    apply_damage,
+                    kill_players,
+                    respawn_players,
+                )
+                .chain()
+                .in_set(FixedSet::Main)
+            );
+    }
+}
+
+/// Marker for players whose life reached `LifePool::MIN`. Dead players can't move or use abilities until they respawn.
+#[derive(Component, Serialize, Deserialize, PartialEq, Clone, Reflect)]
+pub struct Dead;
+
+/// Server-side countdown until a dead player respawns
+#[derive(Component)]
+pub struct RespawnTimer(pub Timer);
+
+#[derive(Event)]
+pub struct DamageEvent {
+    pub target: Entity,
+    /// Client responsible for the damage, if any
+    pub source: Option<ClientId>,
+    pub amount: Life,
+    /// Already taken from the target's `LifePool` by its status effects, the event only credits the source
+    pub over_time: bool,
+}
+
+/// Locations where players are (re)spawned, used in round-robin order
+#[derive(Resource)]
+pub struct SpawnPoints {
+    pub points: Vec<Vec3>,
+    next: usize,
+}
+
+impl SpawnPoints {
+    pub fn new(points: Vec<Vec3>) -> Self {
+        Self {
+            points,
+            next: 0,
+        }
+    }
+
+    pub fn next(&mut self) -> Vec3 {
+        if self.points.is_empty() {
+            return Vec3::ZERO;
+        }
+
+        let point = self.points[self.next % self.points.len()];
+        self.next = (self.next + 1) % self.points.len();
+        point
+    }
+}
+
+impl Default for SpawnPoints {
+    fn default() -> Self {
+        Self::new(vec![
+            Vec3::new(-5., 0., -5.),
+            Vec3::new(5., 0., -5.),
+            Vec3::new(5., 0., 5.),
+            Vec3::new(-5., 0., 5.),
+        ])
+    }
+}
+
+fn projectile_damage(
+    mut hits: EventReader<ProjectileHit>,
+    mut damage: EventWriter<DamageEvent>,
+    mut target_query: Query<Option<&mut StatusEffects>, (With<LifePool>, Without<Dead>)>,
+) {
+    for hit in hits.read() {
+        let Ok(status_effects) = target_query.get_mut(hit.target) else {
+            continue;
+        };
+
+        damage.send(DamageEvent {
+            target: hit.target,
+            source: Some(hit.owner),
+            amount: Life(hit.damage),
+            over_time: false,
+        });
+
+        if let Some(mut status_effects) = status_effects {
+            for effect in hit.on_hit.iter() {
+                status_effects.apply(effect, Some(hit.owner));
+            }
+        }
+    }
+}
+
+/// Negative `LifePerSecond` effects are applied to the life pool like any status effect, so that the
+/// owning client predicts them. This reports the damage of every tick, credited to the client who applied them.
+fn damage_over_time(
+    time: Res<Time>,
+    mut damage: EventWriter<DamageEvent>,
+    query: Query<(Entity, &StatusEffects), (With<LifePool>, Without<Dead>)>,
+) {
+    let delta_seconds = time.delta_seconds();
+
+    for (entity, effects) in query.iter() {
+        for effect in effects.iter() {
+            let StatusModifier::LifePerSecond(rate) = effect.modifier else {
+                continue;
+            };
+            if rate >= 0. {
+                continue;
+            }
+
+            damage.send(DamageEvent {
+                target: entity,
+                source: effect.source,
+                amount: Life(-rate * effect.strength() * delta_seconds),
+                over_time: true,
+            });
+        }
+    }
+}
+
+fn apply_damage(
+    mut events: EventReader<DamageEvent>,
+    mut query: Query<&mut LifePool, Without<Dead>>,
+) {
+    for event in events.read().filter(|event| !event.over_time) {
+        if let Ok(mut life) = query.get_mut(event.target) {
+            life.deplete(event.amount);
+        }
+    }
+}
+
+fn kill_players(
+    mut commands: Commands,
+    mut query: Query<(Entity, &PlayerId, &LifePool, &mut LinearVelocity), (Changed<LifePool>, Without<Dead>)>,
+) {
+    for (entity, player_id, life, mut velocity) in query.iter_mut() {
+        if !life.is_depleted() {
+            continue;
+        }
+
+        info!("Player {:?} died", player_id.0);
+        velocity.0 = Vec3::ZERO;
+        commands.entity(entity).insert((
+            Dead,
+            RespawnTimer(Timer::new(RESPAWN_TIME, TimerMode::Once)),
+        ));
+    }
+}
+
+#[derive(QueryData)]
+#[query_data(mutable)]
+struct RespawnQuery {
+    entity: Entity,
+    player_id: &'static PlayerId,
+    timer: &'static mut RespawnTimer,
+    position: &'static mut Position,
+    linear_velocity: &'static mut LinearVelocity,
+    angular_velocity: &'static mut AngularVelocity,
+    life: &'static mut LifePool,
+    mana: &'static mut ManaPool,
+    heat: &'static mut HeatPool,
+}
+
+fn respawn_players(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut spawn_points: ResMut<SpawnPoints>,
+    mut query: Query<RespawnQuery, With<Dead>>,
+) {
+    for mut player in query.iter_mut() {
+        player.timer.0.tick(time.delta());
+        if !player.timer.0.finished() {
+            continue;
+        }
+
+        info!("Respawning player {:?}", player.player_id.0);
+        player.position.0 = spawn_points.next();
+        player.linear_velocity.0 = Vec3::ZERO;
+        player.angular_velocity.0 = Vec3::ZERO;
+        let max_life = player.life.max();
+        player.life.set_current(max_life);
+        let max_mana = player.mana.max();
+        player.mana.set_current(max_mana);
+        player.heat.reset();
+
+        commands.entity(player.entity).remove::<(Dead, RespawnTimer)>();
+    }
+}
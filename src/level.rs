@@ -0,0 +1,217 @@
+use avian3d::prelude::{Collider, Position, RigidBody};
+use bevy::{asset::{io::Reader, ron, AssetLoader, AsyncReadExt, LoadContext}, prelude::*};
+use derive_more::derive::{Display, Error, From};
+use serde::Deserialize;
+
+use crate::{assets::LevelAssets, combat::SpawnPoints, shared::GameState};
+
+/// Spawns the level on both the server and the client. Only physics and gameplay data is spawned here,
+/// the render plugin attaches meshes and lights to the level entities when it is present.
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<LevelDefinition>()
+            .init_asset_loader::<LevelDefinitionLoader>();
+
+        app.add_systems(OnEnter(GameState::Game), spawn_level);
+        app.add_systems(Update, reload_level.run_if(in_state(GameState::Game)));
+    }
+}
+
+/// Static level description, loaded from `assets/levels/*.level.ron`.
+#[derive(Asset, TypePath, Debug, Deserialize)]
+pub struct LevelDefinition {
+    pub name: String,
+    /// Solid geometry, blocks players and projectiles
+    #[serde(default)]
+    pub geometry: Vec<LevelShape>,
+    /// Purely visual geometry without colliders
+    #[serde(default)]
+    pub props: Vec<LevelShape>,
+    #[serde(default)]
+    pub lights: Vec<LevelLight>,
+    /// Player (re)spawn locations, used in order
+    pub spawn_points: Vec<Vec3>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum Shape {
+    /// Horizontal plane, `size` is along the x and z axes
+    Plane { size: Vec2 },
+    Sphere { radius: f32 },
+    Cuboid { size: Vec3 },
+}
+
+impl Shape {
+    pub fn collider(&self) -> Collider {
+        match self {
+            // planes have no thickness, use a thin box so they still block movement
+            Shape::Plane { size } => Collider::cuboid(size.x, 0.01, size.y),
+            Shape::Sphere { radius } => Collider::sphere(*radius),
+            Shape::Cuboid { size } => Collider::cuboid(size.x, size.y, size.z),
+        }
+    }
+
+    pub fn mesh(&self) -> Mesh {
+        match self {
+            Shape::Plane { size } => Plane3d::default().mesh().size(size.x, size.y).build(),
+            Shape::Sphere { radius } => Sphere::new(*radius).into(),
+            Shape::Cuboid { size } => Cuboid::new(size.x, size.y, size.z).into(),
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, PartialEq, Deserialize)]
+pub struct LevelShape {
+    pub shape: Shape,
+    pub translation: Vec3,
+    /// Srgb color, only used for rendering
+    #[serde(default = "LevelShape::default_color")]
+    pub color: [f32; 3],
+}
+
+impl LevelShape {
+    fn default_color() -> [f32; 3] {
+        [1., 1., 1.]
+    }
+
+    /// Area covered on the ground, `x` and `y` of the rect are along the x and z axes
+    pub fn footprint(&self) -> Rect {
+        let half_size = match &self.shape {
+            Shape::Plane { size } => *size / 2.,
+            Shape::Sphere { radius } => Vec2::splat(*radius),
+            Shape::Cuboid { size } => size.xz() / 2.,
+        };
+        Rect::from_center_half_size(self.translation.xz(), half_size)
+    }
+}
+
+#[derive(Component, Debug, Clone, PartialEq, Deserialize)]
+pub struct LevelLight {
+    pub translation: Vec3,
+    pub intensity: f32,
+    #[serde(default = "LevelShape::default_color")]
+    pub color: [f32; 3],
+    #[serde(default)]
+    pub shadows: bool,
+}
+
+/// Marker for every entity spawned from the level definition, used to despawn it on reload
+#[derive(Component)]
+pub struct LevelEntity;
+
+#[derive(Default)]
+pub struct LevelDefinitionLoader;
+
+#[derive(Debug, Display, Error, From)]
+pub enum LevelDefinitionLoaderError {
+    #[display("could not read level definition: {_0}")]
+    Io(std::io::Error),
+    #[display("could not parse level definition: {_0}")]
+    Ron(ron::error::SpannedError),
+    #[display("level must have at least one spawn point")]
+    NoSpawnPoints,
+}
+
+impl AssetLoader for LevelDefinitionLoader {
+    type Asset = LevelDefinition;
+    type Settings = ();
+    type Error = LevelDefinitionLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<LevelDefinition, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        let definition = ron::de::from_bytes::<LevelDefinition>(&bytes)?;
+        if definition.spawn_points.is_empty() {
+            return Err(LevelDefinitionLoaderError::NoSpawnPoints);
+        }
+
+        Ok(definition)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["level.ron"]
+    }
+}
+
+fn spawn_level(
+    mut commands: Commands,
+    level_assets: Res<LevelAssets>,
+    definitions: Res<Assets<LevelDefinition>>,
+) {
+    let Some(level) = definitions.get(&level_assets.level) else {
+        error!("Level definition is not loaded");
+        return;
+    };
+
+    spawn_level_entities(&mut commands, level);
+}
+
+fn spawn_level_entities(commands: &mut Commands, level: &LevelDefinition) {
+    info!("Spawning level {}", level.name);
+
+    for geometry in &level.geometry {
+        commands.spawn((
+            geometry.clone(),
+            geometry.shape.collider(),
+            RigidBody::Static,
+            Position(geometry.translation),
+            SpatialBundle::from_transform(Transform::from_translation(geometry.translation)),
+            LevelEntity,
+            Name::from("LevelGeometry"),
+        ));
+    }
+
+    for prop in &level.props {
+        commands.spawn((
+            prop.clone(),
+            SpatialBundle::from_transform(Transform::from_translation(prop.translation)),
+            LevelEntity,
+            Name::from("LevelProp"),
+        ));
+    }
+
+    for light in &level.lights {
+        commands.spawn((
+            light.clone(),
+            SpatialBundle::from_transform(Transform::from_translation(light.translation)),
+            LevelEntity,
+            Name::from("LevelLight"),
+        ));
+    }
+
+    commands.insert_resource(SpawnPoints::new(level.spawn_points.clone()));
+}
+
+/// Respawns the whole level when its definition changes on disk
+fn reload_level(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<LevelDefinition>>,
+    level_assets: Res<LevelAssets>,
+    definitions: Res<Assets<LevelDefinition>>,
+    query: Query<Entity, With<LevelEntity>>,
+) {
+    for event in events.read() {
+        let AssetEvent::Modified { id } = event else {
+            continue;
+        };
+        if *id != level_assets.level.id() {
+            continue;
+        }
+        let Some(level) = definitions.get(*id) else {
+            continue;
+        };
+
+        for entity in query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        spawn_level_entities(&mut commands, level);
+    }
+}
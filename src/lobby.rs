@@ -0,0 +1,609 @@
+use std::time::Duration;
+
+use avian3d::prelude::{CollisionLayers, LayerMask};
+use bevy::prelude::*;
+use lightyear::prelude::{client::{self, ClientConnection, NetClient}, server::{ConnectionManager, Replicate, RoomId, RoomManager}, ClientId, NetworkTarget, ReplicationTarget};
+use lightyear::server::events::{DisconnectEvent, MessageEvent};
+use serde::{Deserialize, Serialize};
+
+use crate::{combat::SpawnPoints, protocol::LobbyChannel, server::PlayerSpawner, settings::LobbySettings, shared::GameState, team::{Team, TeamRules}};
+
+/// Identifies a match on the server, also used as the match's collision layer so that
+/// entities of different matches never interact.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+pub struct MatchId(pub u32);
+
+impl MatchId {
+    /// One collision layer per match
+    pub const MAX: u32 = 32;
+
+    pub fn collision_layers(&self) -> CollisionLayers {
+        let layer = LayerMask(1 << self.0);
+        CollisionLayers::new(layer, layer)
+    }
+}
+
+/// Lifecycle of a match, replicated to the clients who joined it.
+/// Clients wait in the lobby until enough of them are ready, then play a timed round.
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub enum MatchPhase {
+    Lobby,
+    Countdown { seconds_left: u32 },
+    InRound { seconds_left: u32 },
+    RoundOver { seconds_left: u32 },
+}
+
+/// Clients who joined the match, and whether they are ready for the next round
+#[derive(Component, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Reflect)]
+pub struct MatchRoster {
+    pub players: Vec<RosterEntry>,
+    /// Clients watching the match, they don't take a player slot
+    pub spectators: Vec<ClientId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
+pub struct RosterEntry {
+    pub client_id: ClientId,
+    pub ready: bool,
+    /// Whether the client has a player in the current round
+    pub playing: bool,
+    /// Server-controlled player, always ready
+    pub bot: bool,
+    /// Assigned when joining, `None` when the match has no teams
+    pub team: Option<Team>,
+    /// Disconnected during the round, its player waits for the client to reconnect
+    pub parked: bool,
+}
+
+impl RosterEntry {
+    pub fn new(client_id: ClientId) -> Self {
+        Self {
+            client_id,
+            ready: false,
+            playing: false,
+            bot: false,
+            team: None,
+            parked: false,
+        }
+    }
+
+    pub fn bot(client_id: ClientId) -> Self {
+        Self {
+            client_id,
+            ready: true,
+            playing: false,
+            bot: true,
+            team: None,
+            parked: false,
+        }
+    }
+}
+
+impl MatchRoster {
+    pub fn get(&self, client_id: ClientId) -> Option<&RosterEntry> {
+        self.players.iter().find(|entry| entry.client_id == client_id)
+    }
+
+    pub fn get_mut(&mut self, client_id: ClientId) -> Option<&mut RosterEntry> {
+        self.players.iter_mut().find(|entry| entry.client_id == client_id)
+    }
+
+    pub fn ready_count(&self) -> usize {
+        self.players.iter().filter(|entry| entry.ready).count()
+    }
+
+    pub fn playing_count(&self) -> usize {
+        self.players.iter().filter(|entry| entry.playing).count()
+    }
+
+    pub fn human_players(&self) -> Vec<ClientId> {
+        self.players.iter().filter(|entry| !entry.bot).map(|entry| entry.client_id).collect()
+    }
+
+    pub fn is_spectating(&self, client_id: ClientId) -> bool {
+        self.spectators.contains(&client_id)
+    }
+
+    /// Adds a player to the team with the fewest members
+    pub fn join(&mut self, mut entry: RosterEntry, rules: &TeamRules) {
+        entry.team = rules.balanced_team(self.players.iter().map(|entry| entry.team));
+        self.players.push(entry);
+    }
+
+    pub fn team_of(&self, client_id: ClientId) -> Option<Team> {
+        self.get(client_id).and_then(|entry| entry.team)
+    }
+
+    /// Human players on the same team as `client_id`, including itself
+    pub fn teammates(&self, client_id: ClientId) -> Vec<ClientId> {
+        let team = self.team_of(client_id);
+        self.players
+            .iter()
+            .filter(|entry| !entry.bot && (entry.client_id == client_id || Team::allied(team, entry.team)))
+            .map(|entry| entry.client_id)
+            .collect()
+    }
+
+    /// Everyone in the lobby is ready and there are enough players to start
+    pub fn can_start(&self, min_players: usize) -> bool {
+        self.players.len() >= min_players && self.ready_count() == self.players.len()
+    }
+}
+
+/// Sent by a client once connected to be assigned to a match, `None` joins any match that has room
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JoinMatchMessage {
+    pub match_id: Option<MatchId>,
+}
+
+/// Sent by a spectator once connected to watch a match, `None` watches the first open match
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SpectateMessage {
+    pub match_id: Option<MatchId>,
+}
+
+/// Sent back when a client couldn't be assigned to any match
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JoinDeniedMessage {
+    pub reason: String,
+}
+
+/// Sent by a client to ready up, or to cancel a countdown by un-readying
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReadyMessage {
+    pub ready: bool,
+}
+
+/// Server-side link from an in-round entity to its match. The entity is only replicated to the match's
+/// room and is despawned when the round ends.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InMatch(pub Entity);
+
+impl InMatch {
+    pub fn room(&self) -> RoomId {
+        RoomId::from(self.0)
+    }
+}
+
+/// Components scoping an in-round entity to its match
+#[derive(Bundle, Clone)]
+pub struct MatchScope {
+    pub in_match: InMatch,
+    pub layers: CollisionLayers,
+}
+
+impl MatchScope {
+    pub fn new(match_entity: Entity, match_id: MatchId) -> Self {
+        Self {
+            in_match: InMatch(match_entity),
+            layers: match_id.collision_layers(),
+        }
+    }
+}
+
+/// Server-side timer of the current phase
+#[derive(Component)]
+struct PhaseTimer(Timer);
+
+impl PhaseTimer {
+    fn new(seconds: u32) -> Self {
+        Self(Timer::new(Duration::from_secs(seconds as u64), TimerMode::Once))
+    }
+
+    fn seconds_left(&self) -> u32 {
+        self.0.remaining_secs().ceil() as u32
+    }
+}
+
+/// Server systems managing rosters and match phases
+#[derive(SystemSet, Hash, PartialEq, Eq, Clone, Copy, Debug)]
+pub struct LobbySet;
+
+pub struct LobbyServerPlugin {
+    pub settings: LobbySettings,
+}
+
+impl Plugin for LobbyServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.settings.clone());
+
+        app.add_systems(Update, (
+                receive_join_requests,
+                receive_spectate_requests,
+                leave_match,
+                receive_ready,
+                advance_match_phase,
+                add_match_entities_to_rooms,
+                update_match_replication_targets,
+            )
+            .chain()
+            .in_set(LobbySet)
+            .run_if(in_state(GameState::Game))
+        );
+    }
+}
+
+pub struct LobbyClientPlugin {
+    /// Match requested when connecting, any match with room is joined otherwise
+    pub preferred_match: Option<MatchId>,
+    /// Watch the match instead of playing in it
+    pub spectate: bool,
+}
+
+impl Plugin for LobbyClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(MatchRequest {
+            preferred_match: self.preferred_match,
+            spectate: self.spectate,
+        });
+
+        app.add_systems(Update, (
+            request_match,
+            receive_join_denied,
+            toggle_ready,
+        ));
+    }
+}
+
+#[derive(Resource)]
+struct MatchRequest {
+    preferred_match: Option<MatchId>,
+    spectate: bool,
+}
+
+/// Clients can only join a match before its round starts
+fn is_joinable(phase: &MatchPhase) -> bool {
+    matches!(phase, MatchPhase::Lobby | MatchPhase::Countdown { .. })
+}
+
+/// Opens a match in the lobby phase with the clients of `roster`
+pub fn spawn_match(commands: &mut Commands, match_id: MatchId, roster: MatchRoster, rules: TeamRules) -> Entity {
+    info!("Opening match {}", match_id.0);
+
+    commands.spawn((
+        match_id,
+        MatchPhase::Lobby,
+        roster,
+        rules,
+        PhaseTimer::new(0),
+        Replicate {
+            // updated with the roster so that only the match's own clients receive it
+            target: ReplicationTarget {
+                target: NetworkTarget::None,
+            },
+            ..default()
+        },
+        Name::from(format!("Match {}", match_id.0)),
+    )).id()
+}
+
+fn receive_join_requests(
+    mut commands: Commands,
+    mut messages: EventReader<MessageEvent<JoinMatchMessage>>,
+    mut connection: ResMut<ConnectionManager>,
+    mut room_manager: ResMut<RoomManager>,
+    settings: Res<LobbySettings>,
+    mut match_query: Query<(Entity, &MatchId, &MatchPhase, &mut MatchRoster, &TeamRules)>,
+) {
+    // matches opened this frame aren't in the query yet
+    let mut opened: Vec<(MatchId, MatchRoster)> = Vec::new();
+
+    for message in messages.read() {
+        let client_id = *message.context();
+        let requested = message.message().match_id;
+        let wanted = |id: &MatchId| requested.is_none_or(|requested| requested == *id);
+
+        let in_round = match_query
+            .iter()
+            .any(|(.., roster, _)| roster.get(client_id).is_some_and(|entry| entry.playing));
+        // a reconnecting client is already back in its round, it stays there
+        if in_round {
+            deny_join(&mut connection, client_id, "already playing a round".to_string());
+            continue;
+        }
+
+        // a client changing match leaves its previous one, or stops spectating
+        for (match_entity, .., mut roster, _) in match_query.iter_mut() {
+            if roster.get(client_id).is_some() {
+                roster.players.retain(|entry| entry.client_id != client_id);
+            }
+            if roster.is_spectating(client_id) {
+                stop_spectating(&mut roster, &mut room_manager, match_entity, client_id);
+            }
+        }
+
+        let entry = RosterEntry::new(client_id);
+
+        let existing = match_query
+            .iter_mut()
+            .find(|(_, id, phase, roster, _)| wanted(id) && is_joinable(phase) && roster.players.len() < settings.max_players_per_match);
+        if let Some((_, id, _, mut roster, rules)) = existing {
+            info!("Client {client_id:?} joined match {}", id.0);
+            roster.join(entry, rules);
+            continue;
+        }
+
+        let opened_match = opened
+            .iter_mut()
+            .find(|(id, roster)| wanted(id) && roster.players.len() < settings.max_players_per_match);
+        if let Some((id, roster)) = opened_match {
+            info!("Client {client_id:?} joined match {}", id.0);
+            roster.join(entry, &settings.team_rules(*id));
+            continue;
+        }
+
+        let in_use = |id: &MatchId| match_query.iter().any(|(_, existing, ..)| existing == id) || opened.iter().any(|(existing, _)| existing == id);
+        let free_id = match requested {
+            Some(id) if id.0 < settings.max_matches && !in_use(&id) => Some(id),
+            Some(_) => None,
+            None => (0..settings.max_matches).map(MatchId).find(|id| !in_use(id)),
+        };
+        let Some(free_id) = free_id else {
+            let reason = match requested {
+                Some(id) => format!("match {} is full or already playing", id.0),
+                None => "every match is full".to_string(),
+            };
+            deny_join(&mut connection, client_id, reason);
+            continue;
+        };
+
+        info!("Client {client_id:?} joined match {}", free_id.0);
+        let mut roster = MatchRoster::default();
+        roster.join(entry, &settings.team_rules(free_id));
+        opened.push((free_id, roster));
+    }
+
+    for (match_id, roster) in opened {
+        spawn_match(&mut commands, match_id, roster, settings.team_rules(match_id));
+    }
+}
+
+/// Spectators can watch a match in any phase. Without a spectator delay they are in the match's
+/// room and receive its round entities like players do, otherwise `SpectatorServerPlugin` feeds them.
+fn receive_spectate_requests(
+    mut messages: EventReader<MessageEvent<SpectateMessage>>,
+    mut connection: ResMut<ConnectionManager>,
+    mut room_manager: ResMut<RoomManager>,
+    settings: Res<LobbySettings>,
+    mut match_query: Query<(Entity, &MatchId, &mut MatchRoster)>,
+) {
+    for message in messages.read() {
+        let client_id = *message.context();
+        let requested = message.message().match_id;
+
+        if match_query.iter().any(|(.., roster)| roster.get(client_id).is_some()) {
+            deny_join(&mut connection, client_id, "players can't spectate".to_string());
+            continue;
+        }
+
+        for (match_entity, _, mut roster) in match_query.iter_mut() {
+            if roster.is_spectating(client_id) {
+                stop_spectating(&mut roster, &mut room_manager, match_entity, client_id);
+            }
+        }
+
+        let watched = match_query
+            .iter_mut()
+            .filter(|(_, id, _)| requested.is_none_or(|requested| requested == **id))
+            .min_by_key(|(_, id, _)| id.0);
+        let Some((match_entity, id, mut roster)) = watched else {
+            let reason = match requested {
+                Some(id) => format!("match {} is not open", id.0),
+                None => "no match is open".to_string(),
+            };
+            deny_join(&mut connection, client_id, reason);
+            continue;
+        };
+
+        info!("Client {client_id:?} is spectating match {}", id.0);
+        roster.spectators.push(client_id);
+        if settings.spectator_delay_ms == 0 {
+            room_manager.add_client(client_id, RoomId::from(match_entity));
+        }
+    }
+}
+
+fn stop_spectating(roster: &mut MatchRoster, room_manager: &mut RoomManager, match_entity: Entity, client_id: ClientId) {
+    roster.spectators.retain(|spectator| *spectator != client_id);
+    room_manager.remove_client(client_id, RoomId::from(match_entity));
+}
+
+fn deny_join(connection: &mut ConnectionManager, client_id: ClientId, reason: String) {
+    warn!("Client {client_id:?} could not join a match: {reason}");
+
+    if let Err(e) = connection.send_message::<LobbyChannel, _>(client_id, &mut JoinDeniedMessage { reason }) {
+        error!("Could not send join denial to {client_id:?}: {e:?}");
+    }
+}
+
+fn leave_match(
+    mut events: EventReader<DisconnectEvent>,
+    mut match_query: Query<&mut MatchRoster>,
+) {
+    for event in events.read() {
+        for mut roster in match_query.iter_mut() {
+            // parked players keep their slot until the end of the grace window
+            if roster.get(event.client_id).is_some_and(|entry| !entry.parked) {
+                roster.players.retain(|entry| entry.client_id != event.client_id);
+            }
+            // rooms forget disconnected clients on their own
+            if roster.is_spectating(event.client_id) {
+                roster.spectators.retain(|spectator| *spectator != event.client_id);
+            }
+        }
+    }
+}
+
+fn receive_ready(
+    mut messages: EventReader<MessageEvent<ReadyMessage>>,
+    mut match_query: Query<(&MatchPhase, &mut MatchRoster)>,
+) {
+    for message in messages.read() {
+        let client_id = *message.context();
+
+        for (phase, mut roster) in match_query.iter_mut() {
+            if !is_joinable(phase) {
+                continue;
+            }
+            if let Some(entry) = roster.get_mut(client_id) {
+                entry.ready = message.message().ready;
+            }
+        }
+    }
+}
+
+fn advance_match_phase(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<LobbySettings>,
+    mut spawn_points: ResMut<SpawnPoints>,
+    mut room_manager: ResMut<RoomManager>,
+    mut spawner: PlayerSpawner,
+    mut match_query: Query<(Entity, &MatchId, &mut MatchPhase, &mut MatchRoster, &mut PhaseTimer)>,
+    round_entities: Query<(Entity, &InMatch)>,
+) {
+    for (match_entity, &match_id, mut phase, mut roster, mut timer) in match_query.iter_mut() {
+        timer.0.tick(time.delta());
+        let room = RoomId::from(match_entity);
+
+        let next_phase = match *phase {
+            // frees the match id for new matches
+            MatchPhase::Lobby if roster.players.is_empty() => {
+                info!("Closing empty match {}", match_id.0);
+                commands.entity(match_entity).despawn();
+                continue;
+            },
+            MatchPhase::Lobby if roster.can_start(settings.min_players) => {
+                *timer = PhaseTimer::new(settings.countdown_seconds);
+                MatchPhase::Countdown { seconds_left: timer.seconds_left() }
+            },
+            MatchPhase::Lobby => continue,
+            MatchPhase::Countdown { .. } if !roster.can_start(settings.min_players) => {
+                info!("Match {} countdown cancelled", match_id.0);
+                MatchPhase::Lobby
+            },
+            MatchPhase::Countdown { .. } if timer.0.finished() => {
+                let players = roster.human_players();
+                for entry in roster.players.iter_mut() {
+                    let scope = MatchScope::new(match_entity, match_id);
+                    let player = if entry.bot {
+                        spawner.spawn_bot(entry.client_id, spawn_points.next(), scope)
+                    } else {
+                        room_manager.add_client(entry.client_id, room);
+                        spawner.spawn(entry.client_id, spawn_points.next(), scope, &players)
+                    };
+                    if let Some(team) = entry.team {
+                        commands.entity(player).insert(team);
+                    }
+                    entry.playing = true;
+                }
+
+                info!("Match {} started a round with {} players", match_id.0, roster.playing_count());
+                *timer = PhaseTimer::new(settings.round_seconds);
+                MatchPhase::InRound { seconds_left: timer.seconds_left() }
+            },
+            MatchPhase::InRound { .. } if timer.0.finished() || roster.playing_count() < settings.min_players => {
+                info!("Match {} round over", match_id.0);
+                *timer = PhaseTimer::new(settings.round_over_seconds);
+                MatchPhase::RoundOver { seconds_left: timer.seconds_left() }
+            },
+            MatchPhase::RoundOver { .. } if timer.0.finished() => {
+                for (entity, in_match) in round_entities.iter() {
+                    if in_match.0 == match_entity {
+                        commands.entity(entity).despawn_recursive();
+                    }
+                }
+                // their players are gone, reconnecting clients join again like new ones
+                roster.players.retain(|entry| !entry.parked);
+                for entry in roster.players.iter_mut() {
+                    if !entry.bot {
+                        room_manager.remove_client(entry.client_id, room);
+                    }
+                    entry.ready = entry.bot;
+                    entry.playing = false;
+                }
+
+                MatchPhase::Lobby
+            },
+            MatchPhase::Countdown { .. } => MatchPhase::Countdown { seconds_left: timer.seconds_left() },
+            MatchPhase::InRound { .. } => MatchPhase::InRound { seconds_left: timer.seconds_left() },
+            MatchPhase::RoundOver { .. } => MatchPhase::RoundOver { seconds_left: timer.seconds_left() },
+        };
+
+        // only replicate when the displayed value changes
+        phase.set_if_neq(next_phase);
+    }
+}
+
+fn add_match_entities_to_rooms(
+    mut room_manager: ResMut<RoomManager>,
+    query: Query<(Entity, &InMatch), Added<InMatch>>,
+) {
+    for (entity, in_match) in query.iter() {
+        room_manager.add_entity(entity, in_match.room());
+    }
+}
+
+/// Only the clients in a match, and its spectators, receive its phase and roster
+fn update_match_replication_targets(
+    mut query: Query<(&MatchRoster, &mut ReplicationTarget), Changed<MatchRoster>>,
+) {
+    for (roster, mut target) in query.iter_mut() {
+        let clients = roster.players.iter().map(|entry| entry.client_id).chain(roster.spectators.iter().copied());
+        target.target = NetworkTarget::Only(clients.collect());
+    }
+}
+
+fn request_match(
+    mut events: EventReader<client::ConnectEvent>,
+    mut manager: ResMut<client::ConnectionManager>,
+    request: Res<MatchRequest>,
+) {
+    for _ in events.read() {
+        let result = if request.spectate {
+            manager.send_message::<LobbyChannel, _>(&mut SpectateMessage {
+                match_id: request.preferred_match,
+            })
+        } else {
+            manager.send_message::<LobbyChannel, _>(&mut JoinMatchMessage {
+                match_id: request.preferred_match,
+            })
+        };
+        if let Err(e) = result {
+            error!("Could not send join request: {e:?}");
+        }
+    }
+}
+
+fn receive_join_denied(
+    mut messages: EventReader<client::MessageEvent<JoinDeniedMessage>>,
+) {
+    for message in messages.read() {
+        warn!("Could not join a match: {}", message.message().reason);
+    }
+}
+
+fn toggle_ready(
+    keys: Res<ButtonInput<KeyCode>>,
+    connection: Option<Res<ClientConnection>>,
+    mut manager: ResMut<client::ConnectionManager>,
+    match_query: Query<&MatchRoster>,
+) {
+    if !keys.just_pressed(KeyCode::KeyR) {
+        return;
+    }
+    let Some(connection) = connection else {
+        return;
+    };
+    let Some(entry) = match_query.iter().find_map(|roster| roster.get(connection.id())) else {
+        return;
+    };
+
+    let mut message = ReadyMessage {
+        ready: !entry.ready,
+    };
+    if let Err(e) = manager.send_message::<LobbyChannel, _>(&mut message) {
+        error!("Could not send ready message: {e:?}");
+    }
+}
+
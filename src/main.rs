@@ -1,12 +1,17 @@
+// systems take their parameters as arguments, with filters spelled out where they are used
+#![allow(clippy::type_complexity, clippy::too_many_arguments)]
+
 use std::time::Duration;
 
-use app::{Apps, Cli};
+use app::{run_auth_service, run_replay, Apps, Cli, Command};
 use client::OverheatClientPlugin;
+use lobby::MatchId;
 use server::OverheatServerPlugin;
-use settings::{read_settings, Settings};
+use settings::load_settings;
 use shared::OverheatSharedPlugin;
 
 mod settings;
+mod auth;
 mod app;
 mod client;
 mod server;
@@ -14,21 +19,67 @@ mod protocol;
 mod shared;
 mod player;
 mod physics;
+mod projectile;
 mod assets;
 mod rendering;
 mod animation;
+mod camera;
 mod ability_framework;
 mod abilities;
+mod combat;
+mod level;
+mod lobby;
+mod bots;
+mod replay;
+mod spectator;
+mod chat;
+mod team;
+mod scoreboard;
+mod validation;
+mod reconnect;
+mod bindings;
+
+#[cfg(test)]
+mod tests;
 
 pub const FIXED_TIMESTEP_HZ: f64 = 64.;
 pub const REPLICATION_INTERVAL: Duration = Duration::from_millis(100);
 
 fn main() {
     let cli = Cli::default();
-    let settings_str = include_str!("../assets/settings.ron");
-    let settings = read_settings::<Settings>(settings_str);
+    let settings = match load_settings(cli.settings.as_deref(), &cli.overrides) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    if cli.command == Command::Auth {
+        if let Err(e) = run_auth_service(&settings) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let mut apps = Apps::new(&settings, cli)
+    if let Command::Replay { path } = &cli.command {
+        if let Err(e) = run_replay(&settings, path) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let spectate = matches!(cli.command, Command::Spectate);
+    let apps = match Apps::new(&settings, cli.command) {
+        Ok(apps) => apps,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let mut apps = apps
         .with_server_replication_send_interval(Duration::from_millis(settings.server_replication_send_interval));
 
     apps
@@ -38,9 +89,20 @@ fn main() {
     })
     .add_lightyear_plugins()
     .add_plugins(
-        OverheatClientPlugin,
+        OverheatClientPlugin {
+            preferred_match: settings.client.preferred_match.map(MatchId),
+            spectate,
+            bindings: settings.client.bindings.clone(),
+            camera: settings.client.camera.clone(),
+        },
         OverheatServerPlugin {
             predict_all: settings.predict_all,
+            lobby: settings.lobby.clone(),
+            chat: settings.chat.clone(),
+            validation: settings.validation.clone(),
+            bots: settings.server.bots,
+            replays: settings.server.replays.clone(),
+            stats: settings.server.stats.clone(),
         },
         OverheatSharedPlugin
     );
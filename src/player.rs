@@ -1,11 +1,12 @@
-use std::ops::{Add, Mul};
+use std::{ops::{Add, Mul}, time::Duration};
 
+use avian3d::prelude::Position;
 use bevy::prelude::*;
-use leafwing_input_manager::{prelude::{ActionState, InputMap}, Actionlike, InputManagerBundle};
-use lightyear::prelude::{client, ClientId, PrePredicted, ReplicateHierarchy, ReplicationGroup};
+use leafwing_input_manager::{prelude::ActionState, Actionlike};
+use lightyear::prelude::{client, ClientId, ReplicationGroup};
 use serde::{Deserialize, Serialize};
 
-use crate::{ability_framework::pools::{life::{Life, LifePool}, mana::{Mana, ManaPool}}, physics::{CharacterQueryItem, PhysicsBundle}};
+use crate::{ability_framework::{pools::{heat::{Heat, HeatPool}, life::{Life, LifePool}, mana::{Mana, ManaPool}}, status_effect::StatusEffects}, physics::{CharacterQueryItem, PhysicsBundle}};
 
 
 pub const REPLICATION_GROUP: ReplicationGroup = ReplicationGroup::new_id(1);
@@ -16,6 +17,13 @@ pub enum PlayerActions {
     Move,
     Dodge,
     PrimaryAttack,
+    /// Gamepad aiming, places the cursor around the player
+    #[actionlike(DualAxis)]
+    Aim,
+    /// Starts typing a chat message to the whole match
+    Chat,
+    /// Starts typing a chat message to the team
+    TeamChat,
 }
 
 #[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq, Reflect)]
@@ -24,14 +32,14 @@ pub struct PlayerId(pub ClientId);
 #[derive(Component, Serialize, Deserialize, PartialEq, Reflect, Clone)]
 pub struct MoveSpeed(pub f32);
 
+/// Gameplay components of a player, spawned by the server when a round starts
 #[derive(Bundle)]
 pub struct PlayerBundle {
     id: PlayerId,
-    replicate: client::Replicate,
-    inputs: InputManagerBundle<PlayerActions>,
-    pre_predicted: PrePredicted,
+    action_state: ActionState<PlayerActions>,
     name: Name,
 
+    position: Position,
     spatial: SpatialBundle,
     physics: PhysicsBundle,
 
@@ -40,6 +48,8 @@ pub struct PlayerBundle {
 
     life: LifePool,
     mana: ManaPool,
+    heat: HeatPool,
+    status_effects: StatusEffects,
 }
 
 #[derive(Component, Serialize, Deserialize, PartialEq, Clone, Reflect)]
@@ -69,34 +79,44 @@ impl Mul<f32> for &CursorPosition {
 
 
 impl PlayerBundle {
-    pub fn new(id: ClientId, position: Vec2, input_map: InputMap<PlayerActions>) -> Self {
+    pub fn new(id: ClientId, position: Vec3) -> Self {
         Self {
             id: PlayerId(id),
-            replicate: client::Replicate {
-                group: REPLICATION_GROUP,
-                hierarchy: ReplicateHierarchy {
-                    recursive: false,
-                },
-                ..default()
-            },
-            inputs: InputManagerBundle::<PlayerActions> {
-                action_state: ActionState::default(),
-                input_map,
-            },
-            spatial: SpatialBundle::from_transform(Transform::from_xyz(position.x, 0., position.y)),
+            action_state: ActionState::default(),
+            position: Position(position),
+            spatial: SpatialBundle::from_transform(Transform::from_translation(position)),
             physics: PhysicsBundle::player(),
-            pre_predicted: PrePredicted::default(),
             move_speed: MoveSpeed(12.),
             name: Name::from("Player"),
             life: LifePool::new(Life(100.), Life(100.), Life(5.)),
             mana: ManaPool::new(Mana(100.), Mana(100.), Mana(5.)),
+            heat: HeatPool::new(Heat(0.), Heat(100.), Heat(20.), Duration::from_secs(2)),
+            status_effects: StatusEffects::default(),
         }
     }
 }
 
+/// Horizontal direction pointed at by the `Aim` axis, rotated like `Move` to match the camera
+pub fn aim_direction(aim: Vec2) -> Vec3 {
+    use std::f32::consts::PI;
+
+    let direction = Vec2::from_angle(-PI / 4.).rotate(aim.normalize_or_zero());
+    Vec3::new(direction.x, 0., direction.y)
+}
+
+/// `Move` axis that makes `shared_player_movement` head along the horizontal `direction`, used by bots
+pub fn move_input_towards(direction: Vec3) -> Vec2 {
+    use std::f32::consts::PI;
+
+    let mut input_dir = Vec2::from_angle(PI / 4.).rotate(Vec2::new(direction.x, direction.z));
+    input_dir.y /= 1.5;
+    input_dir.normalize_or_zero()
+}
+
 pub fn shared_player_movement(
     time: &Res<Time>,
     move_speed: &MoveSpeed,
+    status_effects: &StatusEffects,
     action: &ActionState<PlayerActions>,
     character: &mut CharacterQueryItem,
 ) {
@@ -113,7 +133,7 @@ pub fn shared_player_movement(
     let move_dir = Vec3::new(move_dir.x, 0., move_dir.y);
 
     let current_velocity = Vec3::new(character.linear_velocity.x, 0., character.linear_velocity.z);
-    let desired_velocity = move_dir * move_speed.0;
+    let desired_velocity = move_dir * move_speed.0 * status_effects.move_speed_multiplier();
 
     let new_velocity = current_velocity.move_towards(desired_velocity, max_velocity_delta_per_tick);
 
@@ -0,0 +1,169 @@
+use std::{hash::{DefaultHasher, Hash, Hasher}, time::Duration};
+
+use avian3d::prelude::{Collider, CollidingEntities, Position, Sensor};
+use bevy::prelude::*;
+use lightyear::prelude::{client::{Confirmed, Interpolated}, ClientId, Tick};
+use serde::{Deserialize, Serialize};
+
+use crate::{ability_framework::status_effect::StatusEffect, combat::Dead, player::PlayerId, shared::FixedSet, team::{Targets, Team}};
+
+pub struct ProjectilePlugin;
+
+impl Plugin for ProjectilePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ProjectileHit>();
+
+        app.register_type::<Projectile>();
+        app.register_type::<ProjectileLifetime>();
+
+        app.add_systems(FixedUpdate, (
+                move_projectiles,
+                tick_projectile_lifetimes,
+                detect_projectile_hits,
+            )
+            .chain()
+            .in_set(FixedSet::Main)
+        );
+    }
+}
+
+#[derive(Component, Serialize, Deserialize, PartialEq, Clone, Reflect)]
+pub struct Projectile {
+    /// Client whose player fired the projectile, it never collides with its owner
+    pub owner: ClientId,
+    pub velocity: Vec3,
+    pub radius: f32,
+    pub damage: f32,
+    pub on_hit: Vec<StatusEffect>,
+    /// Team of the owner when the projectile was fired
+    pub team: Option<Team>,
+    /// Players the projectile collides with, the others are passed through
+    pub targets: Targets,
+}
+
+/// Time left before the projectile despawns on its own
+#[derive(Component, Serialize, Deserialize, PartialEq, Clone, Reflect)]
+pub struct ProjectileLifetime(pub Duration);
+
+#[derive(Event)]
+pub struct ProjectileHit {
+    pub projectile: Entity,
+    /// Entity the projectile collided with, either a player or level geometry
+    pub target: Entity,
+    pub owner: ClientId,
+    pub damage: f32,
+    pub on_hit: Vec<StatusEffect>,
+}
+
+/// Filter for projectiles which are simulated locally: every projectile on the server, and
+/// pre-spawned or predicted projectiles on the client.
+pub type SimulatedProjectile = (With<Projectile>, Without<Confirmed>, Without<Interpolated>);
+
+#[derive(Bundle)]
+pub struct ProjectileBundle {
+    projectile: Projectile,
+    lifetime: ProjectileLifetime,
+    position: Position,
+    spatial: SpatialBundle,
+    collider: Collider,
+    sensor: Sensor,
+    colliding: CollidingEntities,
+    name: Name,
+}
+
+impl ProjectileBundle {
+    pub fn new(owner: ClientId, origin: Vec3, target: Vec3, speed: f32, radius: f32, damage: f32, lifetime: Duration) -> Self {
+        let direction = Vec3::new(target.x - origin.x, 0., target.z - origin.z).normalize_or_zero();
+
+        Self {
+            projectile: Projectile {
+                owner,
+                velocity: direction * speed,
+                radius,
+                damage,
+                on_hit: Vec::new(),
+                team: None,
+                targets: Targets::default(),
+            },
+            lifetime: ProjectileLifetime(lifetime),
+            position: Position(origin),
+            spatial: SpatialBundle::from_transform(Transform::from_translation(origin)),
+            collider: Collider::sphere(radius),
+            sensor: Sensor,
+            colliding: CollidingEntities::default(),
+            name: Name::from("Projectile"),
+        }
+    }
+
+    pub fn with_on_hit(mut self, on_hit: Vec<StatusEffect>) -> Self {
+        self.projectile.on_hit = on_hit;
+        self
+    }
+
+    pub fn with_targets(mut self, team: Option<Team>, targets: Targets) -> Self {
+        self.projectile.team = team;
+        self.projectile.targets = targets;
+        self
+    }
+}
+
+/// Hash used to match the projectile pre-spawned by the client with the one spawned by the server.
+/// Both sides trigger the ability on the same tick, `slot` disambiguates multiple projectiles fired on that tick.
+/// The default archetype-based hash can't be used since physics adds different components on the client and server.
+pub fn projectile_hash(owner: ClientId, tick: Tick, slot: usize) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    owner.hash(&mut hasher);
+    tick.hash(&mut hasher);
+    slot.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn move_projectiles(
+    time: Res<Time>,
+    mut query: Query<(&Projectile, &mut Position), SimulatedProjectile>,
+) {
+    for (projectile, mut position) in query.iter_mut() {
+        position.0 += projectile.velocity * time.delta_seconds();
+    }
+}
+
+fn tick_projectile_lifetimes(
+    time: Res<Time>,
+    mut query: Query<&mut ProjectileLifetime, SimulatedProjectile>,
+) {
+    for mut lifetime in query.iter_mut() {
+        lifetime.0 = lifetime.0.saturating_sub(time.delta());
+    }
+}
+
+fn detect_projectile_hits(
+    mut hits: EventWriter<ProjectileHit>,
+    query: Query<(Entity, &Projectile, &CollidingEntities), SimulatedProjectile>,
+    player_query: Query<(&PlayerId, Has<Dead>, Option<&Team>)>,
+    projectile_query: Query<(), With<Projectile>>,
+) {
+    for (entity, projectile, colliding) in query.iter() {
+        for &target in colliding.iter() {
+            if projectile_query.contains(target) {
+                continue;
+            }
+            // projectiles pass through their owner, dead players and players they don't target
+            let passes_through = |(id, dead, team): (&PlayerId, bool, Option<&Team>)| {
+                id.0 == projectile.owner || dead || !projectile.targets.allows(projectile.team, team.copied())
+            };
+            if player_query.get(target).is_ok_and(passes_through) {
+                continue;
+            }
+
+            hits.send(ProjectileHit {
+                projectile: entity,
+                target,
+                owner: projectile.owner,
+                damage: projectile.damage,
+                on_hit: projectile.on_hit.clone(),
+            });
+            // a projectile can only hit a single target
+            break;
+        }
+    }
+}
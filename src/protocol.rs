@@ -1,56 +1,115 @@
 use avian3d::prelude::{AngularVelocity, LinearVelocity, Position, Rotation};
 use bevy::prelude::*;
-use lightyear::{prelude::{client::ComponentSyncMode, AppComponentExt, ChannelDirection}, utils::avian3d::{position, rotation}};
+use lightyear::{prelude::{client::ComponentSyncMode, AppChannelExt, AppComponentExt, AppMessageExt, Channel, ChannelDirection, ChannelMode, ChannelSettings, ReliableSettings}, utils::avian3d::{position, rotation}};
 use lightyear::shared::input::leafwing::LeafwingInputPlugin;
 
-use crate::{abilities::Dodge, ability_framework::{ability_map::AbilityMap, cooldown::Cooldown, pool::AbilityCost, pools::{life::LifePool, mana::ManaPool}, Ability, AbilityCharge, PredictedAbility}, player::{CursorPosition, MoveSpeed, PlayerActions, PlayerId}};
+use crate::{abilities::definition::AbilityEffects, chat::{ChatMessage, SendChatMessage}, ability_framework::{ability_map::AbilityMap, cooldown::Cooldown, pipeline::AbilityFailedMessage, pool::PoolPlugin, pools::{heat::HeatPool, life::LifePool, mana::ManaPool}, status_effect::StatusEffects, Ability, AbilityActive, AbilityCharge, ActivationMode, PredictedAbility}, combat::Dead, lobby::{JoinDeniedMessage, JoinMatchMessage, MatchId, MatchPhase, MatchRoster, ReadyMessage, SpectateMessage}, player::{CursorPosition, MoveSpeed, PlayerActions, PlayerId}, projectile::{Projectile, ProjectileLifetime}, scoreboard::Scoreboard, spectator::SpectatorFrameMessage, team::{Team, TeamRules}};
 
 pub struct ProtocolPlugin;
 
+/// Gameplay feedback from the server which isn't part of the replicated state
+#[derive(Channel)]
+pub struct FeedbackChannel;
+
+/// Match lifecycle requests from clients
+#[derive(Channel)]
+pub struct LobbyChannel;
+
+/// Delayed round state sent to spectators instead of replication
+#[derive(Channel)]
+pub struct SpectatorChannel;
+
+/// Chat messages and notices from the server
+#[derive(Channel)]
+pub struct ChatChannel;
+
 impl Plugin for ProtocolPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(LeafwingInputPlugin::<PlayerActions>::default());
 
+        // pools register their own components
+        app.add_plugins(PoolPlugin::<LifePool>::default());
+        app.add_plugins(PoolPlugin::<ManaPool>::default());
+        app.add_plugins(PoolPlugin::<HeatPool>::default());
+
+        app.add_channel::<FeedbackChannel>(ChannelSettings {
+            mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
+            ..default()
+        });
+
+        app.add_channel::<LobbyChannel>(ChannelSettings {
+            mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
+            ..default()
+        });
+
+        app.add_channel::<SpectatorChannel>(ChannelSettings {
+            mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
+            ..default()
+        });
+
+        app.add_channel::<ChatChannel>(ChannelSettings {
+            mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
+            ..default()
+        });
+
+        app.register_message::<AbilityFailedMessage>(ChannelDirection::ServerToClient)
+            .add_map_entities();
+        app.register_message::<JoinMatchMessage>(ChannelDirection::ClientToServer);
+        app.register_message::<JoinDeniedMessage>(ChannelDirection::ServerToClient);
+        app.register_message::<ReadyMessage>(ChannelDirection::ClientToServer);
+        app.register_message::<SpectateMessage>(ChannelDirection::ClientToServer);
+        app.register_message::<SpectatorFrameMessage>(ChannelDirection::ServerToClient);
+        app.register_message::<SendChatMessage>(ChannelDirection::ClientToServer);
+        app.register_message::<ChatMessage>(ChannelDirection::ServerToClient);
+
+        app.register_component::<MatchId>(ChannelDirection::ServerToClient);
+        app.register_component::<MatchPhase>(ChannelDirection::ServerToClient);
+        app.register_component::<MatchRoster>(ChannelDirection::ServerToClient);
+        app.register_component::<TeamRules>(ChannelDirection::ServerToClient);
+        app.register_component::<Scoreboard>(ChannelDirection::ServerToClient);
+
         app.register_component::<Name>(ChannelDirection::ServerToClient)
             .add_prediction(ComponentSyncMode::Once);
 
-        app.register_component::<PlayerId>(ChannelDirection::Bidirectional)
+        app.register_component::<PlayerId>(ChannelDirection::ServerToClient)
             .add_prediction(ComponentSyncMode::Once)
             .add_interpolation(ComponentSyncMode::Once);
 
-        app.register_component::<Position>(ChannelDirection::Bidirectional)
+        app.register_component::<Position>(ChannelDirection::ServerToClient)
             .add_prediction(ComponentSyncMode::Full)
             .add_interpolation(ComponentSyncMode::Full)
             .add_interpolation_fn(position::lerp)
             .add_correction_fn(position::lerp);
-        app.register_component::<Rotation>(ChannelDirection::Bidirectional)
+        app.register_component::<Rotation>(ChannelDirection::ServerToClient)
             .add_prediction(ComponentSyncMode::Full)
             .add_interpolation(ComponentSyncMode::Full)
             .add_interpolation_fn(rotation::lerp)
             .add_correction_fn(rotation::lerp);
 
-        app.register_component::<MoveSpeed>(ChannelDirection::Bidirectional)
+        app.register_component::<Team>(ChannelDirection::ServerToClient)
+            .add_prediction(ComponentSyncMode::Once)
+            .add_interpolation(ComponentSyncMode::Once);
+
+        app.register_component::<MoveSpeed>(ChannelDirection::ServerToClient)
             .add_prediction(ComponentSyncMode::Simple);
 
-        app.register_component::<LinearVelocity>(ChannelDirection::Bidirectional)
+        app.register_component::<LinearVelocity>(ChannelDirection::ServerToClient)
             .add_prediction(ComponentSyncMode::Full);
-        app.register_component::<AngularVelocity>(ChannelDirection::Bidirectional)
+        app.register_component::<AngularVelocity>(ChannelDirection::ServerToClient)
             .add_prediction(ComponentSyncMode::Full);
 
+        // the only component clients author, checked by `ClientValidationPlugin`
         app.register_component::<CursorPosition>(ChannelDirection::Bidirectional)
             .add_prediction(ComponentSyncMode::Full)
             .add_interpolation(ComponentSyncMode::Full)
             .add_linear_interpolation_fn();
 
-        app.register_component::<LifePool>(ChannelDirection::Bidirectional)
+        app.register_component::<StatusEffects>(ChannelDirection::ServerToClient)
             .add_prediction(ComponentSyncMode::Full);
-        app.register_component::<AbilityCost<LifePool>>(ChannelDirection::Bidirectional)
-            .add_prediction(ComponentSyncMode::Simple);
 
-        app.register_component::<ManaPool>(ChannelDirection::Bidirectional)
-            .add_prediction(ComponentSyncMode::Full);
-        app.register_component::<AbilityCost<ManaPool>>(ChannelDirection::Bidirectional)
-            .add_prediction(ComponentSyncMode::Simple);
+        app.register_component::<Dead>(ChannelDirection::ServerToClient)
+            .add_prediction(ComponentSyncMode::Simple)
+            .add_interpolation(ComponentSyncMode::Simple);
 
         //  sync ability states
         app.register_component::<Ability>(ChannelDirection::ServerToClient)
@@ -59,6 +118,10 @@ impl Plugin for ProtocolPlugin {
             .add_prediction(ComponentSyncMode::Once);
         app.register_component::<AbilityCharge>(ChannelDirection::ServerToClient)
             .add_prediction(ComponentSyncMode::Full);
+        app.register_component::<ActivationMode>(ChannelDirection::ServerToClient)
+            .add_prediction(ComponentSyncMode::Simple);
+        app.register_component::<AbilityActive>(ChannelDirection::ServerToClient)
+            .add_prediction(ComponentSyncMode::Full);
 
         app.register_component::<Cooldown>(ChannelDirection::ServerToClient)
             .add_prediction(ComponentSyncMode::Simple);
@@ -67,8 +130,13 @@ impl Plugin for ProtocolPlugin {
             .add_prediction(ComponentSyncMode::Simple)
             .add_map_entities();
 
-        // Ability tags
-        app.register_component::<Dodge>(ChannelDirection::ServerToClient)
-            .add_prediction(ComponentSyncMode::Once);
+        app.register_component::<AbilityEffects>(ChannelDirection::ServerToClient)
+            .add_prediction(ComponentSyncMode::Simple);
+
+        app.register_component::<Projectile>(ChannelDirection::ServerToClient)
+            .add_prediction(ComponentSyncMode::Once)
+            .add_interpolation(ComponentSyncMode::Once);
+        app.register_component::<ProjectileLifetime>(ChannelDirection::ServerToClient)
+            .add_prediction(ComponentSyncMode::Full);
     }
 }
\ No newline at end of file
@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+use lightyear::prelude::server::{ControlledBy, RoomManager};
+use lightyear::server::events::{ConnectEvent, DisconnectEvent};
+
+use crate::{ability_framework::ability_map::AbilityMap, bots::Bot, lobby::{InMatch, LobbySet, MatchRoster}, player::{PlayerActions, PlayerId}, shared::GameState};
+
+/// Cleans up after clients who disconnect mid-round. Their player is parked for a grace window so that
+/// the same client can reconnect and resume it with its pools and cooldowns intact, then despawned
+/// along with its abilities.
+pub struct ReconnectServerPlugin {
+    /// No grace window despawns players as soon as their client disconnects
+    pub grace: Duration,
+}
+
+impl Plugin for ReconnectServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ReconnectGrace(self.grace));
+
+        // before the lobby removes disconnected clients from the rosters
+        app.add_systems(Update, (
+                park_disconnected_players,
+                resume_parked_players,
+                expire_parked_players,
+            )
+            .chain()
+            .before(LobbySet)
+            .run_if(in_state(GameState::Game))
+        );
+    }
+}
+
+#[derive(Resource)]
+struct ReconnectGrace(Duration);
+
+/// Server-side marker on the player of a disconnected client, until it reconnects or the grace window ends
+#[derive(Component)]
+struct Parked {
+    timer: Timer,
+}
+
+/// Abilities are separate entities, only referenced by the player's `AbilityMap`
+fn despawn_player(commands: &mut Commands, player: Entity, ability_map: &AbilityMap<PlayerActions>) {
+    for (_, ability) in ability_map.iter() {
+        if let Some(ability) = commands.get_entity(*ability) {
+            ability.despawn_recursive();
+        }
+    }
+    commands.entity(player).despawn_recursive();
+}
+
+fn park_disconnected_players(
+    mut commands: Commands,
+    grace: Res<ReconnectGrace>,
+    mut events: EventReader<DisconnectEvent>,
+    mut player_query: Query<(Entity, &PlayerId, &InMatch, &AbilityMap<PlayerActions>, &mut ActionState<PlayerActions>), Without<Bot>>,
+    mut match_query: Query<&mut MatchRoster>,
+) {
+    for event in events.read() {
+        let client_id = event.client_id;
+        let Some((player, _, in_match, ability_map, mut action_state)) = player_query
+            .iter_mut()
+            .find(|(_, player_id, ..)| player_id.0 == client_id)
+        else {
+            continue;
+        };
+
+        if grace.0.is_zero() {
+            despawn_player(&mut commands, player, ability_map);
+            continue;
+        }
+        let Ok(mut roster) = match_query.get_mut(in_match.0) else {
+            continue;
+        };
+        let Some(entry) = roster.get_mut(client_id) else {
+            continue;
+        };
+
+        info!("Parking the player of client {client_id:?} for {:?}", grace.0);
+        entry.parked = true;
+        // stands still until its client is back
+        *action_state = ActionState::default();
+        commands.entity(player).insert(Parked {
+            timer: Timer::new(grace.0, TimerMode::Once),
+        });
+    }
+}
+
+fn resume_parked_players(
+    mut commands: Commands,
+    mut events: EventReader<ConnectEvent>,
+    mut room_manager: ResMut<RoomManager>,
+    mut player_query: Query<(Entity, &PlayerId, &InMatch, &mut ControlledBy), With<Parked>>,
+    mut match_query: Query<&mut MatchRoster>,
+) {
+    for event in events.read() {
+        let client_id = event.client_id;
+        let Some((player, _, in_match, mut controlled_by)) = player_query
+            .iter_mut()
+            .find(|(_, player_id, ..)| player_id.0 == client_id)
+        else {
+            continue;
+        };
+
+        info!("Client {client_id:?} reconnected, resuming its player");
+        commands.entity(player).remove::<Parked>();
+        // the new connection has to learn that it controls the player
+        controlled_by.set_changed();
+        // round entities are only relevant to the clients in the match's room
+        room_manager.add_client(client_id, in_match.room());
+        if let Ok(mut roster) = match_query.get_mut(in_match.0) {
+            if let Some(entry) = roster.get_mut(client_id) {
+                entry.parked = false;
+            }
+        }
+    }
+}
+
+fn expire_parked_players(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut player_query: Query<(Entity, &PlayerId, &InMatch, &AbilityMap<PlayerActions>, &mut Parked)>,
+    mut match_query: Query<&mut MatchRoster>,
+) {
+    for (player, player_id, in_match, ability_map, mut parked) in player_query.iter_mut() {
+        if !parked.timer.tick(time.delta()).finished() {
+            continue;
+        }
+
+        info!("Client {:?} did not reconnect in time, despawning its player", player_id.0);
+        despawn_player(&mut commands, player, ability_map);
+        if let Ok(mut roster) = match_query.get_mut(in_match.0) {
+            roster.players.retain(|entry| entry.client_id != player_id.0);
+        }
+    }
+}
@@ -1,11 +1,13 @@
+use std::{marker::PhantomData, ops::Div};
+
 use avian3d::prelude::{Position, Rotation};
-use bevy::{core_pipeline::{bloom::BloomSettings, tonemapping::Tonemapping}, pbr::ScreenSpaceAmbientOcclusionBundle, prelude::*, render::camera::ScalingMode};
-use bevy_asset_loader::loading_state::{config::ConfigureLoadingState, LoadingState, LoadingStateAppExt};
+use bevy::{core_pipeline::{bloom::BloomSettings, tonemapping::Tonemapping}, pbr::{CubemapVisibleEntities, ScreenSpaceAmbientOcclusionBundle}, prelude::*, render::{camera::ScalingMode, primitives::CubemapFrusta, render_resource::{AsBindGroup, ShaderRef}}};
+use bevy_asset_loader::loading_state::{config::{ConfigureLoadingState, LoadingStateConfig}, LoadingStateAppExt};
 use bevy_screen_diagnostics::{Aggregate, ScreenDiagnostics, ScreenDiagnosticsPlugin};
 use bevy_sprite3d::{Sprite3d, Sprite3dParams, Sprite3dPlugin};
-use lightyear::{client::prediction::diagnostics::PredictionDiagnosticsPlugin, prelude::client::{Confirmed, Predicted, VisualInterpolateStatus, VisualInterpolationPlugin}, transport::io::IoDiagnosticsPlugin};
+use lightyear::{client::prediction::diagnostics::PredictionDiagnosticsPlugin, prelude::client::{ClientConnection, Confirmed, Interpolated, NetClient, Predicted, VisualInterpolateStatus, VisualInterpolationPlugin}, shared::replication::components::Controlled, transport::io::IoDiagnosticsPlugin};
 
-use crate::{animation::{Animation, FaceCamera, OverheatAnimationPlugin}, assets::PlayerAssets, player::PlayerId, shared::GameState};
+use crate::{camera::CameraControllerPlugin, ability_framework::{ability_map::AbilityMap, cooldown::Cooldown, pool::Pool, pools::{heat::HeatPool, life::LifePool, mana::ManaPool}, AbilityActive, AbilityCharge, ActivationMode}, animation::{FaceCamera, OverheatAnimationPlugin, PlayerAnimator}, assets::PlayerAssets, combat::Dead, level::{LevelLight, LevelShape}, lobby::{MatchId, MatchPhase, MatchRoster}, player::{PlayerActions, PlayerId}, projectile::Projectile, shared::GameState, team::Team};
 
 pub struct OverheatRenderPlugin;
 
@@ -14,11 +16,11 @@ impl Plugin for OverheatRenderPlugin {
         app.add_plugins(Sprite3dPlugin);
 
         app.add_plugins(OverheatAnimationPlugin);
+        app.add_plugins(HudPlugin);
+        app.add_plugins(CameraControllerPlugin);
 
-        app.init_state::<GameState>();
-        app.add_loading_state(
-            LoadingState::new(GameState::AssetLoading)
-                .continue_to_state(GameState::Game)
+        app.configure_loading_state(
+            LoadingStateConfig::new(GameState::AssetLoading)
                 .load_collection::<PlayerAssets>()
         );
 
@@ -30,16 +32,16 @@ impl Plugin for OverheatRenderPlugin {
         app.observe(add_visual_interpolation_components::<Position>);
         app.observe(add_visual_interpolation_components::<Rotation>);
 
-        app.add_systems(Startup, (
-            init,
-            init_level_visuals,
-        ));
+        app.add_systems(Startup, init);
         app.add_systems(Startup, setup_diagnostics);
 
 
         app.add_systems(Update, (
             init_player_visuals
             .run_if(in_state(GameState::Game)),
+            init_projectile_visuals,
+            init_level_visuals,
+            hide_dead_players,
         ));
 
     }
@@ -62,7 +64,7 @@ fn init(
                 scaling_mode: ScalingMode::FixedVertical(15.0),
                 ..default()
             }.into(),
-            transform: Transform::from_xyz(22., 18., 22.).looking_at(Vec3::splat(0.), Vec3::Y),
+            transform: Transform::from_translation(CAMERA_OFFSET).looking_at(Vec3::splat(0.), Vec3::Y),
             ..default()
         },
         BloomSettings {
@@ -75,67 +77,51 @@ fn init(
     commands.spawn(Tonemapping::AcesFitted);
 }
 
+/// Offset from the followed player to the camera
+pub(crate) const CAMERA_OFFSET: Vec3 = Vec3::new(22., 18., 22.);
+/// Strength of the team colour added to player sprites
+const TEAM_TINT: f32 = 0.35;
+
+/// Attaches meshes to the level geometry and props spawned by `LevelPlugin`
 fn init_level_visuals(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    shape_query: Query<(Entity, &LevelShape), Added<LevelShape>>,
+    light_query: Query<(Entity, &LevelLight), Added<LevelLight>>,
 ) {
-    // #todo: this is only spawning the rendering state of the world, we need a way to split render and physics and spawn this in a common way for server/client
-
-    commands.spawn(PointLightBundle {
-        point_light: PointLight {
-            intensity: 1_000_000.,
-            color: Color::srgb(1., 231. / 255., 221. / 255.),
-            shadows_enabled: true,
-            ..default()
-        },
-        transform: Transform::from_xyz(-2., 5., -2.),
-        ..default()
-    });
+    for (entity, shape) in shape_query.iter() {
+        let [r, g, b] = shape.color;
 
-    commands.spawn(PbrBundle {
-        mesh: meshes.add(Plane3d::default().mesh().size(30., 30.)),
-        material: materials.add(Color::srgb(0.3, 0.5, 0.3)),
-        transform: Transform::from_xyz(0., 0., 0.),
-        ..default()
-    });
-
-    commands.spawn(PbrBundle {
-        mesh: meshes.add(Sphere::new(0.6)),
-        material: materials.add(Color::WHITE),
-        transform: Transform::from_xyz(-1., 0.5, -1.),
-        ..default()
-    });
-
-    commands.spawn(PbrBundle {
-        mesh: meshes.add(Sphere::new(0.6)),
-        material: materials.add(Color::WHITE),
-        transform: Transform::from_xyz(-1., 0.5, 7.),
-        ..default()
-    });
+        commands.entity(entity).insert((
+            meshes.add(shape.shape.mesh()),
+            materials.add(Color::srgb(r, g, b)),
+        ));
+    }
 
-    commands.spawn(PbrBundle {
-        mesh: meshes.add(Cuboid::new(10., 2., 2.)),
-        material: materials.add(Color::WHITE),
-        transform: Transform::from_xyz(-1., 0.5, 7.),
-        ..default()
-    });
+    for (entity, light) in light_query.iter() {
+        let [r, g, b] = light.color;
 
-    commands.spawn(PbrBundle {
-        mesh: meshes.add(Cuboid::new(2., 2., 10.)),
-        material: materials.add(Color::WHITE),
-        transform: Transform::from_xyz(7., 0.5, -1.),
-        ..default()
-    });
+        commands.entity(entity).insert((
+            PointLight {
+                intensity: light.intensity,
+                color: Color::srgb(r, g, b),
+                shadows_enabled: light.shadows,
+                ..default()
+            },
+            CubemapVisibleEntities::default(),
+            CubemapFrusta::default(),
+        ));
+    }
 }
 
 fn init_player_visuals(
     mut commands: Commands,
     mut sprite_params: Sprite3dParams,
     player_assets: Res<PlayerAssets>,
-    query: Query<Entity, (With<PlayerId>, With<Predicted>, Without<PlayerVisualsMarker>)>,
+    query: Query<(Entity, Option<&Team>), (With<PlayerId>, Or<(With<Predicted>, With<Interpolated>)>, Without<PlayerVisualsMarker>)>,
 ) {
-    for player in &query {
+    for (player, team) in &query {
         let atlas = TextureAtlas {
             layout: player_assets.player_atlas.clone(),
             index: 0
@@ -148,19 +134,51 @@ fn init_player_visuals(
                 double_sided: false,
                 pivot: Some(Vec2::new(0.5, 1. / 3.)),
                 transform: Transform::from_xyz(0., 0., 0.),
+                // tints the sprite, materials are cached per colour so each team shares one
+                emissive: team.map_or(LinearRgba::BLACK, |team| team.color().to_linear() * TEAM_TINT),
                 ..default()
             }.bundle_with_atlas(&mut sprite_params, atlas),
             FaceCamera {},
-            Animation {
-                frames: vec![1, 2, 1, 0],
-                current: 0,
-                timer: Timer::from_seconds(0.2, TimerMode::Repeating),
-            },
         )).id();
 
         commands.entity(player).add_child(sprite);
 
-        commands.entity(player).insert(PlayerVisualsMarker);
+        commands.entity(player).insert((PlayerVisualsMarker, PlayerAnimator::new(sprite)));
+    }
+}
+
+/// Dead players are hidden once their death animation is over
+fn hide_dead_players(
+    mut query: Query<(&mut Visibility, Has<Dead>, Option<&PlayerAnimator>), (With<PlayerId>, Without<Confirmed>)>,
+) {
+    for (mut visibility, dead, animator) in query.iter_mut() {
+        let hidden = dead && animator.is_none_or(PlayerAnimator::death_finished);
+        let target = if hidden { Visibility::Hidden } else { Visibility::Inherited };
+        visibility.set_if_neq(target);
+    }
+}
+
+fn init_projectile_visuals(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    query: Query<(Entity, &Projectile, Option<&Position>, Has<Transform>), (Added<Projectile>, Without<Confirmed>)>,
+) {
+    for (entity, projectile, position, has_transform) in &query {
+        // projectiles received from the server only contain the replicated components
+        if !has_transform {
+            let translation = position.map_or(Vec3::ZERO, |p| p.0);
+            commands.entity(entity).insert(SpatialBundle::from_transform(Transform::from_translation(translation)));
+        }
+
+        commands.entity(entity).insert((
+            meshes.add(Sphere::new(projectile.radius)),
+            materials.add(StandardMaterial {
+                base_color: Color::srgb(1., 0.6, 0.2),
+                emissive: LinearRgba::rgb(8., 2., 0.4),
+                ..default()
+            }),
+        ));
     }
 }
 
@@ -215,4 +233,435 @@ fn add_visual_interpolation_components<T: Component>(
 }
 
 #[derive(Component)]
-struct PlayerVisualsMarker;
\ No newline at end of file
+struct PlayerVisualsMarker;
+
+/// Pools, ability cooldowns and charge of the locally controlled player, and health bars above remote players
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(UiMaterialPlugin::<CooldownMaterial>::default());
+
+        app.add_systems(Startup, init_hud);
+        app.add_systems(Update, (
+            update_pool_bar::<LifePool>,
+            update_pool_bar::<ManaPool>,
+            update_pool_bar::<HeatPool>,
+            update_overheat_bar,
+            (
+                rebuild_ability_slots,
+                update_ability_slots,
+            ).chain(),
+            update_charge_meter,
+            (
+                spawn_floating_health_bars,
+                update_floating_health_bars,
+            ).chain(),
+            update_match_status,
+        ));
+    }
+}
+
+const LIFE_COLOR: Color = Color::srgb(0.8, 0.15, 0.15);
+const MANA_COLOR: Color = Color::srgb(0.2, 0.4, 0.9);
+const HEAT_COLOR: Color = Color::srgb(1., 0.55, 0.1);
+const OVERHEAT_COLOR: Color = Color::srgb(1., 0.9, 0.3);
+const BAR_BACKGROUND: Color = Color::srgba(0., 0., 0., 0.6);
+
+/// Height above a remote player's origin where its health bar is drawn
+const FLOATING_BAR_OFFSET: f32 = 2.;
+const FLOATING_BAR_WIDTH: f32 = 60.;
+
+pub(crate) type LocalPlayer = (With<PlayerId>, With<Predicted>, With<Controlled>);
+
+/// Radial cooldown indicator, rendered by `assets/shaders/cooldown_radial.wgsl`
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+struct CooldownMaterial {
+    #[uniform(0)]
+    color: Vec4,
+    #[uniform(1)]
+    remaining: f32,
+}
+
+impl UiMaterial for CooldownMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/cooldown_radial.wgsl".into()
+    }
+}
+
+#[derive(Component)]
+struct PoolBarFill<P: Pool + Component> {
+    _marker: PhantomData<P>,
+}
+
+impl<P: Pool + Component> Default for PoolBarFill<P> {
+    fn default() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[derive(Component)]
+struct AbilitySlots;
+
+#[derive(Component)]
+struct AbilitySlot {
+    ability: Entity,
+}
+
+#[derive(Component)]
+struct ChargeMeter;
+
+#[derive(Component)]
+struct ChargeMeterFill;
+
+#[derive(Component)]
+struct FloatingHealthBar {
+    target: Entity,
+}
+
+#[derive(Component)]
+struct FloatingHealthBarFill;
+
+#[derive(Component)]
+struct MatchStatusText;
+
+fn init_hud(
+    mut commands: Commands,
+) {
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(16.),
+            left: Val::Px(0.),
+            right: Val::Px(0.),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            row_gap: Val::Px(4.),
+            ..default()
+        },
+        ..default()
+    })
+    .with_children(|hud| {
+        let mut charge_meter = bar_background(Val::Px(120.), Val::Px(6.));
+        charge_meter.visibility = Visibility::Hidden;
+        hud.spawn((charge_meter, ChargeMeter))
+            .with_children(|meter| {
+                meter.spawn((bar_fill(Color::WHITE), ChargeMeterFill));
+            });
+
+        hud.spawn((
+            NodeBundle {
+                style: Style {
+                    column_gap: Val::Px(8.),
+                    margin: UiRect::vertical(Val::Px(4.)),
+                    ..default()
+                },
+                ..default()
+            },
+            AbilitySlots,
+        ));
+
+        spawn_pool_bar::<LifePool>(hud, LIFE_COLOR);
+        spawn_pool_bar::<ManaPool>(hud, MANA_COLOR);
+        spawn_pool_bar::<HeatPool>(hud, HEAT_COLOR);
+    });
+
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(16.),
+            left: Val::Px(0.),
+            right: Val::Px(0.),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        ..default()
+    })
+    .with_children(|status| {
+        status.spawn((
+            TextBundle::from_section("", TextStyle {
+                font_size: 24.,
+                color: Color::WHITE,
+                ..default()
+            }),
+            MatchStatusText,
+        ));
+    });
+}
+
+fn bar_background(width: Val, height: Val) -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            width,
+            height,
+            ..default()
+        },
+        background_color: BAR_BACKGROUND.into(),
+        ..default()
+    }
+}
+
+fn bar_fill(color: Color) -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            ..default()
+        },
+        background_color: color.into(),
+        ..default()
+    }
+}
+
+fn spawn_pool_bar<P: Pool + Component>(parent: &mut ChildBuilder, color: Color) {
+    parent.spawn(bar_background(Val::Px(240.), Val::Px(12.)))
+        .with_children(|bar| {
+            bar.spawn((bar_fill(color), PoolBarFill::<P>::default()));
+        });
+}
+
+fn pool_fraction<P>(pool: &P) -> f32
+where
+    P: Pool,
+    P::Quantity: Div<Output = f32>,
+{
+    if pool.max() <= P::MIN {
+        return 0.;
+    }
+    (pool.current() / pool.max()).clamp(0., 1.)
+}
+
+fn update_pool_bar<P>(
+    player_query: Query<&P, LocalPlayer>,
+    mut bar_query: Query<&mut Style, With<PoolBarFill<P>>>,
+)
+where
+    P: Pool + Component,
+    P::Quantity: Div<Output = f32>,
+{
+    let Ok(pool) = player_query.get_single() else {
+        return;
+    };
+
+    for mut style in bar_query.iter_mut() {
+        style.width = Val::Percent(pool_fraction(pool) * 100.);
+    }
+}
+
+fn update_overheat_bar(
+    player_query: Query<&HeatPool, (LocalPlayer, Changed<HeatPool>)>,
+    mut bar_query: Query<&mut BackgroundColor, With<PoolBarFill<HeatPool>>>,
+) {
+    let Ok(heat) = player_query.get_single() else {
+        return;
+    };
+
+    let color = if heat.is_overheated() { OVERHEAT_COLOR } else { HEAT_COLOR };
+    for mut background in bar_query.iter_mut() {
+        background.0 = color;
+    }
+}
+
+/// Abilities can be rebound or replaced at any time, so the slots are rebuilt whenever the map changes
+fn rebuild_ability_slots(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<CooldownMaterial>>,
+    player_query: Query<&AbilityMap<PlayerActions>, (LocalPlayer, Changed<AbilityMap<PlayerActions>>)>,
+    slots_query: Query<Entity, With<AbilitySlots>>,
+) {
+    let Ok(map) = player_query.get_single() else {
+        return;
+    };
+    let Ok(slots) = slots_query.get_single() else {
+        return;
+    };
+
+    let mut bindings: Vec<_> = map.iter().collect();
+    bindings.sort_by_key(|(action, _)| format!("{action:?}"));
+
+    commands.entity(slots).despawn_descendants();
+    commands.entity(slots).with_children(|slots| {
+        for (action, &ability) in bindings {
+            slots.spawn((
+                MaterialNodeBundle {
+                    style: Style {
+                        width: Val::Px(48.),
+                        height: Val::Px(48.),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    material: materials.add(CooldownMaterial {
+                        color: Vec4::new(0.85, 0.85, 0.85, 1.),
+                        remaining: 0.,
+                    }),
+                    ..default()
+                },
+                AbilitySlot {
+                    ability,
+                },
+            ))
+            .with_children(|slot| {
+                slot.spawn(TextBundle::from_section(
+                    format!("{action:?}"),
+                    TextStyle {
+                        font_size: 10.,
+                        color: Color::BLACK,
+                        ..default()
+                    },
+                ));
+            });
+        }
+    });
+}
+
+fn update_ability_slots(
+    mut materials: ResMut<Assets<CooldownMaterial>>,
+    slot_query: Query<(&AbilitySlot, &Handle<CooldownMaterial>)>,
+    ability_query: Query<&Cooldown>,
+) {
+    for (slot, handle) in slot_query.iter() {
+        let Ok(cooldown) = ability_query.get(slot.ability) else {
+            continue;
+        };
+
+        let remaining = cooldown.remaining().as_secs_f32() / cooldown.duration().as_secs_f32();
+        // get_mut flags the material as modified, only do it when the value actually changed
+        if materials.get(handle).is_some_and(|material| material.remaining != remaining) {
+            if let Some(material) = materials.get_mut(handle) {
+                material.remaining = remaining;
+            }
+        }
+    }
+}
+
+fn update_charge_meter(
+    player_query: Query<&AbilityMap<PlayerActions>, LocalPlayer>,
+    ability_query: Query<(&ActivationMode, &AbilityActive, &AbilityCharge)>,
+    mut meter_query: Query<&mut Visibility, With<ChargeMeter>>,
+    mut fill_query: Query<&mut Style, With<ChargeMeterFill>>,
+) {
+    let Ok(mut visibility) = meter_query.get_single_mut() else {
+        return;
+    };
+
+    // charged and channelled abilities both use the charge to track how long they've been held
+    let charging = player_query.get_single().ok().and_then(|map| {
+        map.iter()
+            .filter_map(|(_, &ability)| ability_query.get(ability).ok())
+            .find(|(mode, active, _)| active.0 && matches!(mode, ActivationMode::Charge | ActivationMode::Channel))
+    });
+
+    let Some((_, _, charge)) = charging else {
+        visibility.set_if_neq(Visibility::Hidden);
+        return;
+    };
+
+    visibility.set_if_neq(Visibility::Inherited);
+    let fraction = if charge.max().is_zero() {
+        1.
+    } else {
+        charge.duration().as_secs_f32() / charge.max().as_secs_f32()
+    };
+    for mut style in fill_query.iter_mut() {
+        style.width = Val::Percent(fraction.min(1.) * 100.);
+    }
+}
+
+/// Every player but the local one, whether interpolated or predicted with `predict_all`
+fn spawn_floating_health_bars(
+    mut commands: Commands,
+    query: Query<Entity, (With<PlayerId>, Without<Confirmed>, Without<Controlled>, Added<LifePool>)>,
+) {
+    for target in query.iter() {
+        let mut background = bar_background(Val::Px(FLOATING_BAR_WIDTH), Val::Px(6.));
+        background.style.position_type = PositionType::Absolute;
+
+        commands.spawn((background, FloatingHealthBar { target }))
+            .with_children(|bar| {
+                bar.spawn((bar_fill(LIFE_COLOR), FloatingHealthBarFill));
+            });
+    }
+}
+
+fn update_floating_health_bars(
+    mut commands: Commands,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    target_query: Query<(&GlobalTransform, &LifePool, Has<Dead>)>,
+    mut bar_query: Query<(Entity, &FloatingHealthBar, &mut Style, &mut Visibility, &Children)>,
+    mut fill_query: Query<&mut Style, (With<FloatingHealthBarFill>, Without<FloatingHealthBar>)>,
+) {
+    let Ok((camera, camera_transform)) = camera_query.get_single() else {
+        return;
+    };
+
+    for (entity, bar, mut style, mut visibility, children) in bar_query.iter_mut() {
+        let Ok((transform, life, dead)) = target_query.get(bar.target) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+
+        let above = transform.translation() + Vec3::Y * FLOATING_BAR_OFFSET;
+        let Some(screen_position) = camera.world_to_viewport(camera_transform, above).filter(|_| !dead) else {
+            visibility.set_if_neq(Visibility::Hidden);
+            continue;
+        };
+
+        visibility.set_if_neq(Visibility::Inherited);
+        style.left = Val::Px(screen_position.x - FLOATING_BAR_WIDTH / 2.);
+        style.top = Val::Px(screen_position.y);
+
+        for &child in children.iter() {
+            if let Ok(mut fill_style) = fill_query.get_mut(child) {
+                fill_style.width = Val::Percent(pool_fraction(life) * 100.);
+            }
+        }
+    }
+}
+
+fn update_match_status(
+    connection: Option<Res<ClientConnection>>,
+    match_query: Query<(&MatchId, &MatchPhase, &MatchRoster), Or<(Changed<MatchPhase>, Changed<MatchRoster>)>>,
+    mut text_query: Query<&mut Text, With<MatchStatusText>>,
+) {
+    let Some(connection) = connection else {
+        return;
+    };
+    // a host server's client also sees the other matches
+    let Some((match_id, phase, roster)) = match_query
+        .iter()
+        .find(|(_, _, roster)| roster.get(connection.id()).is_some() || roster.is_spectating(connection.id()))
+    else {
+        return;
+    };
+    let Ok(mut text) = text_query.get_single_mut() else {
+        return;
+    };
+
+    // spectators have no roster entry
+    let Some(entry) = roster.get(connection.id()) else {
+        let status = match phase {
+            MatchPhase::Lobby => format!("Lobby - {}/{} ready", roster.ready_count(), roster.players.len()),
+            MatchPhase::Countdown { seconds_left } => format!("Round starts in {seconds_left}"),
+            MatchPhase::InRound { seconds_left } => format!("{}:{:02}", seconds_left / 60, seconds_left % 60),
+            MatchPhase::RoundOver { .. } => "Round over".to_string(),
+        };
+        text.sections[0].value = format!("Spectating match {} - {status}", match_id.0);
+        return;
+    };
+
+    let ready = entry.ready;
+    let status = match phase {
+        MatchPhase::Lobby if ready => format!("Lobby - {}/{} ready, press R to cancel", roster.ready_count(), roster.players.len()),
+        MatchPhase::Lobby => format!("Lobby - {}/{} ready, press R to ready up", roster.ready_count(), roster.players.len()),
+        MatchPhase::Countdown { seconds_left } => format!("Round starts in {seconds_left}"),
+        MatchPhase::InRound { seconds_left } => format!("{}:{:02}", seconds_left / 60, seconds_left % 60),
+        MatchPhase::RoundOver { seconds_left } => format!("Round over, back to the lobby in {seconds_left}"),
+    };
+    text.sections[0].value = match entry.team {
+        Some(team) => format!("Match {} - {} team - {status}", match_id.0, team.name()),
+        None => format!("Match {} - {status}", match_id.0),
+    };
+}
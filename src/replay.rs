@@ -0,0 +1,669 @@
+use std::{fs::File, io::{BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use avian3d::prelude::{Position, Rotation};
+use bevy::{app::AppExit, ecs::system::EntityCommands, input::common_conditions::input_just_pressed, prelude::*, tasks::IoTaskPool, utils::{HashMap, HashSet}};
+use bincode::{config, error::{DecodeError, EncodeError}};
+use derive_more::derive::{Display, Error, From};
+use lightyear::prelude::{client::Interpolated, ClientId, InputMessage, Tick, TickManager};
+use serde::{Deserialize, Serialize};
+
+use crate::{ability_framework::pools::{heat::HeatPool, life::LifePool, mana::ManaPool}, combat::Dead, lobby::{InMatch, MatchId, MatchPhase}, player::{PlayerActions, PlayerId}, projectile::Projectile, shared::GameState, team::Team};
+
+/// Start of every replay file
+const REPLAY_MAGIC: [u8; 4] = *b"RPLY";
+/// Bumped whenever the layout of `Replay` changes, older files are rejected before decoding their body
+const REPLAY_VERSION: u32 = 3;
+/// Time skipped by a single seek
+const SEEK_STEP: Duration = Duration::from_secs(5);
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 8.;
+
+#[derive(Debug, Display, Error, From)]
+pub enum ReplayError {
+    #[display("replay io error: {_0}")]
+    #[from]
+    Io(std::io::Error),
+    #[display("could not encode replay: {_0}")]
+    #[from]
+    Encode(EncodeError),
+    #[display("could not decode replay: {_0}")]
+    #[from]
+    Decode(DecodeError),
+    #[display("not a replay file")]
+    Format,
+    #[display("replay has version {found}, this build only plays version {REPLAY_VERSION}")]
+    Version {
+        found: u32,
+    },
+}
+
+/// One match round as seen by the server, stored as bincode after a header holding
+/// `REPLAY_MAGIC` and the little endian `REPLAY_VERSION`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Replay {
+    pub match_id: MatchId,
+    pub tick_duration: Duration,
+    /// One frame per server tick, empty frames included so that frame indices map to time
+    pub frames: Vec<ReplayFrame>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).map_err(|_| ReplayError::Format)?;
+        if magic != REPLAY_MAGIC {
+            return Err(ReplayError::Format);
+        }
+        let mut version = [0; 4];
+        reader.read_exact(&mut version).map_err(|_| ReplayError::Format)?;
+        let version = u32::from_le_bytes(version);
+        if version != REPLAY_VERSION {
+            return Err(ReplayError::Version { found: version });
+        }
+
+        Ok(bincode::serde::decode_from_std_read(&mut reader, config::standard())?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&REPLAY_MAGIC)?;
+        writer.write_all(&REPLAY_VERSION.to_le_bytes())?;
+        bincode::serde::encode_into_std_write(self, &mut writer, config::standard())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.tick_duration * self.frames.len() as u32
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ReplayFrame {
+    pub tick: Tick,
+    /// Components that changed during the tick, keyed by the server entity
+    pub updates: Vec<(u64, RecordedComponent)>,
+    pub despawned: Vec<u64>,
+    /// Inputs received from the match's clients during the tick
+    pub inputs: Vec<(ClientId, InputMessage<PlayerActions>)>,
+}
+
+/// The replicated components needed to show a round again
+#[derive(Serialize, Deserialize, Clone)]
+pub enum RecordedComponent {
+    PlayerId(PlayerId),
+    Position(Position),
+    Rotation(Rotation),
+    Life(LifePool),
+    Mana(ManaPool),
+    Heat(HeatPool),
+    Projectile(Projectile),
+    Team(Team),
+    Dead(bool),
+}
+
+macro_rules! recorded_component_from {
+    ($($variant:ident($component:ty)),* $(,)?) => {
+        $(
+            impl From<$component> for RecordedComponent {
+                fn from(component: $component) -> Self {
+                    Self::$variant(component)
+                }
+            }
+        )*
+    };
+}
+
+recorded_component_from!(
+    PlayerId(PlayerId),
+    Position(Position),
+    Rotation(Rotation),
+    Life(LifePool),
+    Mana(ManaPool),
+    Heat(HeatPool),
+    Projectile(Projectile),
+    Team(Team),
+);
+
+impl RecordedComponent {
+    fn apply(self, entity: &mut EntityCommands) {
+        match self {
+            Self::PlayerId(c) => entity.insert(c),
+            Self::Position(c) => entity.insert(c),
+            Self::Rotation(c) => entity.insert(c),
+            Self::Life(c) => entity.insert(c),
+            Self::Mana(c) => entity.insert(c),
+            Self::Heat(c) => entity.insert(c),
+            Self::Projectile(c) => entity.insert(c),
+            Self::Team(c) => entity.insert(c),
+            Self::Dead(true) => entity.insert(Dead),
+            Self::Dead(false) => entity.remove::<Dead>(),
+        };
+    }
+}
+
+/// Records the rounds of every match tick by tick, the frames are sent as `RecordedFrame` events.
+/// Added by the plugins consuming them.
+pub struct MatchRecordingPlugin;
+
+impl Plugin for MatchRecordingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MatchRecorder>();
+        app.add_event::<RecordedFrame>();
+
+        // after the simulation so that each frame holds the state at the end of its tick
+        app.add_systems(FixedPostUpdate, (
+                start_recordings,
+                (
+                    record_component::<PlayerId>,
+                    record_component::<Position>,
+                    record_component::<Rotation>,
+                    record_component::<LifePool>,
+                    record_component::<ManaPool>,
+                    record_component::<HeatPool>,
+                    record_component::<Projectile>,
+                    record_component::<Team>,
+                    record_deaths,
+                    record_despawns,
+                ),
+                finish_frames,
+            )
+            .chain()
+            .in_set(MatchRecordingSet)
+            .run_if(in_state(GameState::Game))
+        );
+    }
+}
+
+/// Consumers of `RecordedFrame` run after this set
+#[derive(SystemSet, Hash, PartialEq, Eq, Clone, Copy, Debug)]
+pub struct MatchRecordingSet;
+
+/// A finished tick of a round
+#[derive(Event)]
+pub struct RecordedFrame {
+    pub match_entity: Entity,
+    pub match_id: MatchId,
+    pub frame: ReplayFrame,
+    /// The match went back to its lobby, this is the round's last frame
+    pub last: bool,
+}
+
+#[derive(Resource, Default)]
+pub struct MatchRecorder {
+    /// Rounds being recorded, by match entity
+    recordings: HashMap<Entity, Recording>,
+}
+
+impl MatchRecorder {
+    pub fn record_input(&mut self, match_entity: Entity, client_id: ClientId, message: InputMessage<PlayerActions>) {
+        if let Some(recording) = self.recordings.get_mut(&match_entity) {
+            recording.current.inputs.push((client_id, message));
+        }
+    }
+}
+
+struct Recording {
+    match_id: MatchId,
+    current: ReplayFrame,
+    /// Entities with recorded components, their despawns are recorded too
+    entities: HashSet<Entity>,
+}
+
+impl Recording {
+    fn update(&mut self, entity: Entity, component: RecordedComponent) {
+        self.entities.insert(entity);
+        self.current.updates.push((entity.to_bits(), component));
+    }
+}
+
+/// Rounds are recorded from their start until the match is back in its lobby
+fn start_recordings(
+    mut recorder: ResMut<MatchRecorder>,
+    match_query: Query<(Entity, &MatchId, &MatchPhase), Changed<MatchPhase>>,
+) {
+    for (match_entity, match_id, phase) in match_query.iter() {
+        if !matches!(phase, MatchPhase::InRound { .. }) || recorder.recordings.contains_key(&match_entity) {
+            continue;
+        }
+
+        recorder.recordings.insert(match_entity, Recording {
+            match_id: *match_id,
+            current: ReplayFrame::default(),
+            entities: HashSet::default(),
+        });
+    }
+}
+
+fn record_component<C: Component + Clone + Into<RecordedComponent>>(
+    mut recorder: ResMut<MatchRecorder>,
+    query: Query<(Entity, &C, &InMatch), Changed<C>>,
+) {
+    for (entity, component, in_match) in query.iter() {
+        if let Some(recording) = recorder.recordings.get_mut(&in_match.0) {
+            recording.update(entity, component.clone().into());
+        }
+    }
+}
+
+fn record_deaths(
+    mut recorder: ResMut<MatchRecorder>,
+    mut respawned: RemovedComponents<Dead>,
+    dead_query: Query<(Entity, &InMatch), Added<Dead>>,
+    player_query: Query<&InMatch>,
+) {
+    let deaths = dead_query.iter().map(|(entity, in_match)| (entity, *in_match, true));
+    let respawns = respawned
+        .read()
+        .filter_map(|entity| player_query.get(entity).ok().map(|in_match| (entity, *in_match, false)));
+
+    for (entity, in_match, dead) in deaths.chain(respawns) {
+        if let Some(recording) = recorder.recordings.get_mut(&in_match.0) {
+            recording.update(entity, RecordedComponent::Dead(dead));
+        }
+    }
+}
+
+fn record_despawns(
+    mut recorder: ResMut<MatchRecorder>,
+    mut removed: RemovedComponents<InMatch>,
+) {
+    for entity in removed.read() {
+        for recording in recorder.recordings.values_mut() {
+            if recording.entities.remove(&entity) {
+                recording.current.despawned.push(entity.to_bits());
+            }
+        }
+    }
+}
+
+fn finish_frames(
+    mut recorder: ResMut<MatchRecorder>,
+    mut frames: EventWriter<RecordedFrame>,
+    tick_manager: Res<TickManager>,
+    match_query: Query<&MatchPhase>,
+) {
+    for (&match_entity, recording) in recorder.recordings.iter_mut() {
+        let mut frame = std::mem::take(&mut recording.current);
+        frame.tick = tick_manager.tick();
+
+        frames.send(RecordedFrame {
+            match_entity,
+            match_id: recording.match_id,
+            frame,
+            last: match_query.get(match_entity).map_or(true, |phase| *phase == MatchPhase::Lobby),
+        });
+    }
+
+    recorder.recordings.retain(|match_entity, _| match_query.get(*match_entity).is_ok_and(|phase| *phase != MatchPhase::Lobby));
+}
+
+/// Saves every round played on the server into `directory`, one file per round
+pub struct ReplayServerPlugin {
+    pub directory: PathBuf,
+}
+
+impl Plugin for ReplayServerPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<MatchRecordingPlugin>() {
+            app.add_plugins(MatchRecordingPlugin);
+        }
+
+        app.insert_resource(ReplayWriter {
+            directory: self.directory.clone(),
+            replays: HashMap::default(),
+        });
+
+        app.add_systems(FixedPostUpdate, store_frames.after(MatchRecordingSet));
+        app.add_systems(Last, save_replays_on_exit);
+    }
+}
+
+#[derive(Resource)]
+struct ReplayWriter {
+    directory: PathBuf,
+    /// Rounds in progress, by match entity
+    replays: HashMap<Entity, Replay>,
+}
+
+impl ReplayWriter {
+    fn path(&self, match_id: MatchId) -> PathBuf {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.directory.join(format!("match-{}-{timestamp}.replay", match_id.0))
+    }
+
+    /// Writes the replay on the io task pool
+    fn save(&self, replay: Replay) {
+        let path = self.path(replay.match_id);
+
+        IoTaskPool::get()
+            .spawn(async move {
+                match replay.save(&path) {
+                    Ok(()) => info!("Saved replay {}", path.display()),
+                    Err(e) => error!("Could not save replay {}: {e}", path.display()),
+                }
+            })
+            .detach();
+    }
+}
+
+fn store_frames(
+    mut writer: ResMut<ReplayWriter>,
+    mut frames: EventReader<RecordedFrame>,
+    tick_manager: Res<TickManager>,
+) {
+    for recorded in frames.read() {
+        let replay = writer.replays.entry(recorded.match_entity).or_insert_with(|| {
+            info!("Recording match {}", recorded.match_id.0);
+            Replay {
+                match_id: recorded.match_id,
+                tick_duration: tick_manager.config.tick_duration,
+                frames: Vec::new(),
+            }
+        });
+        replay.frames.push(recorded.frame.clone());
+
+        if recorded.last {
+            let replay = writer.replays.remove(&recorded.match_entity).unwrap();
+            writer.save(replay);
+        }
+    }
+}
+
+/// Rounds still in progress are saved as they are when the server stops
+fn save_replays_on_exit(
+    mut writer: ResMut<ReplayWriter>,
+    mut exit: EventReader<AppExit>,
+) {
+    if exit.read().next().is_none() {
+        return;
+    }
+
+    for replay in writer.replays.values() {
+        let path = writer.path(replay.match_id);
+        // the io task pool may not run again, so block until the file is written
+        if let Err(e) = replay.save(&path) {
+            error!("Could not save replay {}: {e}", path.display());
+        }
+    }
+    writer.replays.clear();
+}
+
+/// Local stand-ins for the entities of a recorded round, shown like remote players
+#[derive(Default)]
+pub struct ReplayEntities {
+    entities: HashMap<u64, Entity>,
+}
+
+impl ReplayEntities {
+    pub fn apply(&mut self, commands: &mut Commands, frame: &ReplayFrame) {
+        for (server_entity, component) in frame.updates.iter().cloned() {
+            let entity = *self.entities.entry(server_entity).or_insert_with(|| {
+                // remote player rendering and health bars apply to interpolated entities
+                commands.spawn((
+                    SpatialBundle::default(),
+                    Interpolated {
+                        confirmed_entity: Entity::PLACEHOLDER,
+                    },
+                )).id()
+            });
+            component.apply(&mut commands.entity(entity));
+        }
+
+        for server_entity in frame.despawned.iter() {
+            if let Some(entity) = self.entities.remove(server_entity) {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+
+    pub fn clear(&mut self, commands: &mut Commands) {
+        for (_, entity) in self.entities.drain() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Plays a recorded round offline. Recorded entities are shown like remote players, smoothed
+/// between ticks by the same visual interpolation.
+pub struct ReplayPlaybackPlugin {
+    pub replay: Replay,
+}
+
+impl Plugin for ReplayPlaybackPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ReplayPlayback {
+            replay: self.replay.clone(),
+            cursor: 0,
+            entities: ReplayEntities::default(),
+            speed: 1.,
+            inputs: 0,
+        });
+
+        app.add_systems(Startup, init_playback_text);
+        app.add_systems(FixedUpdate, advance_playback.run_if(in_state(GameState::Game)));
+        app.add_systems(Update, (
+            toggle_pause.run_if(input_just_pressed(KeyCode::Space)),
+            change_speed,
+            seek.run_if(in_state(GameState::Game)),
+            update_playback_text,
+        ));
+    }
+}
+
+#[derive(Resource)]
+struct ReplayPlayback {
+    replay: Replay,
+    /// Next frame to apply
+    cursor: usize,
+    entities: ReplayEntities,
+    speed: f32,
+    /// Input messages the players sent up to the cursor
+    inputs: usize,
+}
+
+impl ReplayPlayback {
+    fn apply_next(&mut self, commands: &mut Commands) {
+        let Some(frame) = self.replay.frames.get(self.cursor) else {
+            return;
+        };
+        self.cursor += 1;
+        self.inputs += frame.inputs.len();
+        self.entities.apply(commands, frame);
+    }
+
+    /// Jumps to `frame`, rewinding requires applying the replay again from its start
+    fn seek(&mut self, commands: &mut Commands, frame: usize) {
+        let frame = frame.min(self.replay.frames.len());
+        if frame < self.cursor {
+            self.entities.clear(commands);
+            self.cursor = 0;
+            self.inputs = 0;
+        }
+
+        while self.cursor < frame {
+            self.apply_next(commands);
+        }
+    }
+}
+
+fn advance_playback(
+    mut commands: Commands,
+    mut playback: ResMut<ReplayPlayback>,
+) {
+    playback.apply_next(&mut commands);
+}
+
+fn toggle_pause(
+    mut time: ResMut<Time<Virtual>>,
+) {
+    if time.is_paused() {
+        time.unpause();
+    } else {
+        time.pause();
+    }
+}
+
+fn change_speed(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut time: ResMut<Time<Virtual>>,
+    mut playback: ResMut<ReplayPlayback>,
+) {
+    let speed = if keys.any_just_pressed([KeyCode::Equal, KeyCode::NumpadAdd]) {
+        playback.speed * 2.
+    } else if keys.any_just_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        playback.speed / 2.
+    } else {
+        return;
+    };
+
+    playback.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    time.set_relative_speed(playback.speed);
+}
+
+fn seek(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut playback: ResMut<ReplayPlayback>,
+) {
+    let step = (SEEK_STEP.as_secs_f64() / playback.replay.tick_duration.as_secs_f64()) as usize;
+    let target = if keys.just_pressed(KeyCode::ArrowRight) {
+        playback.cursor + step
+    } else if keys.just_pressed(KeyCode::ArrowLeft) {
+        playback.cursor.saturating_sub(step)
+    } else if keys.just_pressed(KeyCode::Home) {
+        0
+    } else {
+        return;
+    };
+
+    playback.seek(&mut commands, target);
+}
+
+#[derive(Component)]
+struct PlaybackText;
+
+fn init_playback_text(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 20.,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(8.),
+            right: Val::Px(8.),
+            ..default()
+        }),
+        PlaybackText,
+    ));
+}
+
+fn update_playback_text(
+    time: Res<Time<Virtual>>,
+    playback: Res<ReplayPlayback>,
+    mut text_query: Query<&mut Text, With<PlaybackText>>,
+) {
+    let Ok(mut text) = text_query.get_single_mut() else {
+        return;
+    };
+
+    let replay = &playback.replay;
+    let elapsed = replay.tick_duration * playback.cursor as u32;
+    let state = if time.is_paused() { "paused".to_string() } else { format!("x{}", playback.speed) };
+    text.sections[0].value = format!(
+        "Replay of match {} - {} / {} - {state} - {} inputs\nSpace pause, +/- speed, arrows seek, Home restart",
+        replay.match_id.0,
+        format_duration(elapsed),
+        format_duration(replay.duration()),
+        playback.inputs,
+    );
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file in the temporary directory, removed when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("{name}-{}.replay", std::process::id())))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn replay() -> Replay {
+        Replay {
+            match_id: MatchId(7),
+            tick_duration: Duration::from_millis(16),
+            frames: (0..3)
+                .map(|tick| ReplayFrame {
+                    tick: Tick(tick),
+                    updates: vec![(42, RecordedComponent::Position(Position(Vec3::X * tick as f32)))],
+                    despawned: if tick == 2 { vec![42] } else { Vec::new() },
+                    inputs: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn replays_round_trip() {
+        let file = TempFile::new("round-trip");
+        replay().save(&file.0).unwrap();
+
+        let loaded = Replay::load(&file.0).unwrap();
+        assert_eq!(loaded.match_id, MatchId(7));
+        assert_eq!(loaded.tick_duration, Duration::from_millis(16));
+        assert_eq!(loaded.frames.len(), 3);
+        assert_eq!(loaded.frames[2].tick, Tick(2));
+        assert_eq!(loaded.frames[2].despawned, vec![42]);
+        assert!(matches!(loaded.frames[1].updates[..], [(42, RecordedComponent::Position(Position(position)))] if position == Vec3::X));
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let file = TempFile::new("version");
+        let mut bytes = REPLAY_MAGIC.to_vec();
+        bytes.extend_from_slice(&(REPLAY_VERSION - 1).to_le_bytes());
+        // the body of an older layout must not even be decoded
+        bytes.extend_from_slice(&[0xff; 16]);
+        std::fs::write(&file.0, bytes).unwrap();
+
+        let found = REPLAY_VERSION - 1;
+        assert!(matches!(Replay::load(&file.0), Err(ReplayError::Version { found: version }) if version == found));
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let file = TempFile::new("format");
+        std::fs::write(&file.0, b"not a replay").unwrap();
+        assert!(matches!(Replay::load(&file.0), Err(ReplayError::Format)));
+
+        std::fs::write(&file.0, b"RP").unwrap();
+        assert!(matches!(Replay::load(&file.0), Err(ReplayError::Format)));
+    }
+}
@@ -0,0 +1,412 @@
+use std::{path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+
+use bevy::{prelude::*, tasks::IoTaskPool, utils::{HashMap, HashSet}};
+use lightyear::prelude::{client::{ClientConnection, NetClient}, ClientId};
+use serde::{Deserialize, Serialize};
+
+use crate::{ability_framework::TriggerAbility, combat::{DamageEvent, Dead}, lobby::{InMatch, MatchId, MatchPhase, MatchRoster}, player::PlayerId, shared::GameState, team::Team};
+
+const COLUMN_WIDTH: f32 = 90.;
+const NAME_COLUMN_WIDTH: f32 = 160.;
+const FONT_SIZE: f32 = 20.;
+const OWN_ROW_COLOR: Color = Color::srgba(1., 1., 1., 0.15);
+
+/// Statistics of the current round of a match, kept by the server on the match entity so that
+/// only the match's own clients receive it
+#[derive(Component, Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Scoreboard {
+    pub players: Vec<PlayerStats>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlayerStats {
+    pub player: PlayerId,
+    pub team: Option<Team>,
+    pub kills: u32,
+    pub deaths: u32,
+    pub damage_dealt: f32,
+    pub abilities_used: u32,
+}
+
+impl PlayerStats {
+    fn new(client_id: ClientId, team: Option<Team>) -> Self {
+        Self {
+            player: PlayerId(client_id),
+            team,
+            kills: 0,
+            deaths: 0,
+            damage_dealt: 0.,
+            abilities_used: 0,
+        }
+    }
+}
+
+impl Scoreboard {
+    fn get_mut(&mut self, client_id: ClientId) -> Option<&mut PlayerStats> {
+        self.players.iter_mut().find(|stats| stats.player.0 == client_id)
+    }
+
+    /// Rows of the match, best players first
+    pub fn standings(&self) -> Vec<&PlayerStats> {
+        let mut rows: Vec<&PlayerStats> = self.players.iter().collect();
+        rows.sort_by(|a, b| {
+            b.kills
+                .cmp(&a.kills)
+                .then(a.deaths.cmp(&b.deaths))
+                .then(b.damage_dealt.total_cmp(&a.damage_dealt))
+        });
+        rows
+    }
+}
+
+/// Written to disk at the end of every round
+#[derive(Serialize)]
+struct RoundSummary {
+    match_id: u32,
+    /// Seconds since the unix epoch
+    ended_at: u64,
+    players: Vec<PlayerSummary>,
+}
+
+#[derive(Serialize)]
+struct PlayerSummary {
+    client_id: u64,
+    team: Option<u8>,
+    kills: u32,
+    deaths: u32,
+    damage_dealt: f32,
+    abilities_used: u32,
+}
+
+/// Counts the kills, deaths, damage and ability uses of every player
+pub struct ScoreboardServerPlugin {
+    /// Directory where a JSON summary of every round is written, if any
+    pub summaries: Option<PathBuf>,
+}
+
+impl Plugin for ScoreboardServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ScoreKeeper {
+            summaries: self.summaries.clone(),
+            ..default()
+        });
+
+        // after the simulation, like the match recording
+        app.add_systems(FixedPostUpdate, (
+                start_rounds,
+                count_damage,
+                count_abilities,
+                count_kills,
+                finish_rounds,
+            )
+            .chain()
+            .run_if(in_state(GameState::Game))
+        );
+    }
+}
+
+#[derive(Resource, Default)]
+struct ScoreKeeper {
+    summaries: Option<PathBuf>,
+    /// Matches whose round is being counted
+    rounds: HashSet<Entity>,
+    /// Client who last damaged each player, credited with the kill
+    last_hits: HashMap<Entity, ClientId>,
+}
+
+/// Stats of `client_id` in whichever match it plays
+fn stats_mut<'a>(scoreboards: impl Iterator<Item = Mut<'a, Scoreboard>>, client_id: ClientId) -> Option<Mut<'a, PlayerStats>> {
+    scoreboards
+        .filter(|scoreboard| scoreboard.players.iter().any(|stats| stats.player.0 == client_id))
+        .map(|scoreboard| scoreboard.map_unchanged(|scoreboard| scoreboard.get_mut(client_id).unwrap()))
+        .next()
+}
+
+/// Every round starts from a clean slate
+fn start_rounds(
+    mut commands: Commands,
+    mut keeper: ResMut<ScoreKeeper>,
+    match_query: Query<(Entity, &MatchPhase, &MatchRoster), Changed<MatchPhase>>,
+) {
+    for (match_entity, phase, roster) in match_query.iter() {
+        if !matches!(phase, MatchPhase::InRound { .. }) || !keeper.rounds.insert(match_entity) {
+            continue;
+        }
+
+        // replicated along with the match entity
+        commands.entity(match_entity).insert(Scoreboard {
+            players: roster
+                .players
+                .iter()
+                .filter(|entry| entry.playing)
+                .map(|entry| PlayerStats::new(entry.client_id, entry.team))
+                .collect(),
+        });
+    }
+}
+
+fn count_damage(
+    mut keeper: ResMut<ScoreKeeper>,
+    mut scoreboard_query: Query<&mut Scoreboard>,
+    mut events: EventReader<DamageEvent>,
+    player_query: Query<&PlayerId>,
+) {
+    for event in events.read() {
+        let Some(source) = event.source else {
+            continue;
+        };
+        // hurting yourself doesn't count
+        if player_query.get(event.target).is_ok_and(|target| target.0 == source) {
+            continue;
+        }
+
+        keeper.last_hits.insert(event.target, source);
+        if let Some(mut stats) = stats_mut(scoreboard_query.iter_mut(), source) {
+            stats.damage_dealt += event.amount.0;
+        }
+    }
+}
+
+fn count_abilities(
+    mut scoreboard_query: Query<&mut Scoreboard>,
+    mut triggers: EventReader<TriggerAbility>,
+    player_query: Query<&PlayerId>,
+) {
+    for trigger in triggers.read() {
+        let Ok(player_id) = player_query.get(trigger.source) else {
+            continue;
+        };
+        if let Some(mut stats) = stats_mut(scoreboard_query.iter_mut(), player_id.0) {
+            stats.abilities_used += 1;
+        }
+    }
+}
+
+fn count_kills(
+    mut keeper: ResMut<ScoreKeeper>,
+    mut scoreboard_query: Query<&mut Scoreboard>,
+    dead_query: Query<(Entity, &PlayerId), Added<Dead>>,
+) {
+    for (entity, player_id) in dead_query.iter() {
+        if let Some(mut stats) = stats_mut(scoreboard_query.iter_mut(), player_id.0) {
+            stats.deaths += 1;
+        }
+
+        let killer = keeper.last_hits.remove(&entity);
+        if let Some(mut stats) = killer.and_then(|killer| stats_mut(scoreboard_query.iter_mut(), killer)) {
+            stats.kills += 1;
+        }
+    }
+}
+
+/// Writes the summary of rounds that ended, their rows stay on the scoreboard until the next round
+fn finish_rounds(
+    mut keeper: ResMut<ScoreKeeper>,
+    match_query: Query<(&MatchId, &MatchPhase, Option<&Scoreboard>)>,
+    player_query: Query<(), With<InMatch>>,
+) {
+    let keeper = keeper.as_mut();
+    keeper.last_hits.retain(|entity, _| player_query.contains(*entity));
+
+    let ended: Vec<Entity> = keeper
+        .rounds
+        .iter()
+        .copied()
+        .filter(|match_entity| !match_query.get(*match_entity).is_ok_and(|(_, phase, _)| matches!(phase, MatchPhase::InRound { .. })))
+        .collect();
+    for match_entity in ended {
+        keeper.rounds.remove(&match_entity);
+
+        // closed matches take their scoreboard with them
+        let (Some(directory), Ok((match_id, _, Some(scoreboard)))) = (&keeper.summaries, match_query.get(match_entity)) else {
+            continue;
+        };
+        save_summary(directory, *match_id, scoreboard);
+    }
+}
+
+fn save_summary(directory: &Path, match_id: MatchId, scoreboard: &Scoreboard) {
+    let ended_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let path = directory.join(format!("match-{}-{ended_at}.json", match_id.0));
+
+    let summary = RoundSummary {
+        match_id: match_id.0,
+        ended_at,
+        players: scoreboard
+            .standings()
+            .into_iter()
+            .map(|stats| PlayerSummary {
+                client_id: stats.player.0.to_bits(),
+                team: stats.team.map(|team| team.0),
+                kills: stats.kills,
+                deaths: stats.deaths,
+                damage_dealt: stats.damage_dealt,
+                abilities_used: stats.abilities_used,
+            })
+            .collect(),
+    };
+    let directory = directory.to_path_buf();
+    let json = match serde_json::to_vec_pretty(&summary) {
+        Ok(json) => json,
+        Err(e) => {
+            error!("Could not serialize the summary of match {}: {e}", match_id.0);
+            return;
+        },
+    };
+
+    IoTaskPool::get()
+        .spawn(async move {
+            let result = std::fs::create_dir_all(&directory).and_then(|()| std::fs::write(&path, json));
+            match result {
+                Ok(()) => info!("Saved round summary {}", path.display()),
+                Err(e) => error!("Could not save round summary {}: {e}", path.display()),
+            }
+        })
+        .detach();
+}
+
+/// Overlay listing the players of the match while Tab is held
+pub struct ScoreboardClientPlugin;
+
+impl Plugin for ScoreboardClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, init_scoreboard_overlay);
+        app.add_systems(Update, update_scoreboard_overlay);
+    }
+}
+
+#[derive(Component)]
+struct ScoreboardOverlay;
+
+fn init_scoreboard_overlay(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                align_self: AlignSelf::Center,
+                justify_self: JustifySelf::Center,
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(16.)),
+                row_gap: Val::Px(4.),
+                ..default()
+            },
+            background_color: Color::srgba(0., 0., 0., 0.75).into(),
+            visibility: Visibility::Hidden,
+            z_index: ZIndex::Global(10),
+            ..default()
+        },
+        ScoreboardOverlay,
+    ));
+}
+
+fn update_scoreboard_overlay(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    connection: Option<Res<ClientConnection>>,
+    match_query: Query<(Ref<Scoreboard>, &MatchRoster)>,
+    mut overlay_query: Query<(Entity, &mut Visibility), With<ScoreboardOverlay>>,
+) {
+    let Ok((overlay, mut visibility)) = overlay_query.get_single_mut() else {
+        return;
+    };
+    if !keys.pressed(KeyCode::Tab) {
+        visibility.set_if_neq(Visibility::Hidden);
+        return;
+    }
+    let opened = visibility.set_if_neq(Visibility::Inherited);
+
+    let Some(connection) = connection else {
+        return;
+    };
+
+    let own_id = connection.id();
+    // spectators have no row but still see their match
+    let Some((scoreboard, _)) = match_query
+        .iter()
+        .find(|(_, roster)| roster.get(own_id).is_some() || roster.is_spectating(own_id))
+    else {
+        return;
+    };
+    if !opened && !scoreboard.is_changed() {
+        return;
+    }
+
+    commands.entity(overlay).despawn_descendants().with_children(|overlay| {
+        spawn_row(overlay, ["Player", "Kills", "Deaths", "Damage", "Abilities"].map(String::from), Color::WHITE, false);
+
+        for stats in scoreboard.standings() {
+            let columns = [
+                format!("Player {}", stats.player.0.to_bits()),
+                stats.kills.to_string(),
+                stats.deaths.to_string(),
+                format!("{:.0}", stats.damage_dealt),
+                stats.abilities_used.to_string(),
+            ];
+            let color = stats.team.map_or(Color::WHITE, |team| team.color());
+            spawn_row(overlay, columns, color, stats.player.0 == own_id);
+        }
+    });
+}
+
+fn spawn_row(parent: &mut ChildBuilder, columns: [String; 5], name_color: Color, highlighted: bool) {
+    parent.spawn(NodeBundle {
+        style: Style {
+            flex_direction: FlexDirection::Row,
+            ..default()
+        },
+        background_color: if highlighted { OWN_ROW_COLOR.into() } else { Color::NONE.into() },
+        ..default()
+    })
+    .with_children(|row| {
+        for (index, column) in columns.into_iter().enumerate() {
+            let (width, color) = if index == 0 { (NAME_COLUMN_WIDTH, name_color) } else { (COLUMN_WIDTH, Color::WHITE) };
+            row.spawn(TextBundle::from_section(
+                column,
+                TextStyle {
+                    font_size: FONT_SIZE,
+                    color,
+                    ..default()
+                },
+            ).with_style(Style {
+                width: Val::Px(width),
+                ..default()
+            }));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(id: u64, kills: u32, deaths: u32, damage_dealt: f32) -> PlayerStats {
+        PlayerStats {
+            kills,
+            deaths,
+            damage_dealt,
+            ..PlayerStats::new(ClientId::Netcode(id), None)
+        }
+    }
+
+    fn order(scoreboard: &Scoreboard) -> Vec<u64> {
+        scoreboard.standings().into_iter().map(|stats| stats.player.0.to_bits()).collect()
+    }
+
+    #[test]
+    fn most_kills_come_first() {
+        let scoreboard = Scoreboard {
+            players: vec![stats(1, 0, 0, 0.), stats(2, 3, 5, 0.), stats(3, 1, 0, 0.)],
+        };
+        assert_eq!(order(&scoreboard), vec![2, 3, 1]);
+    }
+
+    #[test]
+    fn ties_are_broken_by_deaths_then_damage() {
+        let scoreboard = Scoreboard {
+            players: vec![stats(1, 2, 1, 10.), stats(2, 2, 0, 5.), stats(3, 2, 1, 40.)],
+        };
+        assert_eq!(order(&scoreboard), vec![2, 3, 1]);
+    }
+}
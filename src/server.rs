@@ -1,14 +1,23 @@
-use std::time::Duration;
+use std::{path::PathBuf, time::Duration};
 
-use bevy::prelude::*;
+use avian3d::prelude::{CollisionLayers, Position};
+use bevy::{ecs::system::SystemParam, prelude::*};
 use leafwing_input_manager::prelude::ActionState;
-use lightyear::prelude::{server::{AuthorityPeer, ControlledBy, Replicate, ServerCommands, ServerReplicationSet, SyncTarget}, InputChannel, InputMessage, MainSet, NetworkTarget, OverrideTargetComponent, PrePredicted, Replicated, ReplicationTarget};
+use lightyear::prelude::{server::{AuthorityPeer, ControlledBy, Lifetime, Replicate, ServerCommands, ServerReplicationSet, SyncTarget}, ClientId, InputChannel, InputMessage, MainSet, NetworkRelevanceMode, NetworkTarget, PreSpawnedPlayerObject, Replicated, ReplicationTarget, TickManager};
 use lightyear::server::{connection::ConnectionManager, events::MessageEvent};
 
-use crate::{abilities::Dodge, ability_framework::{ability_map::AbilityMap, pools::{life::LifePool, mana::ManaPool}, AbilityBundle, AbilityCharge, AbilityFrameworkServerPlugin, AbilityState, PredictedAbility, TriggerAbility}, physics::{CharacterQuery, PhysicsBundle}, player::{shared_player_movement, CursorPosition, MoveSpeed, PlayerActions, PlayerId, REPLICATION_GROUP}, shared::FixedSet};
+use crate::{abilities::definition::{reload_ability_definitions, AbilityDefinition, AbilityDefinitionHandle, AbilityEffect, AbilityEffects}, ability_framework::{ability_map::AbilityMap, pipeline::{AbilityFailed, AbilityFailedMessage, AbilityPipelinePlugin, AbilityPipelineSet}, status_effect::StatusEffects, AbilityFrameworkServerPlugin, PredictedAbility, TriggerAbility}, assets::AbilityAssets, bots::{Bot, BotServerPlugin}, chat::ChatServerPlugin, combat::{CombatServerPlugin, Dead}, lobby::{InMatch, LobbyServerPlugin, MatchRoster, MatchScope}, physics::CharacterQuery, player::{shared_player_movement, CursorPosition, MoveSpeed, PlayerActions, PlayerBundle, PlayerId, REPLICATION_GROUP}, projectile::{projectile_hash, ProjectileBundle, ProjectileHit, ProjectileLifetime, SimulatedProjectile}, protocol::FeedbackChannel, reconnect::ReconnectServerPlugin, replay::{MatchRecorder, ReplayServerPlugin}, scoreboard::ScoreboardServerPlugin, settings::{ChatSettings, LobbySettings, ValidationSettings}, shared::FixedSet, spectator::SpectatorServerPlugin, team::{Team, TeamRules}, validation::ClientValidationPlugin};
 
 pub struct OverheatServerPlugin {
     pub predict_all: bool,
+    pub lobby: LobbySettings,
+    pub chat: ChatSettings,
+    pub validation: ValidationSettings,
+    pub bots: usize,
+    /// Directory where rounds are recorded, if any
+    pub replays: Option<PathBuf>,
+    /// Directory where round statistics are written, if any
+    pub stats: Option<PathBuf>,
 }
 
 #[derive(Resource)]
@@ -16,10 +25,51 @@ pub struct Global {
     predict_all: bool,
 }
 
+impl Global {
+    /// Entities of a player are predicted by its client and interpolated by everyone else, spectators
+    /// included. With `predict_all` every human player of the round predicts them.
+    fn sync_target(&self, client_id: ClientId, players: &[ClientId]) -> SyncTarget {
+        if self.predict_all {
+            SyncTarget {
+                prediction: NetworkTarget::Only(players.to_vec()),
+                interpolation: NetworkTarget::AllExcept(players.to_vec()),
+            }
+        } else {
+            SyncTarget {
+                prediction: NetworkTarget::Single(client_id),
+                interpolation: NetworkTarget::AllExceptSingle(client_id),
+            }
+        }
+    }
+}
+
 impl Plugin for OverheatServerPlugin {
     fn build(&self, app: &mut App) {
         app
         .add_plugins(AbilityFrameworkServerPlugin)
+        .add_plugins(AbilityPipelinePlugin::<PlayerActions, Without<Dead>>::default())
+        .add_plugins(CombatServerPlugin)
+        .add_plugins(LobbyServerPlugin {
+            settings: self.lobby.clone(),
+        })
+        .add_plugins(ChatServerPlugin {
+            settings: self.chat.clone(),
+        })
+        .add_plugins(ClientValidationPlugin {
+            settings: self.validation.clone(),
+        })
+        .add_plugins(ScoreboardServerPlugin {
+            summaries: self.stats.clone(),
+        })
+        .add_plugins(BotServerPlugin {
+            count: self.bots,
+        })
+        .add_plugins(SpectatorServerPlugin {
+            delay: Duration::from_millis(self.lobby.spectator_delay_ms),
+        })
+        .add_plugins(ReconnectServerPlugin {
+            grace: Duration::from_secs(self.lobby.reconnect_grace_seconds as u64),
+        })
         .insert_resource(Global {
             predict_all: self.predict_all
         })
@@ -30,20 +80,29 @@ impl Plugin for OverheatServerPlugin {
                 .after(MainSet::EmitEvents)
         )
         .add_systems(
-            PreUpdate, (
-                replicate_players,
-                replicate_cursors,
-            ).in_set(ServerReplicationSet::ClientReplication)
+            PreUpdate,
+            replicate_cursors
+                .in_set(ServerReplicationSet::ClientReplication)
         )
+        .add_systems(Update, (reload_ability_definitions, update_cursor_targets))
         .add_systems(
             FixedUpdate, (
                 movement,
-                test_handle_abilities,
-                start_charging_abilities,
-                trigger_bound_abilities,
+                (
+                    test_handle_abilities,
+                    send_ability_failures,
+                    spawn_projectiles,
+                ).after(AbilityPipelineSet),
+                despawn_projectiles,
             )
             .in_set(FixedSet::Main),
         );
+
+        if let Some(directory) = &self.replays {
+            app.add_plugins(ReplayServerPlugin {
+                directory: directory.clone(),
+            });
+        }
     }
 }
 
@@ -71,77 +130,114 @@ fn init(
 
 }
 
+/// Forwards inputs to the other players of the sender's match, who predict its player
 fn replicate_inputs(
     mut connection: ResMut<ConnectionManager>,
     mut input_events: ResMut<Events<MessageEvent<InputMessage<PlayerActions>>>>,
+    mut recorder: Option<ResMut<MatchRecorder>>,
+    match_query: Query<(Entity, &MatchRoster)>,
 ) {
     for mut event in input_events.drain() {
         let client_id = *event.context();
+        let Some((match_entity, roster)) = match_query.iter().find(|(_, roster)| roster.get(client_id).is_some()) else {
+            continue;
+        };
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.record_input(match_entity, client_id, event.message.clone());
+        }
 
+        let others = roster
+            .players
+            .iter()
+            .filter(|entry| entry.playing && !entry.bot && entry.client_id != client_id)
+            .map(|entry| entry.client_id)
+            .collect();
         connection
             .send_message_to_target::<InputChannel, _>(
                 &mut event.message,
-                NetworkTarget::AllExceptSingle(client_id)
+                NetworkTarget::Only(others)
             )
             .unwrap()
     }
 }
 
-fn replicate_players(
-    mut commands: Commands,
-    global: Res<Global>,
-    query: Query<(Entity, &Replicated), (Added<Replicated>, With<PlayerId>)>,
-) {
-    for (entity, replicated) in query.iter() {
-        let client_id = replicated.client_id();
-        info!("received player spawn event from client {client_id:?}");
+/// Spawns server-owned players, predicted by their controlling client
+#[derive(SystemParam)]
+pub struct PlayerSpawner<'w, 's> {
+    commands: Commands<'w, 's>,
+    global: Res<'w, Global>,
+    ability_assets: Res<'w, AbilityAssets>,
+    definitions: Res<'w, Assets<AbilityDefinition>>,
+}
 
-        if let Some(e) = commands.get_entity(entity) {
-            let player = e.id();
-            drop(e);
+impl PlayerSpawner<'_, '_> {
+    /// Spawns the player of `client_id` along with the abilities bound by default, all scoped to a match.
+    /// `players` are the human players of the round.
+    pub fn spawn(&mut self, client_id: ClientId, position: Vec3, scope: MatchScope, players: &[ClientId]) -> Entity {
+        info!("Spawning player for client {client_id:?}");
 
-            let mut sync_target = SyncTarget::default();
+        let sync_target = self.global.sync_target(client_id, players);
 
-            if global.predict_all {
-                sync_target.prediction = NetworkTarget::All;
-            } else {
-                sync_target.interpolation = NetworkTarget::AllExceptSingle(client_id);
-            }
+        let controlled_by = ControlledBy {
+            target: NetworkTarget::Single(client_id),
+            // parked or despawned by `ReconnectServerPlugin` when the client disconnects
+            lifetime: Lifetime::Persistent,
+        };
+        self.spawn_with(client_id, position, scope, sync_target, controlled_by)
+    }
 
-            let replicate = Replicate {
-                sync: sync_target.clone(),
-                controlled_by: ControlledBy {
-                    target: NetworkTarget::Single(client_id),
-                    ..default()
-                },
-                group: REPLICATION_GROUP,
-                ..default()
+    /// Spawns a server-controlled player. Clients never receive its inputs so they all interpolate it.
+    pub fn spawn_bot(&mut self, client_id: ClientId, position: Vec3, scope: MatchScope) -> Entity {
+        info!("Spawning bot {client_id:?}");
+
+        let sync_target = SyncTarget {
+            interpolation: NetworkTarget::All,
+            ..default()
+        };
+        let entity = self.spawn_with(client_id, position, scope, sync_target, ControlledBy::default());
+        self.commands.entity(entity).insert(Bot::default());
+        entity
+    }
+
+    fn spawn_with(&mut self, client_id: ClientId, position: Vec3, scope: MatchScope, sync_target: SyncTarget, controlled_by: ControlledBy) -> Entity {
+        let mut ability_map = AbilityMap::new();
+        for handle in &self.ability_assets.definitions {
+            let Some(definition) = self.definitions.get(handle) else {
+                continue;
+            };
+            let Some(binding) = definition.binding else {
+                continue;
             };
-            commands.entity(player).insert((
-                replicate,
-                OverrideTargetComponent::<PrePredicted>::new(NetworkTarget::Single(client_id)),
-                PhysicsBundle::player(),
-            ));
 
-            // #todo: temporarily set up some default abilities for testing
-            let dodge_ability = commands.spawn((
-                AbilityBundle::new(10., 0., Duration::from_secs_f32(2.)),
+            let ability = self.commands.spawn((
+                definition.bundle(),
+                AbilityDefinitionHandle(handle.clone()),
                 Replicate {
                     sync: sync_target.clone(),
+                    relevance_mode: NetworkRelevanceMode::InterestManagement,
                     group: REPLICATION_GROUP,
                     ..default()
                 },
-                Dodge,
-                AbilityCharge::default(),
                 PredictedAbility,
-                Name::from("DodgeAbility"),
+                scope.in_match,
+                Name::from(definition.name.clone()),
             )).id();
 
-            let mut ability_map = AbilityMap::new();
-            ability_map.add_binding(PlayerActions::Dodge, dodge_ability);
-
-            commands.entity(player).insert(ability_map);
+            ability_map.add_binding(binding, ability);
         }
+
+        self.commands.spawn((
+            PlayerBundle::new(client_id, position),
+            ability_map,
+            Replicate {
+                sync: sync_target,
+                controlled_by,
+                relevance_mode: NetworkRelevanceMode::InterestManagement,
+                group: REPLICATION_GROUP,
+                ..default()
+            },
+            scope,
+        )).id()
     }
 }
 
@@ -174,63 +270,160 @@ fn replicate_cursors(
     }
 }
 
+/// Cursors show where a player aims, so only its teammates and the match's spectators receive them.
+/// Without teams every player of the match does.
+fn update_cursor_targets(
+    match_query: Query<&MatchRoster>,
+    changed_query: Query<(), Changed<MatchRoster>>,
+    mut cursor_query: Query<(&Replicated, &mut ReplicationTarget), With<CursorPosition>>,
+) {
+    let rosters_changed = !changed_query.is_empty();
+
+    for (replicated, mut target) in cursor_query.iter_mut() {
+        if !rosters_changed && !target.is_added() {
+            continue;
+        }
+
+        let client_id = replicated.client_id();
+        let clients = match match_query.iter().find(|roster| roster.get(client_id).is_some()) {
+            Some(roster) => {
+                let players = match roster.team_of(client_id) {
+                    Some(_) => roster.teammates(client_id),
+                    None => roster.human_players(),
+                };
+                players
+                    .into_iter()
+                    .filter(|player| *player != client_id)
+                    .chain(roster.spectators.iter().copied())
+                    .collect()
+            },
+            None => Vec::new(),
+        };
+
+        target.set_if_neq(ReplicationTarget {
+            target: NetworkTarget::Only(clients),
+        });
+    }
+}
+
 fn movement(
     time: Res<Time>,
-    mut query: Query<(CharacterQuery, &MoveSpeed, &ActionState<PlayerActions>)>,
+    mut query: Query<(CharacterQuery, &MoveSpeed, &ActionState<PlayerActions>, &StatusEffects), Without<Dead>>,
 ) {
-    for (mut character, move_speed, action_state) in &mut query {
-        shared_player_movement(&time, move_speed, action_state, &mut character);
+    for (mut character, move_speed, action_state, status_effects) in &mut query {
+        shared_player_movement(&time, move_speed, status_effects, action_state, &mut character);
     }
 }
 
-fn start_charging_abilities(
-    action_query: Query<(&ActionState<PlayerActions>, &AbilityMap<PlayerActions>, &LifePool, &ManaPool)>,
-    mut ability_query: Query<(AbilityState, &mut AbilityCharge)>,
+fn test_handle_abilities(
+    mut triggers: EventReader<TriggerAbility>,
 ) {
-    for (actions, map, life, mana) in action_query.iter() {
-        for pressed in actions.get_just_pressed() {
-            if let Ok(ability_entity) = map.mapped(pressed) {
-                if let Ok((ability_state, mut charge)) = ability_query.get_mut(ability_entity) {
-                    if ability_state.ready(mana, life).is_ok() {
-                        charge.start();
-                    }
-                }
-            }
-        }
+    for trigger in triggers.read() {
+        info!("Triggered ability {:?}", trigger.ability);
     }
 }
 
-fn trigger_bound_abilities(
-    mut action_query: Query<(Entity, &ActionState<PlayerActions>, &AbilityMap<PlayerActions>, &mut LifePool, &mut ManaPool)>,
-    mut triggers: EventWriter<TriggerAbility>,
-    mut ability_query: Query<AbilityState>,
+/// Lets the owning client know why the server rejected one of its abilities
+fn send_ability_failures(
+    mut connection: ResMut<ConnectionManager>,
+    mut failures: EventReader<AbilityFailed>,
+    // bots have no connection to notify
+    player_query: Query<&PlayerId, Without<Bot>>,
 ) {
-    for (entity, actions, map, mut life, mut mana) in action_query.iter_mut() {
-        for pressed in actions.get_just_released() {
-            if let Ok(ability_entity) = map.mapped(pressed) {
-                if let Ok(mut ability) = ability_query.get_mut(ability_entity) {
-
-                    match ability.trigger(&mut mana, &mut life) {
-                        Ok(()) => {
-                            triggers.send(TriggerAbility {
-                                source: entity,
-                                ability: ability_entity,
-                            });
-                        },
-                        Err(e) => {
-                            info!("Failed triggering ability: {e:?}");
-                        },
-                    }
-                }
-            }
+    for failure in failures.read() {
+        let Ok(player_id) = player_query.get(failure.source) else {
+            continue;
+        };
+
+        let mut message = AbilityFailedMessage {
+            source: failure.source,
+            ability: failure.ability,
+            reason: failure.reason,
+        };
+        if let Err(e) = connection.send_message::<FeedbackChannel, _>(player_id.0, &mut message) {
+            error!("Could not send ability failure to {:?}: {e:?}", player_id.0);
         }
     }
 }
 
-fn test_handle_abilities(
+fn spawn_projectiles(
+    mut commands: Commands,
+    global: Res<Global>,
+    tick_manager: Res<TickManager>,
     mut triggers: EventReader<TriggerAbility>,
+    ability_query: Query<&AbilityEffects>,
+    player_query: Query<(&PlayerId, &Position, &InMatch, &CollisionLayers, Option<&Bot>, Option<&Team>)>,
+    match_query: Query<(&MatchRoster, &TeamRules)>,
+    cursor_query: Query<(&CursorPosition, &Replicated)>,
 ) {
     for trigger in triggers.read() {
-        info!("Triggered ability {:?}", trigger.ability);
+        let Ok(effects) = ability_query.get(trigger.ability) else {
+            continue;
+        };
+        let Ok((player_id, position, in_match, layers, bot, team)) = player_query.get(trigger.source) else {
+            continue;
+        };
+        let client_id = player_id.0;
+        let aim = match bot {
+            Some(bot) => bot.aim,
+            None => {
+                let Some((cursor, _)) = cursor_query.iter().find(|(_, replicated)| replicated.client_id() == client_id) else {
+                    continue;
+                };
+                cursor.0
+            },
+        };
+
+        let Ok((roster, rules)) = match_query.get(in_match.0) else {
+            continue;
+        };
+        let players = roster.human_players();
+
+        for (slot, effect) in effects.iter().enumerate() {
+            let AbilityEffect::Projectile { speed, radius, damage, lifetime, on_hit, targets } = effect else {
+                continue;
+            };
+
+            let sync_target = match bot {
+                Some(_) => SyncTarget {
+                    interpolation: NetworkTarget::All,
+                    ..default()
+                },
+                None => global.sync_target(client_id, &players),
+            };
+
+            let mut projectile = commands.spawn((
+                ProjectileBundle::new(client_id, position.0, aim, *speed, *radius, *damage, Duration::from_secs_f32(*lifetime))
+                    .with_on_hit(on_hit.clone())
+                    .with_targets(team.copied(), targets.with_friendly_fire(rules.friendly_fire)),
+                Replicate {
+                    sync: sync_target,
+                    relevance_mode: NetworkRelevanceMode::InterestManagement,
+                    ..default()
+                },
+                MatchScope {
+                    in_match: *in_match,
+                    layers: *layers,
+                },
+            ));
+            // no client predicts the projectiles of bots
+            if bot.is_none() {
+                projectile.insert(PreSpawnedPlayerObject::new(projectile_hash(client_id, tick_manager.tick(), slot)));
+            }
+        }
+    }
+}
+
+fn despawn_projectiles(
+    mut commands: Commands,
+    mut hits: EventReader<ProjectileHit>,
+    query: Query<(Entity, &ProjectileLifetime), SimulatedProjectile>,
+) {
+    let hit_projectiles: Vec<Entity> = hits.read().map(|hit| hit.projectile).collect();
+
+    for (entity, lifetime) in query.iter() {
+        if lifetime.0.is_zero() || hit_projectiles.contains(&entity) {
+            commands.entity(entity).despawn();
+        }
     }
-}
\ No newline at end of file
+}
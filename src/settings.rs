@@ -1,11 +1,84 @@
-use std::{net::{Ipv4Addr, SocketAddr}, time::Duration};
+use std::{net::{Ipv4Addr, SocketAddr}, path::{Path, PathBuf}, str::FromStr, sync::Arc, time::Duration};
 
-use bevy::{asset::ron, utils::default};
-use lightyear::prelude::{client::{self, Authentication, SocketConfig, SteamConfig}, server, CompressionConfig, LinkConditionerConfig};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use bevy::{asset::ron, prelude::Resource, utils::{default, HashMap}};
+use clap::Args;
+use derive_more::derive::{Display, Error};
+use lightyear::prelude::{client::{self, Authentication, SocketConfig, SteamConfig}, server, CompressionConfig, Key, LinkConditionerConfig};
+use serde::{Deserialize, Serialize};
 
-pub fn read_settings<T: DeserializeOwned>(settings_str: &str) -> T {
-    ron::de::from_str::<T>(settings_str).expect("Error deserializing the settings file")
+use crate::{auth::{client_authentication, AuthConnectionRequestHandler, AuthError, AuthUserData}, lobby::MatchId, team::{Team, TeamRules}};
+
+/// Used when no settings file is given on the command line
+const DEFAULT_SETTINGS: &str = include_str!("../assets/settings.ron");
+
+#[derive(Debug, Display, Error)]
+pub enum SettingsError {
+    #[display("could not read settings file {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[display("could not parse settings: {_0}")]
+    Parse(ron::error::SpannedError),
+    #[display("invalid settings:\n  {}", _0.join("\n  "))]
+    Invalid(#[error(not(source))] Vec<String>),
+}
+
+/// Reads the settings file at `path`, or the embedded default settings, then applies `overrides` and validates the result
+pub fn load_settings(path: Option<&Path>, overrides: &SettingsOverrides) -> Result<Settings, SettingsError> {
+    let settings_str = match path {
+        Some(path) => std::fs::read_to_string(path).map_err(|source| SettingsError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?,
+        None => DEFAULT_SETTINGS.to_string(),
+    };
+
+    let mut settings = ron::de::from_str::<Settings>(&settings_str).map_err(SettingsError::Parse)?;
+    settings.apply_overrides(overrides);
+    settings.validate()?;
+
+    Ok(settings)
+}
+
+/// Command line overrides for individual settings, each can also be set through its environment variable
+#[derive(Args, Debug, Default, PartialEq)]
+pub struct SettingsOverrides {
+    /// Port the server listens on for UDP, and the port clients connect to
+    #[arg(long, env = "OVERHEAT_SERVER_PORT", global = true)]
+    pub server_port: Option<u16>,
+    /// Address clients connect to
+    #[arg(long, env = "OVERHEAT_SERVER_ADDR", global = true)]
+    pub server_addr: Option<Ipv4Addr>,
+    #[arg(long, env = "OVERHEAT_CLIENT_PORT", global = true)]
+    pub client_port: Option<u16>,
+    /// Name sent to the auth service
+    #[arg(long, env = "OVERHEAT_USERNAME", global = true)]
+    pub username: Option<String>,
+    /// Client link conditioner, e.g. `latency=75,jitter=10,loss=0.02`, or `none` to disable it
+    #[arg(long, env = "OVERHEAT_CONDITIONER", global = true)]
+    pub conditioner: Option<ConditionerArg>,
+    /// Server link conditioner, same format as `--conditioner`
+    #[arg(long, env = "OVERHEAT_SERVER_CONDITIONER", global = true)]
+    pub server_conditioner: Option<ConditionerArg>,
+    #[arg(long, env = "OVERHEAT_PREDICT_ALL", global = true)]
+    pub predict_all: Option<bool>,
+    #[arg(long, env = "OVERHEAT_INPUT_DELAY_TICKS", global = true)]
+    pub input_delay_ticks: Option<u16>,
+    #[arg(long, env = "OVERHEAT_HEADLESS", global = true)]
+    pub headless: Option<bool>,
+    /// Number of bots the server adds to its matches
+    #[arg(long, env = "OVERHEAT_BOTS", global = true)]
+    pub bots: Option<usize>,
+    /// Directory the server writes match replays to
+    #[arg(long, env = "OVERHEAT_REPLAYS", global = true)]
+    pub replays: Option<PathBuf>,
+    /// Directory the server writes round statistics to
+    #[arg(long, env = "OVERHEAT_STATS", global = true)]
+    pub stats: Option<PathBuf>,
+    /// Match to join once connected
+    #[arg(long = "match", env = "OVERHEAT_MATCH", global = true)]
+    pub preferred_match: Option<u32>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -13,6 +86,15 @@ pub struct Settings {
     pub server: ServerSettings,
     pub client: ClientSettings,
     pub shared: SharedSettings,
+    /// Remote clients need the auth service to connect, without it only a host server's own client can play
+    #[serde(default)]
+    pub auth: Option<AuthSettings>,
+    #[serde(default)]
+    pub lobby: LobbySettings,
+    #[serde(default)]
+    pub chat: ChatSettings,
+    #[serde(default)]
+    pub validation: ValidationSettings,
 
     pub predict_all: bool,
     pub input_delay_ticks: u16,
@@ -20,21 +102,329 @@ pub struct Settings {
     pub server_replication_send_interval: u64,
 }
 
+impl Settings {
+    fn apply_overrides(&mut self, overrides: &SettingsOverrides) {
+        if let Some(port) = overrides.server_port {
+            self.client.server_port = port;
+            for transport in self.server.transports.iter_mut() {
+                if let ServerTransports::Udp { local_port } = transport {
+                    *local_port = port;
+                }
+            }
+        }
+        if let Some(addr) = overrides.server_addr {
+            self.client.server_addr = addr;
+        }
+        if let Some(port) = overrides.client_port {
+            self.client.client_port = port;
+        }
+        if let Some(username) = &overrides.username {
+            self.client.username = username.clone();
+        }
+        if let Some(ConditionerArg(conditioner)) = &overrides.conditioner {
+            self.client.conditioner = conditioner.clone();
+        }
+        if let Some(ConditionerArg(conditioner)) = &overrides.server_conditioner {
+            self.server.conditioner = conditioner.clone();
+        }
+        if let Some(predict_all) = overrides.predict_all {
+            self.predict_all = predict_all;
+        }
+        if let Some(ticks) = overrides.input_delay_ticks {
+            self.input_delay_ticks = ticks;
+        }
+        if let Some(headless) = overrides.headless {
+            self.server.headless = headless;
+        }
+        if let Some(bots) = overrides.bots {
+            self.server.bots = bots;
+        }
+        if let Some(replays) = &overrides.replays {
+            self.server.replays = Some(replays.clone());
+        }
+        if let Some(stats) = &overrides.stats {
+            self.server.stats = Some(stats.clone());
+        }
+        if let Some(preferred) = overrides.preferred_match {
+            self.client.preferred_match = Some(preferred);
+        }
+    }
+
+    /// Reports every invalid value at once rather than stopping at the first one
+    fn validate(&self) -> Result<(), SettingsError> {
+        let mut problems = Vec::new();
+
+        if self.server.transports.is_empty() {
+            problems.push("server.transports must contain at least one transport".to_string());
+        }
+        for transport in &self.server.transports {
+            if let ServerTransports::Udp { local_port: 0 } = transport {
+                problems.push("server.transports: Udp local_port must not be 0".to_string());
+            }
+        }
+        if self.client.server_port == 0 {
+            problems.push("client.server_port must not be 0".to_string());
+        }
+        if !self.correction_ticks_factor.is_finite() || self.correction_ticks_factor <= 0. {
+            problems.push(format!("correction_ticks_factor must be positive, got {}", self.correction_ticks_factor));
+        }
+        if self.server_replication_send_interval == 0 {
+            problems.push("server_replication_send_interval must be greater than 0".to_string());
+        }
+
+        for (name, conditioner) in [("client", &self.client.conditioner), ("server", &self.server.conditioner)] {
+            if let Some(conditioner) = conditioner {
+                if !(0. ..=1.).contains(&conditioner.packet_loss) {
+                    problems.push(format!("{name}.conditioner.packet_loss must be between 0 and 1, got {}", conditioner.packet_loss));
+                }
+                // lightyear adds up to `jitter` either way, latencies below zero would be clamped
+                if conditioner.jitter_ms > conditioner.latency_ms {
+                    problems.push(format!(
+                        "{name}.conditioner.jitter_ms must not exceed latency_ms ({}), got {}",
+                        conditioner.latency_ms, conditioner.jitter_ms
+                    ));
+                }
+            }
+        }
+
+        if self.lobby.min_players == 0 {
+            problems.push("lobby.min_players must be at least 1".to_string());
+        }
+        if self.lobby.max_players_per_match < self.lobby.min_players {
+            problems.push(format!("lobby.max_players_per_match must be at least min_players ({})", self.lobby.min_players));
+        }
+        if !(1..=MatchId::MAX).contains(&self.lobby.max_matches) {
+            problems.push(format!("lobby.max_matches must be between 1 and {}, got {}", MatchId::MAX, self.lobby.max_matches));
+        }
+        let max_players = self.lobby.max_matches as usize * self.lobby.max_players_per_match;
+        if self.server.bots > max_players {
+            problems.push(format!("server.bots must fit in the matches, at most {max_players}, got {}", self.server.bots));
+        }
+        if let Some(preferred) = self.client.preferred_match {
+            if preferred >= MatchId::MAX {
+                problems.push(format!("client.preferred_match must be less than {}, got {preferred}", MatchId::MAX));
+            }
+        }
+        for rules in std::iter::once(&self.lobby.teams).chain(self.lobby.match_teams.values()) {
+            if rules.teams > Team::MAX {
+                problems.push(format!("lobby.teams and lobby.match_teams allow at most {} teams, got {}", Team::MAX, rules.teams));
+            }
+        }
+
+        if self.chat.max_length == 0 {
+            problems.push("chat.max_length must be at least 1".to_string());
+        }
+        if self.chat.burst == 0 || !self.chat.window_seconds.is_finite() || self.chat.window_seconds <= 0. {
+            problems.push("chat.burst and chat.window_seconds must be positive".to_string());
+        }
+        let camera = &self.client.camera;
+        let camera_values = [camera.damping, camera.look_ahead, camera.max_look_ahead, camera.bounds_inset, camera.damage_trauma, camera.dodge_trauma, camera.trauma_decay, camera.max_shake_offset, camera.max_shake_roll];
+        if camera_values.iter().any(|value| !value.is_finite() || *value < 0.) {
+            problems.push("client.camera values must be positive".to_string());
+        }
+        if camera.look_ahead >= 1. {
+            problems.push(format!("client.camera.look_ahead must be below 1, got {}", camera.look_ahead));
+        }
+        if !self.validation.max_cursor_distance.is_finite() || self.validation.max_cursor_distance <= 0. {
+            problems.push(format!("validation.max_cursor_distance must be positive, got {}", self.validation.max_cursor_distance));
+        }
+        if self.validation.max_strikes == 0 || !self.validation.strike_decay_seconds.is_finite() || self.validation.strike_decay_seconds <= 0. {
+            problems.push("validation.max_strikes and validation.strike_decay_seconds must be positive".to_string());
+        }
+
+        if let Some(auth) = &self.auth {
+            if auth.token_expire_seconds == 0 {
+                problems.push("auth.token_expire_seconds must not be 0".to_string());
+            }
+            if AuthUserData::new(&self.client.username).is_err() {
+                problems.push(format!("client.username must be between 1 and {} bytes", AuthUserData::MAX_USERNAME_BYTES));
+            }
+            if !self.server.transports.iter().any(|t| matches!(t, ServerTransports::Udp { .. })) {
+                problems.push("auth requires at least one Udp server transport".to_string());
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(SettingsError::Invalid(problems))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuthSettings {
+    /// Address the auth service listens on, and where clients request their connect tokens
+    pub service_addr: SocketAddr,
+    /// Run the auth service inside the game server rather than as a separate `auth` process
+    pub in_process: bool,
+    /// Negative values disable expiry
+    pub token_expire_seconds: i32,
+    /// Usernames allowed to connect, everyone is allowed when empty
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub ban: Vec<String>,
+    /// File the claimed usernames are kept in, they can be claimed again after a restart when `None`
+    #[serde(default)]
+    pub accounts: Option<PathBuf>,
+}
+
+#[derive(Resource, Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LobbySettings {
+    /// Ready players needed before the countdown starts, the round also ends when fewer remain
+    pub min_players: usize,
+    pub max_players_per_match: usize,
+    /// Matches the server runs at once, each has its own collision layer so at most `MatchId::MAX`
+    pub max_matches: u32,
+    pub countdown_seconds: u32,
+    pub round_seconds: u32,
+    /// Time between the end of a round and the return to the lobby
+    pub round_over_seconds: u32,
+    /// How far behind the round spectators are kept so they can't pass information to players, 0 disables the delay
+    pub spectator_delay_ms: u64,
+    /// How long the player of a client who disconnected mid-round is kept for it to reconnect, 0 despawns it right away
+    pub reconnect_grace_seconds: u32,
+    pub teams: TeamRules,
+    /// Replaces `teams` for some matches, by match id
+    pub match_teams: HashMap<u32, TeamRules>,
+}
+
+impl LobbySettings {
+    pub fn team_rules(&self, match_id: MatchId) -> TeamRules {
+        self.match_teams.get(&match_id.0).copied().unwrap_or(self.teams)
+    }
+}
+
+impl Default for LobbySettings {
+    fn default() -> Self {
+        Self {
+            min_players: 1,
+            max_players_per_match: 8,
+            max_matches: 4,
+            countdown_seconds: 3,
+            round_seconds: 180,
+            round_over_seconds: 5,
+            spectator_delay_ms: 0,
+            reconnect_grace_seconds: 30,
+            teams: TeamRules::default(),
+            match_teams: HashMap::default(),
+        }
+    }
+}
+
+/// Limits the server applies to chat messages
+#[derive(Resource, Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ChatSettings {
+    /// Longer messages are rejected, in characters
+    pub max_length: usize,
+    /// Messages a client can send within `window_seconds`, further messages are dropped
+    pub burst: u32,
+    pub window_seconds: f32,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        Self {
+            max_length: 200,
+            burst: 5,
+            window_seconds: 5.,
+        }
+    }
+}
+
+/// Checks the server applies to what clients replicate to it
+#[derive(Resource, Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ValidationSettings {
+    /// Cursors further than this from their player are brought back to this distance
+    pub max_cursor_distance: f32,
+    /// Rejected updates before a client is kicked
+    pub max_strikes: u32,
+    /// One strike is forgiven every this many seconds
+    pub strike_decay_seconds: f32,
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        Self {
+            max_cursor_distance: 100.,
+            max_strikes: 5,
+            strike_decay_seconds: 10.,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ServerSettings {
     pub headless: bool,
+    /// Server-controlled players added to the matches
+    #[serde(default)]
+    pub bots: usize,
+    /// Directory where every match round is recorded, nothing is recorded when `None`
+    #[serde(default)]
+    pub replays: Option<PathBuf>,
+    /// Directory where a JSON summary of every round is written, nothing is written when `None`
+    #[serde(default)]
+    pub stats: Option<PathBuf>,
     pub inspector: bool,
+    /// Key connect tokens are signed with, created on first start. Only the server and the auth service read it.
+    #[serde(default = "ServerSettings::default_private_key")]
+    pub private_key: PathBuf,
     pub conditioner: Option<Conditioner>,
     pub transports: Vec<ServerTransports>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl ServerSettings {
+    fn default_private_key() -> PathBuf {
+        PathBuf::from("private.key")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub(crate) struct Conditioner {
     latency_ms: u16,
     jitter_ms: u16,
     packet_loss: f32,
 }
 
+/// `Conditioner` as written on the command line, `None` disables conditioning
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConditionerArg(Option<Conditioner>);
+
+impl FromStr for ConditionerArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("none") {
+            return Ok(ConditionerArg(None));
+        }
+
+        let mut conditioner = Conditioner {
+            latency_ms: 0,
+            jitter_ms: 0,
+            packet_loss: 0.,
+        };
+
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected `key=value`, got `{pair}`"))?;
+
+            match key.trim() {
+                "latency" => conditioner.latency_ms = value.trim().parse().map_err(|e| format!("invalid latency `{value}`: {e}"))?,
+                "jitter" => conditioner.jitter_ms = value.trim().parse().map_err(|e| format!("invalid jitter `{value}`: {e}"))?,
+                "loss" => conditioner.packet_loss = value.trim().parse().map_err(|e| format!("invalid loss `{value}`: {e}"))?,
+                other => return Err(format!("unknown conditioner key `{other}`, expected latency, jitter or loss")),
+            }
+        }
+
+        Ok(ConditionerArg(Some(conditioner)))
+    }
+}
+
 impl Conditioner {
     fn build(&self) -> LinkConditionerConfig {
         LinkConditionerConfig {
@@ -62,7 +452,22 @@ pub enum ServerTransports {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ClientSettings {
     pub inspector: bool,
+    /// Id of a host server's own client, remote clients get theirs from the auth service
     pub client_id: u64,
+    /// Name sent to the auth service
+    #[serde(default = "ClientSettings::default_username")]
+    pub username: String,
+    /// File the secret proving the username is ours is kept in, created on first connect
+    #[serde(default = "ClientSettings::default_secret")]
+    pub secret: PathBuf,
+    /// Match joined after connecting, otherwise the server picks one with room
+    #[serde(default)]
+    pub preferred_match: Option<u32>,
+    /// File the key and gamepad bindings are loaded from and saved to, they aren't saved when `None`
+    #[serde(default)]
+    pub bindings: Option<PathBuf>,
+    #[serde(default)]
+    pub camera: CameraSettings,
     pub client_port: u16,
     pub server_addr: Ipv4Addr,
     pub server_port: u16,
@@ -70,6 +475,53 @@ pub struct ClientSettings {
     pub conditioner: Option<Conditioner>,
 }
 
+/// How the camera follows the local player and shakes
+#[derive(Resource, Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CameraSettings {
+    /// How quickly the camera catches up with the player, per second. Higher is snappier, 0 doesn't smooth at all
+    pub damping: f32,
+    /// Share of the distance to the cursor the camera leans towards it, below 1 since the cursor moves with the camera
+    pub look_ahead: f32,
+    pub max_look_ahead: f32,
+    /// How far inside the level edges the camera stays centered
+    pub bounds_inset: f32,
+    /// Trauma added when the player takes damage or dodges, the shake grows with its square
+    pub damage_trauma: f32,
+    pub dodge_trauma: f32,
+    /// Trauma lost every second
+    pub trauma_decay: f32,
+    /// Largest offset and roll of the camera at full trauma, in metres and radians
+    pub max_shake_offset: f32,
+    pub max_shake_roll: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            damping: 8.,
+            look_ahead: 0.25,
+            max_look_ahead: 4.,
+            bounds_inset: 6.,
+            damage_trauma: 0.5,
+            dodge_trauma: 0.2,
+            trauma_decay: 1.5,
+            max_shake_offset: 0.4,
+            max_shake_roll: 0.04,
+        }
+    }
+}
+
+impl ClientSettings {
+    fn default_username() -> String {
+        "player".to_string()
+    }
+
+    fn default_secret() -> PathBuf {
+        PathBuf::from("secret.key")
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum ClientTransports {
     Udp,
@@ -78,25 +530,24 @@ pub enum ClientTransports {
     }
 }
 
+/// Settings both the server and the clients need, clients read them so nothing here can be secret
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct SharedSettings {
-    protocol_id: u64,
-    private_key: [u8; 32],
+    pub(crate) protocol_id: u64,
     compression: CompressionConfig,
 }
 
 pub(crate) fn build_server_netcode_config(
     conditioner: Option<&Conditioner>,
     shared: &SharedSettings,
+    private_key: Key,
     transform_config: server::ServerTransport,
 ) -> server::NetConfig {
-    let conditioner = conditioner.map_or(None, |c| {
-        Some(c.build())
-    });
+    let conditioner = conditioner.map(|c| c.build());
 
     let netcode_config = server::NetcodeConfig::default()
         .with_protocol_id(shared.protocol_id)
-        .with_key(shared.private_key);
+        .with_key(private_key);
 
     let io_config = server::IoConfig {
         transport: transform_config,
@@ -110,8 +561,8 @@ pub(crate) fn build_server_netcode_config(
     }
 }
 
-pub(crate) fn get_server_net_configs(settings: &Settings) -> Vec<server::NetConfig> {
-    settings
+pub(crate) fn get_server_net_configs(settings: &Settings, private_key: Key) -> Vec<server::NetConfig> {
+    let mut net_configs: Vec<_> = settings
         .server
         .transports
         .iter()
@@ -121,6 +572,7 @@ pub(crate) fn get_server_net_configs(settings: &Settings) -> Vec<server::NetConf
             } => build_server_netcode_config(
                     settings.server.conditioner.as_ref(),
                     &settings.shared,
+                    private_key,
                     server::ServerTransport::UdpSocket(SocketAddr::new(
                         Ipv4Addr::UNSPECIFIED.into(),
                         *local_port,
@@ -147,47 +599,47 @@ pub(crate) fn get_server_net_configs(settings: &Settings) -> Vec<server::NetConf
                     .server
                     .conditioner
                     .as_ref()
-                    .map_or(None, |c| Some(c.build())),
+                    .map(|c| c.build()),
             }
-        }).collect()
+        }).collect();
+
+    if let Some(auth) = &settings.auth {
+        let handler = Arc::new(AuthConnectionRequestHandler::new(auth));
+        for net_config in net_configs.iter_mut() {
+            net_config.set_connection_request_handler(handler.clone());
+        }
+    }
+    net_configs
 }
 
 pub(crate) fn build_client_netcode_config(
-    client_id: u64,
-    server_addr: SocketAddr,
+    auth: Authentication,
     conditioner: Option<&Conditioner>,
     shared: &SharedSettings,
     transform_config: client::ClientTransport,
 ) -> client::NetConfig {
-    let conditioner = conditioner.map_or(None, |c| Some(c.build()));
-    let auth = Authentication::Manual {
-        server_addr: server_addr,
-        client_id: client_id,
-        private_key: shared.private_key,
-        protocol_id: shared.protocol_id
-    };
+    let conditioner = conditioner.map(|c| c.build());
     let netcode_config = client::NetcodeConfig::default();
     let io_config = client::IoConfig {
         transport: transform_config,
         conditioner,
         compression: shared.compression,
     };
-    client::NetConfig::Netcode { auth: auth,
+    client::NetConfig::Netcode { auth,
         config: netcode_config,
         io: io_config
     }
 }
 
-pub(crate) fn get_client_net_config(settings: &Settings, client_id: u64) -> client::NetConfig {
+pub(crate) fn get_client_net_config(settings: &Settings) -> Result<client::NetConfig, AuthError> {
     let server_addr = SocketAddr::new(
         settings.client.server_addr.into(),
         settings.client.server_port,
     );
     let client_addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), settings.client.client_port);
-    match &settings.client.transport {
+    let net_config = match &settings.client.transport {
         ClientTransports::Udp => build_client_netcode_config(
-            client_id,
-            server_addr,
+            client_authentication(settings)?,
             settings.client.conditioner.as_ref(),
             &settings.shared,
             client::ClientTransport::UdpSocket(client_addr)
@@ -202,7 +654,129 @@ pub(crate) fn get_client_net_config(settings: &Settings, client_id: u64) -> clie
                 .server
                 .conditioner
                 .as_ref()
-                .map_or(None, |c| Some(c.build()))
+                .map(|c| c.build())
         },
+    };
+    Ok(net_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_settings() -> Settings {
+        load_settings(None, &SettingsOverrides::default()).expect("the embedded settings are valid")
+    }
+
+    /// Problems reported by `validate`, empty when the settings are valid
+    fn problems(settings: &Settings) -> Vec<String> {
+        match settings.validate() {
+            Ok(()) => Vec::new(),
+            Err(SettingsError::Invalid(problems)) => problems,
+            Err(e) => panic!("unexpected error {e}"),
+        }
+    }
+
+    fn conditioner(latency_ms: u16, jitter_ms: u16, packet_loss: f32) -> Conditioner {
+        Conditioner { latency_ms, jitter_ms, packet_loss }
+    }
+
+    #[test]
+    fn conditioner_arg_parses_pairs() {
+        let arg: ConditionerArg = "latency=75, jitter=10,loss=0.02".parse().unwrap();
+        assert_eq!(arg, ConditionerArg(Some(conditioner(75, 10, 0.02))));
+
+        // missing keys are 0
+        let arg: ConditionerArg = "loss=0.5".parse().unwrap();
+        assert_eq!(arg, ConditionerArg(Some(conditioner(0, 0, 0.5))));
+
+        assert_eq!("none".parse::<ConditionerArg>().unwrap(), ConditionerArg(None));
+        assert_eq!("NONE".parse::<ConditionerArg>().unwrap(), ConditionerArg(None));
+    }
+
+    #[test]
+    fn conditioner_arg_rejects_malformed_pairs() {
+        for malformed in ["latency", "latency=", "latency=fast", "jitter=-5", "loss=lots", "delay=10", "latency=10;jitter=5", "=10"] {
+            assert!(malformed.parse::<ConditionerArg>().is_err(), "`{malformed}` should not parse");
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn validate_rejects_bad_conditioners() {
+        let mut settings = default_settings();
+        assert!(problems(&settings).is_empty());
+
+        settings.client.conditioner = Some(conditioner(20, 50, 0.));
+        settings.server.conditioner = Some(conditioner(50, 5, 1.5));
+        let problems = problems(&settings);
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with("client.conditioner.jitter_ms"), "{problems:?}");
+        assert!(problems[1].starts_with("server.conditioner.packet_loss"), "{problems:?}");
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let mut settings = default_settings();
+        settings.client.server_port = 0;
+        settings.correction_ticks_factor = f32::NAN;
+        settings.client.conditioner = Some(conditioner(0, 0, -0.1));
+
+        assert_eq!(problems(&settings).len(), 3);
+    }
+
+    #[test]
+    fn validate_checks_camera_values() {
+        let mut settings = default_settings();
+        settings.client.camera.damping = -1.;
+        settings.client.camera.max_shake_roll = f32::NAN;
+        assert_eq!(problems(&settings), vec!["client.camera values must be positive".to_string()]);
+
+        // the camera would never reach the player
+        let mut settings = default_settings();
+        settings.client.camera.look_ahead = 1.;
+        let reported = problems(&settings);
+        assert_eq!(reported.len(), 1);
+        assert!(reported[0].starts_with("client.camera.look_ahead"), "{reported:?}");
+    }
+
+    #[test]
+    fn validate_checks_chat_limits() {
+        let mut settings = default_settings();
+        settings.chat.max_length = 0;
+        assert_eq!(problems(&settings), vec!["chat.max_length must be at least 1".to_string()]);
+
+        let mut settings = default_settings();
+        settings.chat.burst = 0;
+        settings.chat.window_seconds = -1.;
+        assert_eq!(problems(&settings).len(), 1);
+    }
+
+    #[test]
+    fn validate_checks_validation_rules() {
+        let mut settings = default_settings();
+        settings.validation.max_cursor_distance = 0.;
+        settings.validation.max_strikes = 0;
+        let reported = problems(&settings);
+        assert_eq!(reported.len(), 2, "{reported:?}");
+        assert!(reported[0].starts_with("validation.max_cursor_distance"), "{reported:?}");
+
+        let mut settings = default_settings();
+        settings.validation.max_cursor_distance = f32::INFINITY;
+        settings.validation.strike_decay_seconds = f32::NAN;
+        assert_eq!(problems(&settings).len(), 2);
+    }
+
+    #[test]
+    fn overrides_replace_settings() {
+        let overrides = SettingsOverrides {
+            server_port: Some(6000),
+            conditioner: Some("none".parse().unwrap()),
+            ..default()
+        };
+        let settings = load_settings(None, &overrides).unwrap();
+
+        assert_eq!(settings.client.server_port, 6000);
+        assert!(settings.server.transports.iter().all(|t| !matches!(t, ServerTransports::Udp { local_port } if *local_port != 6000)));
+        assert_eq!(settings.client.conditioner, None);
+    }
+}
@@ -1,7 +1,8 @@
 use avian3d::prelude::*;
 use bevy::{prelude::*, render::RenderPlugin};
+use bevy_asset_loader::loading_state::{config::ConfigureLoadingState, LoadingState, LoadingStateAppExt};
 
-use crate::{abilities::AbilitiesPlugin, ability_framework::{ability_map::AbilityMap, pool::AbilityCost, pools::{life::LifePool, mana::ManaPool}, TriggerAbility}, player::{CursorPosition, PlayerActions, PlayerId}, protocol::ProtocolPlugin, rendering::OverheatRenderPlugin, FIXED_TIMESTEP_HZ};
+use crate::{abilities::AbilitiesPlugin, ability_framework::{ability_map::AbilityMap, pipeline::AbilityPipelineSet, status_effect::StatusEffects, AbilityActive, ActivationMode}, assets::{AbilityAssets, LevelAssets}, combat::Dead, level::LevelPlugin, lobby::{MatchId, MatchPhase, MatchRoster}, player::{CursorPosition, PlayerActions, PlayerId}, projectile::ProjectilePlugin, protocol::ProtocolPlugin, rendering::OverheatRenderPlugin, FIXED_TIMESTEP_HZ};
 
 pub struct OverheatSharedPlugin;
 
@@ -22,8 +23,18 @@ impl Plugin for OverheatSharedPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(ProtocolPlugin);
         app.add_plugins(AbilitiesPlugin);
+        app.add_plugins(ProjectilePlugin);
+        app.add_plugins(LevelPlugin);
 
-        app.add_event::<TriggerAbility>();
+        // ability and level definitions are needed by both the server and the client so loading happens here,
+        // the render plugin adds its own visual assets to the same loading state.
+        app.init_state::<GameState>();
+        app.add_loading_state(
+            LoadingState::new(GameState::AssetLoading)
+                .continue_to_state(GameState::Game)
+                .load_collection::<AbilityAssets>()
+                .load_collection::<LevelAssets>()
+        );
 
         if app.is_plugin_added::<RenderPlugin>() {
             app.add_plugins(OverheatRenderPlugin);
@@ -58,17 +69,21 @@ impl Plugin for OverheatSharedPlugin {
                     PhysicsSet::StepSimulation,
                     PhysicsSet::Sync,
                 ).in_set(FixedSet::Physics),
-                (FixedSet::Main, FixedSet::Physics).chain()
+                (FixedSet::Main, FixedSet::Physics).chain(),
+                AbilityPipelineSet.in_set(FixedSet::Main),
             ),
         );
 
         app.register_type::<PlayerId>();
         app.register_type::<CursorPosition>();
         app.register_type::<AbilityMap<PlayerActions>>();
-        app.register_type::<LifePool>();
-        app.register_type::<ManaPool>();
-        app.register_type::<AbilityCost<LifePool>>();
-        app.register_type::<AbilityCost<ManaPool>>();
+        app.register_type::<StatusEffects>();
+        app.register_type::<Dead>();
+        app.register_type::<ActivationMode>();
+        app.register_type::<AbilityActive>();
+        app.register_type::<MatchId>();
+        app.register_type::<MatchPhase>();
+        app.register_type::<MatchRoster>();
     }
 }
 
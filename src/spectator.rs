@@ -0,0 +1,363 @@
+use std::{collections::VecDeque, mem::discriminant, time::Duration};
+
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+use lightyear::prelude::{client, server::ConnectionManager, ClientId, NetworkTarget, TickManager};
+use serde::{Deserialize, Serialize};
+
+use crate::{lobby::MatchRoster, player::PlayerId, protocol::SpectatorChannel, rendering::CAMERA_OFFSET, replay::{MatchRecordingPlugin, MatchRecordingSet, RecordedComponent, RecordedFrame, ReplayEntities, ReplayFrame}};
+
+/// Frames buffered by a spectator before it plays them faster to catch up
+const MAX_BUFFERED_FRAMES: usize = 8;
+/// Speed of the free camera, in metres per second
+const FREE_CAMERA_SPEED: f32 = 20.;
+
+/// Round state released to spectators once it is older than the spectator delay
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SpectatorFrameMessage {
+    pub frame: ReplayFrame,
+    /// The whole state of the round, sent when a spectator starts watching
+    pub snapshot: bool,
+}
+
+/// Holds the rounds back from delayed spectators. Without a delay spectators are in their match's
+/// room and the round is replicated to them directly.
+pub struct SpectatorServerPlugin {
+    pub delay: Duration,
+}
+
+impl Plugin for SpectatorServerPlugin {
+    fn build(&self, app: &mut App) {
+        if self.delay.is_zero() {
+            return;
+        }
+        if !app.is_plugin_added::<MatchRecordingPlugin>() {
+            app.add_plugins(MatchRecordingPlugin);
+        }
+
+        app.insert_resource(SpectatorFeeds {
+            delay: self.delay,
+            feeds: HashMap::default(),
+        });
+        app.add_systems(FixedPostUpdate, (
+                buffer_frames,
+                release_frames,
+            )
+            .chain()
+            .after(MatchRecordingSet)
+        );
+    }
+}
+
+#[derive(Resource)]
+struct SpectatorFeeds {
+    delay: Duration,
+    /// By match entity
+    feeds: HashMap<Entity, SpectatorFeed>,
+}
+
+#[derive(Default)]
+struct SpectatorFeed {
+    /// Frames still within the delay
+    pending: VecDeque<ReplayFrame>,
+    /// Everything released so far, for spectators who start watching mid-round
+    released: RoundSnapshot,
+    /// Spectators who received the snapshot and get every released frame
+    watching: HashSet<ClientId>,
+}
+
+/// Latest recorded state of the entities still alive
+#[derive(Default)]
+struct RoundSnapshot {
+    entities: HashMap<u64, Vec<RecordedComponent>>,
+}
+
+impl RoundSnapshot {
+    fn merge(&mut self, frame: ReplayFrame) {
+        for (entity, component) in frame.updates {
+            let components = self.entities.entry(entity).or_default();
+            match components.iter_mut().find(|existing| discriminant(*existing) == discriminant(&component)) {
+                Some(existing) => *existing = component,
+                None => components.push(component),
+            }
+        }
+
+        for entity in frame.despawned {
+            self.entities.remove(&entity);
+        }
+    }
+
+    fn to_frame(&self) -> ReplayFrame {
+        let updates = self
+            .entities
+            .iter()
+            .flat_map(|(entity, components)| components.iter().cloned().map(|component| (*entity, component)))
+            .collect();
+
+        ReplayFrame {
+            updates,
+            ..default()
+        }
+    }
+}
+
+fn buffer_frames(
+    mut feeds: ResMut<SpectatorFeeds>,
+    mut frames: EventReader<RecordedFrame>,
+) {
+    for recorded in frames.read() {
+        // spectators only see the outcome of the inputs
+        let frame = ReplayFrame {
+            tick: recorded.frame.tick,
+            updates: recorded.frame.updates.clone(),
+            despawned: recorded.frame.despawned.clone(),
+            inputs: Vec::new(),
+        };
+        feeds.feeds.entry(recorded.match_entity).or_default().pending.push_back(frame);
+    }
+}
+
+fn release_frames(
+    mut feeds: ResMut<SpectatorFeeds>,
+    mut connection: ResMut<ConnectionManager>,
+    tick_manager: Res<TickManager>,
+    match_query: Query<(Entity, &MatchRoster)>,
+) {
+    let delay_ticks = (feeds.delay.as_secs_f64() / tick_manager.config.tick_duration.as_secs_f64()).ceil() as usize;
+    feeds.feeds.retain(|match_entity, _| match_query.contains(*match_entity));
+
+    for (match_entity, roster) in match_query.iter() {
+        let feed = feeds.feeds.entry(match_entity).or_default();
+
+        feed.watching.retain(|client_id| roster.is_spectating(*client_id));
+        let joined: Vec<ClientId> = roster
+            .spectators
+            .iter()
+            .copied()
+            .filter(|client_id| !feed.watching.contains(client_id))
+            .collect();
+        if !joined.is_empty() {
+            let message = SpectatorFrameMessage {
+                frame: feed.released.to_frame(),
+                snapshot: true,
+            };
+            send_to_spectators(&mut connection, message, joined.clone());
+            feed.watching.extend(joined);
+        }
+
+        while feed.pending.len() > delay_ticks {
+            let frame = feed.pending.pop_front().unwrap();
+            if !feed.watching.is_empty() {
+                let message = SpectatorFrameMessage {
+                    frame: frame.clone(),
+                    snapshot: false,
+                };
+                send_to_spectators(&mut connection, message, feed.watching.iter().copied().collect());
+            }
+            feed.released.merge(frame);
+        }
+    }
+}
+
+fn send_to_spectators(connection: &mut ConnectionManager, mut message: SpectatorFrameMessage, spectators: Vec<ClientId>) {
+    if let Err(e) = connection.send_message_to_target::<SpectatorChannel, _>(&mut message, NetworkTarget::Only(spectators)) {
+        error!("Could not send round state to spectators: {e:?}");
+    }
+}
+
+/// Client watching a match without a player of its own
+pub struct SpectatorClientPlugin;
+
+impl Plugin for SpectatorClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(SpectatorCameraPlugin);
+        app.init_resource::<DelayedRound>();
+        app.insert_resource(Spectator);
+
+        app.add_systems(Update, receive_spectator_frames);
+        app.add_systems(FixedUpdate, play_spectator_frames);
+    }
+}
+
+/// Present on spectating clients
+#[derive(Resource)]
+pub struct Spectator;
+
+/// Frames received from the server when spectators are delayed, played back one per tick
+#[derive(Resource, Default)]
+struct DelayedRound {
+    pending: VecDeque<ReplayFrame>,
+    entities: ReplayEntities,
+}
+
+fn receive_spectator_frames(
+    mut commands: Commands,
+    mut round: ResMut<DelayedRound>,
+    mut messages: ResMut<Events<client::MessageEvent<SpectatorFrameMessage>>>,
+) {
+    for event in messages.drain() {
+        let message = event.message;
+        if message.snapshot {
+            let round = round.as_mut();
+            round.pending.clear();
+            round.entities.clear(&mut commands);
+            round.entities.apply(&mut commands, &message.frame);
+        } else {
+            round.pending.push_back(message.frame);
+        }
+    }
+}
+
+fn play_spectator_frames(
+    mut commands: Commands,
+    mut round: ResMut<DelayedRound>,
+) {
+    let round = round.as_mut();
+    // frames arrive in bursts, catch up rather than drifting further behind
+    while round.pending.len() > MAX_BUFFERED_FRAMES {
+        let frame = round.pending.pop_front().unwrap();
+        round.entities.apply(&mut commands, &frame);
+    }
+
+    if let Some(frame) = round.pending.pop_front() {
+        round.entities.apply(&mut commands, &frame);
+    }
+}
+
+/// Free camera panned with WASD, E follows the next player and F frees the camera again
+pub struct SpectatorCameraPlugin;
+
+impl Plugin for SpectatorCameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpectatorCamera>();
+
+        app.add_systems(Update, cycle_followed_player);
+        app.add_systems(PostUpdate, move_spectator_camera.before(TransformSystem::TransformPropagate));
+    }
+}
+
+#[derive(Resource, Default)]
+struct SpectatorCamera {
+    followed: Option<Entity>,
+}
+
+fn cycle_followed_player(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut camera: ResMut<SpectatorCamera>,
+    player_query: Query<Entity, With<PlayerId>>,
+) {
+    if keys.just_pressed(KeyCode::KeyF) {
+        camera.followed = None;
+        return;
+    }
+    if !keys.just_pressed(KeyCode::KeyE) {
+        return;
+    }
+
+    let mut players: Vec<Entity> = player_query.iter().collect();
+    players.sort();
+
+    // past the last player the camera is free again
+    camera.followed = match camera.followed.and_then(|followed| players.iter().position(|player| *player == followed)) {
+        Some(index) => players.get(index + 1).copied(),
+        None => players.first().copied(),
+    };
+}
+
+fn move_spectator_camera(
+    // keeps panning while a replay is paused
+    time: Res<Time<Real>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut spectator: ResMut<SpectatorCamera>,
+    player_query: Query<&Transform, (With<PlayerId>, Without<Camera3d>)>,
+    mut camera_query: Query<&mut Transform, With<Camera3d>>,
+) {
+    let Ok(mut camera) = camera_query.get_single_mut() else {
+        return;
+    };
+
+    if let Some(followed) = spectator.followed {
+        match player_query.get(followed) {
+            Ok(player) => {
+                camera.translation = player.translation + CAMERA_OFFSET;
+                return;
+            },
+            // the player left or the round ended
+            Err(_) => spectator.followed = None,
+        }
+    }
+
+    let mut input = Vec2::ZERO;
+    if keys.pressed(KeyCode::KeyW) {
+        input.y += 1.;
+    }
+    if keys.pressed(KeyCode::KeyS) {
+        input.y -= 1.;
+    }
+    if keys.pressed(KeyCode::KeyD) {
+        input.x += 1.;
+    }
+    if keys.pressed(KeyCode::KeyA) {
+        input.x -= 1.;
+    }
+
+    // pans along the ground, relative to where the camera looks
+    let forward = camera.forward().with_y(0.).normalize_or_zero();
+    let right = camera.right().with_y(0.).normalize_or_zero();
+    let direction = (forward * input.y + right * input.x).normalize_or_zero();
+    camera.translation += direction * FREE_CAMERA_SPEED * time.delta_seconds();
+}
+
+#[cfg(test)]
+mod tests {
+    use avian3d::prelude::Position;
+
+    use crate::team::Team;
+
+    use super::*;
+
+    fn frame(updates: Vec<(u64, RecordedComponent)>, despawned: Vec<u64>) -> ReplayFrame {
+        ReplayFrame {
+            updates,
+            despawned,
+            ..default()
+        }
+    }
+
+    fn position(snapshot: &RoundSnapshot, entity: u64) -> Option<Vec3> {
+        snapshot.entities.get(&entity)?.iter().find_map(|component| match component {
+            RecordedComponent::Position(position) => Some(position.0),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn later_updates_replace_earlier_ones() {
+        let mut snapshot = RoundSnapshot::default();
+        snapshot.merge(frame(vec![(1, Position(Vec3::X).into()), (1, Team(0).into())], Vec::new()));
+        snapshot.merge(frame(vec![(1, Position(Vec3::Z).into())], Vec::new()));
+
+        assert_eq!(position(&snapshot, 1), Some(Vec3::Z));
+        // one of each component
+        assert_eq!(snapshot.entities[&1].len(), 2);
+        assert_eq!(snapshot.to_frame().updates.len(), 2);
+    }
+
+    #[test]
+    fn despawned_entities_are_forgotten() {
+        let mut snapshot = RoundSnapshot::default();
+        snapshot.merge(frame(vec![(1, Position(Vec3::X).into()), (2, Position(Vec3::Y).into())], Vec::new()));
+        snapshot.merge(frame(Vec::new(), vec![1]));
+
+        assert_eq!(position(&snapshot, 1), None);
+        assert_eq!(position(&snapshot, 2), Some(Vec3::Y));
+    }
+
+    #[test]
+    fn despawns_apply_after_the_frame_updates() {
+        // the last update of a projectile comes with its despawn
+        let mut snapshot = RoundSnapshot::default();
+        snapshot.merge(frame(vec![(3, Position(Vec3::X).into())], vec![3]));
+
+        assert!(snapshot.entities.is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Side a player fights for within its match. Players without a team are on their own.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+pub struct Team(pub u8);
+
+impl Team {
+    /// Most teams a match can be split into, one colour each
+    pub const MAX: u8 = 4;
+
+    const COLORS: [Color; Self::MAX as usize] = [
+        Color::srgb(0.9, 0.25, 0.2),
+        Color::srgb(0.2, 0.45, 0.95),
+        Color::srgb(0.25, 0.8, 0.3),
+        Color::srgb(0.95, 0.8, 0.2),
+    ];
+
+    pub fn color(&self) -> Color {
+        Self::COLORS[self.0 as usize % Self::COLORS.len()]
+    }
+
+    pub fn name(&self) -> &'static str {
+        ["Red", "Blue", "Green", "Yellow"][self.0 as usize % Self::MAX as usize]
+    }
+
+    pub fn allied(a: Option<Team>, b: Option<Team>) -> bool {
+        a.is_some() && a == b
+    }
+}
+
+/// How the players of a match are split, replicated on the match entity
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Reflect)]
+#[serde(default)]
+pub struct TeamRules {
+    /// Teams players are balanced between as they join, 0 or 1 means everyone plays for themselves
+    pub teams: u8,
+    /// Whether effects aimed at enemies also hit allies
+    pub friendly_fire: bool,
+}
+
+impl TeamRules {
+    pub fn has_teams(&self) -> bool {
+        self.teams > 1
+    }
+
+    /// The team with the fewest members out of `members`, the first one on a tie
+    pub fn balanced_team(&self, members: impl IntoIterator<Item = Option<Team>>) -> Option<Team> {
+        if !self.has_teams() {
+            return None;
+        }
+
+        let mut counts = vec![0usize; self.teams as usize];
+        for team in members.into_iter().flatten() {
+            if let Some(count) = counts.get_mut(team.0 as usize) {
+                *count += 1;
+            }
+        }
+        (0..self.teams).min_by_key(|team| counts[*team as usize]).map(Team)
+    }
+}
+
+impl Default for TeamRules {
+    fn default() -> Self {
+        Self {
+            teams: 2,
+            friendly_fire: false,
+        }
+    }
+}
+
+/// Which players an ability effect applies to, relative to the team of its source
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum Targets {
+    #[default]
+    Enemies,
+    Allies,
+    Everyone,
+}
+
+impl Targets {
+    /// Friendly fire turns effects aimed at enemies into effects hitting everyone
+    pub fn with_friendly_fire(self, friendly_fire: bool) -> Self {
+        match self {
+            Self::Enemies if friendly_fire => Self::Everyone,
+            targets => targets,
+        }
+    }
+
+    pub fn allows(&self, source: Option<Team>, target: Option<Team>) -> bool {
+        match self {
+            Self::Enemies => !Team::allied(source, target),
+            Self::Allies => Team::allied(source, target),
+            Self::Everyone => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(teams: u8) -> TeamRules {
+        TeamRules {
+            teams,
+            friendly_fire: false,
+        }
+    }
+
+    #[test]
+    fn players_join_the_smallest_team() {
+        assert_eq!(rules(2).balanced_team([]), Some(Team(0)));
+        assert_eq!(rules(2).balanced_team([Some(Team(0))]), Some(Team(1)));
+        assert_eq!(rules(2).balanced_team([Some(Team(0)), Some(Team(1))]), Some(Team(0)));
+        assert_eq!(rules(3).balanced_team([Some(Team(0)), Some(Team(2)), Some(Team(0))]), Some(Team(1)));
+    }
+
+    #[test]
+    fn members_outside_the_rules_are_ignored() {
+        // spectators and teams from other rules don't count
+        assert_eq!(rules(2).balanced_team([None, None, Some(Team(3)), Some(Team(1))]), Some(Team(0)));
+    }
+
+    #[test]
+    fn matches_without_teams_assign_none() {
+        assert_eq!(rules(0).balanced_team([]), None);
+        assert_eq!(rules(1).balanced_team([Some(Team(0))]), None);
+    }
+
+    #[test]
+    fn targets_follow_teams() {
+        let (red, blue) = (Some(Team(0)), Some(Team(1)));
+
+        assert!(Targets::Enemies.allows(red, blue));
+        assert!(!Targets::Enemies.allows(red, red));
+        assert!(Targets::Allies.allows(red, red));
+        assert!(!Targets::Allies.allows(red, blue));
+        assert!(Targets::Everyone.allows(red, red) && Targets::Everyone.allows(red, blue));
+    }
+
+    #[test]
+    fn players_without_a_team_have_no_allies() {
+        assert!(Targets::Enemies.allows(None, None));
+        assert!(Targets::Enemies.allows(None, Some(Team(0))));
+        assert!(!Targets::Allies.allows(None, None));
+    }
+
+    #[test]
+    fn friendly_fire_only_widens_enemy_effects() {
+        assert_eq!(Targets::Enemies.with_friendly_fire(true), Targets::Everyone);
+        assert_eq!(Targets::Enemies.with_friendly_fire(false), Targets::Enemies);
+        assert_eq!(Targets::Allies.with_friendly_fire(true), Targets::Allies);
+    }
+}
@@ -0,0 +1,93 @@
+use std::{io::Write, net::{SocketAddr, TcpStream}, sync::Arc, time::{Duration, Instant}};
+
+use lightyear::{prelude::{client::{Authentication, ClientConnection, NetClient}, generate_key, ClientId, Key}, transport::LOCAL_SOCKET};
+
+use crate::{auth::{request_token, Accounts, AuthConnectionRequestHandler, AuthService, TokenIssuer}, settings::AuthSettings};
+
+use super::stepper::{test_settings, GameStepper};
+
+/// Frames a client is given to connect, or to be turned away
+const CONNECT_FRAMES: usize = 200;
+
+/// Starts an auth service on a free loopback port, issuing tokens for the stepper's server
+fn start_auth_service(private_key: Key) -> SocketAddr {
+    let issuer = TokenIssuer {
+        protocol_id: test_settings().shared.protocol_id,
+        private_key,
+        server_addresses: vec![LOCAL_SOCKET],
+        expire_seconds: 30,
+    };
+    let service = AuthService::bind("127.0.0.1:0".parse().unwrap(), issuer, Accounts::default()).unwrap();
+    let addr = service.local_addr().unwrap();
+    service.spawn();
+    addr
+}
+
+fn ban_handler(banned: &str) -> Arc<AuthConnectionRequestHandler> {
+    Arc::new(AuthConnectionRequestHandler::new(&AuthSettings {
+        service_addr: LOCAL_SOCKET,
+        in_process: false,
+        token_expire_seconds: 30,
+        allow: Vec::new(),
+        ban: vec![banned.to_string()],
+        accounts: None,
+    }))
+}
+
+#[test]
+fn client_ids_are_stable() {
+    // 64-bit FNV-1a test vectors, the ids of existing players must never change
+    assert_eq!(TokenIssuer::client_id(""), 0xcbf2_9ce4_8422_2325);
+    assert_eq!(TokenIssuer::client_id("a"), 0xaf63_dc4c_8601_ec8c);
+    assert_ne!(TokenIssuer::client_id("alice"), TokenIssuer::client_id("bob"));
+}
+
+#[test]
+fn usernames_belong_to_the_first_secret() {
+    let addr = start_auth_service(generate_key());
+
+    assert!(request_token(addr, "alice", "alice's secret").is_ok());
+    assert!(request_token(addr, "alice", "someone else's secret").is_err(), "another secret got a token for alice");
+    assert!(request_token(addr, "alice", "alice's secret").is_ok());
+    assert!(request_token(addr, "bob", "").is_err(), "a token was issued without a secret");
+}
+
+#[test]
+fn slow_clients_dont_hold_up_others() {
+    let addr = start_auth_service(generate_key());
+
+    // trickles in a username and never finishes its request
+    let mut slow = TcpStream::connect(addr).unwrap();
+    slow.write_all(b"mall").unwrap();
+
+    let start = Instant::now();
+    assert!(request_token(addr, "alice", "alice's secret").is_ok());
+    assert!(start.elapsed() < Duration::from_secs(1), "alice waited {:?} behind the slow client", start.elapsed());
+}
+
+#[test]
+fn token_from_the_auth_service_connects() {
+    let private_key = generate_key();
+    let addr = start_auth_service(private_key);
+
+    let token = request_token(addr, "alice", "secret").unwrap();
+    let mut stepper = GameStepper::with_auth(None, private_key, Authentication::Token(token), Some(ban_handler("mallory")));
+
+    let connected = stepper.step_until(CONNECT_FRAMES, |stepper| stepper.client_connected());
+    assert!(connected, "the client never connected with its token");
+
+    let id = stepper.client_app.world().resource::<ClientConnection>().id();
+    assert_eq!(id, ClientId::Netcode(TokenIssuer::client_id("alice")));
+}
+
+#[test]
+fn banned_users_are_rejected() {
+    let private_key = generate_key();
+    let addr = start_auth_service(private_key);
+
+    let token = request_token(addr, "mallory", "secret").unwrap();
+    let mut stepper = GameStepper::with_auth(None, private_key, Authentication::Token(token), Some(ban_handler("mallory")));
+
+    let connected = stepper.step_until(CONNECT_FRAMES, |stepper| stepper.client_connected());
+    assert!(!connected, "a banned user connected");
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use avian3d::prelude::{LinearVelocity, Position};
+use bevy::prelude::*;
+use lightyear::prelude::LinkConditionerConfig;
+
+use crate::{ability_framework::{pool::Pool, pools::mana::ManaPool}, player::MoveSpeed};
+
+use super::stepper::GameStepper;
+
+/// Mana regenerated while the dodge resolves on the server, on top of its cost
+const MANA_TOLERANCE: f32 = 1.;
+
+#[test]
+fn dodge_applies_impulse_and_costs_mana() {
+    let mut stepper = GameStepper::new(None);
+    let (server_player, _) = stepper.start_round();
+
+    // the dash goes in the direction of movement, so start moving first
+    stepper.press(KeyCode::KeyW);
+    stepper.frame_steps(30);
+
+    let world = stepper.server_app.world();
+    let move_speed = world.get::<MoveSpeed>(server_player).unwrap().0;
+    let mana_before = world.get::<ManaPool>(server_player).unwrap().current();
+    // moving forward is 1.5 times faster because of the camera angle
+    assert!(world.get::<LinearVelocity>(server_player).unwrap().length() <= move_speed * 1.5 + 0.1);
+
+    // dodge charges while held and triggers on release
+    stepper.press(KeyCode::Space);
+    stepper.frame_step();
+    stepper.release(KeyCode::Space);
+
+    let mut fastest = 0f32;
+    let dashed = stepper.step_until(60, |stepper| {
+        let velocity = stepper.server_app.world().get::<LinearVelocity>(server_player).unwrap();
+        fastest = fastest.max(velocity.length());
+        fastest > move_speed * 2.
+    });
+    assert!(dashed, "server velocity never exceeded the move speed, fastest was {fastest}");
+
+    let mana_after = stepper.server_app.world().get::<ManaPool>(server_player).unwrap().current();
+    let spent = mana_before - mana_after;
+    assert!(
+        (spent.0 - 10.).abs() <= MANA_TOLERANCE,
+        "dodge should cost 10 mana, went from {mana_before} to {mana_after}"
+    );
+}
+
+#[test]
+fn prediction_matches_server_under_link_conditioner() {
+    const EPSILON: f32 = 0.1;
+
+    let conditioner = LinkConditionerConfig::new(Duration::from_millis(40), Duration::from_millis(5), 0.);
+    let mut stepper = GameStepper::new(Some(conditioner));
+    let (server_player, client_player) = stepper.start_round();
+
+    let server_position = |stepper: &GameStepper| stepper.server_app.world().get::<Position>(server_player).unwrap().0;
+    let client_position = |stepper: &GameStepper| stepper.client_app.world().get::<Position>(client_player).unwrap().0;
+    let start = server_position(&stepper);
+
+    stepper.press(KeyCode::KeyD);
+    stepper.frame_steps(40);
+    stepper.release(KeyCode::KeyD);
+
+    // the player comes to rest once the released input reaches the server
+    stepper.frame_steps(60);
+    assert!(server_position(&stepper).distance(start) > 1., "the server never moved the player");
+
+    let converged = stepper.step_until(120, |stepper| {
+        server_position(stepper).distance(client_position(stepper)) < EPSILON
+    });
+    assert!(
+        converged,
+        "predicted position {} is still {} away from the server's {}",
+        client_position(&stepper),
+        server_position(&stepper).distance(client_position(&stepper)),
+        server_position(&stepper)
+    );
+}
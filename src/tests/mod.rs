@@ -0,0 +1,6 @@
+//! Headless end-to-end tests running the game's server and client plugins against each other
+
+mod auth;
+mod gameplay;
+mod stepper;
+mod validation;
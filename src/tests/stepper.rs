@@ -0,0 +1,245 @@
+use std::{sync::Arc, time::Duration};
+
+use bevy::{hierarchy::HierarchyPlugin, input::InputPlugin, prelude::*, state::app::StatesPlugin, time::TimeUpdateStrategy, utils::Instant};
+use lightyear::{connection::server::ConnectionRequestHandler, prelude::{client::{self, Authentication, ClientConfig, ClientTransport, NetworkingState, Predicted}, generate_key, server::{self, ServerConfig, ServerTransport}, Key, LinkConditionerConfig, Mode, PingConfig}, shared::replication::components::Controlled, transport::LOCAL_SOCKET};
+
+use crate::{app::shared_config, client::OverheatClientPlugin, lobby::{MatchPhase, ReadyMessage}, player::PlayerId, protocol::LobbyChannel, server::OverheatServerPlugin, settings::{build_client_netcode_config, build_server_netcode_config, load_settings, CameraSettings, Settings, SettingsOverrides}, shared::{GameState, OverheatSharedPlugin}};
+
+pub const TEST_CLIENT_ID: u64 = 111;
+
+/// Frames allowed for connecting, loading assets or starting a round before a test fails
+const MAX_SETUP_FRAMES: usize = 500;
+
+/// A dedicated server and one client running the game plugins headlessly, connected through
+/// in-memory channels. Both apps are updated once per fixed tick with a manually advanced clock.
+pub struct GameStepper {
+    pub client_app: App,
+    pub server_app: App,
+    pub frame_duration: Duration,
+    current_time: Instant,
+    /// The link conditioner delays packets by wall-clock time, so frames are also slept for when one is used
+    realtime: bool,
+}
+
+/// Default settings, with rounds starting as soon as the client is ready
+pub fn test_settings() -> Settings {
+    let mut settings = load_settings(None, &SettingsOverrides::default()).expect("default settings are valid");
+    settings.lobby.countdown_seconds = 0;
+    settings
+}
+
+impl GameStepper {
+    /// The client connects as `TEST_CLIENT_ID` with a key it shares with the server
+    pub fn new(conditioner: Option<LinkConditionerConfig>) -> Self {
+        let private_key = generate_key();
+        let auth = Authentication::Manual {
+            server_addr: LOCAL_SOCKET,
+            client_id: TEST_CLIENT_ID,
+            private_key,
+            protocol_id: test_settings().shared.protocol_id,
+        };
+        Self::with_auth(conditioner, private_key, auth, None)
+    }
+
+    /// The server signs tokens with `private_key` and accepts connections through `handler`, if any
+    pub fn with_auth(
+        conditioner: Option<LinkConditionerConfig>,
+        private_key: Key,
+        auth: Authentication,
+        handler: Option<Arc<dyn ConnectionRequestHandler>>,
+    ) -> Self {
+        let settings = test_settings();
+
+        let (from_server_send, from_server_recv) = crossbeam_channel::unbounded();
+        let (to_server_send, to_server_recv) = crossbeam_channel::unbounded();
+
+        let mut server_net_config = build_server_netcode_config(
+            None,
+            &settings.shared,
+            private_key,
+            ServerTransport::Channels {
+                channels: vec![(LOCAL_SOCKET, to_server_recv, from_server_send)],
+            },
+        );
+        if let server::NetConfig::Netcode { io, .. } = &mut server_net_config {
+            io.conditioner = conditioner.clone();
+        }
+        if let Some(handler) = handler {
+            server_net_config.set_connection_request_handler(handler);
+        }
+
+        let mut server_app = App::new();
+        add_headless_plugins(&mut server_app);
+        server_app.add_plugins(server::ServerPlugins::new(ServerConfig {
+            shared: shared_config(Mode::Separate),
+            net: vec![server_net_config],
+            ping: frequent_pings(),
+            ..default()
+        }));
+        server_app.add_plugins((
+            OverheatServerPlugin {
+                predict_all: settings.predict_all,
+                lobby: settings.lobby.clone(),
+                chat: settings.chat.clone(),
+                validation: settings.validation.clone(),
+                bots: 0,
+                replays: None,
+                stats: None,
+            },
+            OverheatSharedPlugin,
+        ));
+
+        let mut client_net_config = build_client_netcode_config(
+            auth,
+            None,
+            &settings.shared,
+            ClientTransport::LocalChannel {
+                send: to_server_send,
+                recv: from_server_recv,
+            },
+        );
+        if let client::NetConfig::Netcode { io, .. } = &mut client_net_config {
+            io.conditioner = conditioner.clone();
+        }
+
+        let mut client_app = App::new();
+        add_headless_plugins(&mut client_app);
+        client_app.add_plugins(InputPlugin);
+        // sent by the window backend, the cursor systems read them
+        client_app.add_event::<CursorMoved>();
+        client_app.add_plugins(client::ClientPlugins::new(ClientConfig {
+            shared: shared_config(Mode::Separate),
+            net: client_net_config,
+            ping: frequent_pings(),
+            ..default()
+        }));
+        client_app.add_plugins((
+            OverheatClientPlugin {
+                preferred_match: None,
+                spectate: false,
+                bindings: None,
+                camera: CameraSettings::default(),
+            },
+            OverheatSharedPlugin,
+        ));
+
+        let current_time = Instant::now();
+        for app in [&mut client_app, &mut server_app] {
+            app.finish();
+            app.cleanup();
+            app.world_mut().resource_mut::<Time<Real>>().update_with_instant(current_time);
+        }
+
+        Self {
+            client_app,
+            server_app,
+            frame_duration: shared_config(Mode::Separate).tick.tick_duration,
+            current_time,
+            realtime: conditioner.is_some(),
+        }
+    }
+
+    /// Advances both apps by one frame, the client first like the lightyear steppers
+    pub fn frame_step(&mut self) {
+        if self.realtime {
+            std::thread::sleep(self.frame_duration);
+        }
+
+        self.current_time += self.frame_duration;
+        for app in [&mut self.client_app, &mut self.server_app] {
+            app.insert_resource(TimeUpdateStrategy::ManualInstant(self.current_time));
+        }
+        self.client_app.update();
+        self.server_app.update();
+    }
+
+    pub fn frame_steps(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.frame_step();
+        }
+    }
+
+    /// Steps until `condition` holds, returns false if it still doesn't after `max_frames`
+    pub fn step_until(&mut self, max_frames: usize, mut condition: impl FnMut(&mut Self) -> bool) -> bool {
+        for _ in 0..max_frames {
+            if condition(self) {
+                return true;
+            }
+            self.frame_step();
+        }
+        condition(self)
+    }
+
+    /// Connects, readies the client up and waits for the round to spawn its player.
+    /// Returns the player on the server and its predicted copy on the client.
+    pub fn start_round(&mut self) -> (Entity, Entity) {
+        let loaded = self.step_until(MAX_SETUP_FRAMES, |stepper| {
+            in_game(&stepper.client_app) && in_game(&stepper.server_app) && stepper.client_in_lobby()
+        });
+        assert!(loaded, "the client never joined a match lobby");
+
+        self.client_app
+            .world_mut()
+            .resource_mut::<client::ConnectionManager>()
+            .send_message::<LobbyChannel, _>(&mut ReadyMessage {
+                ready: true,
+            })
+            .expect("ready message is sent");
+
+        let spawned = self.step_until(MAX_SETUP_FRAMES, |stepper| stepper.local_player().is_some());
+        assert!(spawned, "the round never spawned the client's player");
+
+        let server_player = self
+            .server_app
+            .world_mut()
+            .query_filtered::<Entity, With<PlayerId>>()
+            .single(self.server_app.world());
+        (server_player, self.local_player().unwrap())
+    }
+
+    pub fn client_connected(&self) -> bool {
+        *self.client_app.world().resource::<State<NetworkingState>>().get() == NetworkingState::Connected
+    }
+
+    /// The client's predicted player
+    pub fn local_player(&mut self) -> Option<Entity> {
+        self.client_app
+            .world_mut()
+            .query_filtered::<Entity, (With<PlayerId>, With<Predicted>, With<Controlled>)>()
+            .get_single(self.client_app.world())
+            .ok()
+    }
+
+    pub fn press(&mut self, key: KeyCode) {
+        self.client_app.world_mut().resource_mut::<ButtonInput<KeyCode>>().press(key);
+    }
+
+    pub fn release(&mut self, key: KeyCode) {
+        self.client_app.world_mut().resource_mut::<ButtonInput<KeyCode>>().release(key);
+    }
+
+    fn client_in_lobby(&mut self) -> bool {
+        self.client_app
+            .world_mut()
+            .query::<&MatchPhase>()
+            .iter(self.client_app.world())
+            .any(|phase| *phase == MatchPhase::Lobby)
+    }
+}
+
+/// `MinimalPlugins` and what else the game plugins need to run without a window or renderer
+fn add_headless_plugins(app: &mut App) {
+    app.add_plugins((MinimalPlugins, StatesPlugin, AssetPlugin::default(), TransformPlugin, HierarchyPlugin));
+}
+
+/// Pings every frame so that clients sync within a few frames
+fn frequent_pings() -> PingConfig {
+    PingConfig {
+        ping_interval: Duration::ZERO,
+        ..default()
+    }
+}
+
+fn in_game(app: &App) -> bool {
+    *app.world().resource::<State<GameState>>() == GameState::Game
+}
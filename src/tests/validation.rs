@@ -0,0 +1,73 @@
+use avian3d::prelude::Position;
+use bevy::prelude::*;
+use lightyear::prelude::{HasAuthority, Replicated};
+
+use crate::{ability_framework::{pool::Pool, pools::life::{Life, LifePool}}, player::CursorPosition};
+
+use super::stepper::{test_settings, GameStepper};
+
+#[test]
+fn clients_cannot_teleport_or_heal() {
+    let mut stepper = GameStepper::new(None);
+    let (server_player, client_player) = stepper.start_round();
+
+    let start = stepper.server_app.world().get::<Position>(server_player).unwrap().0;
+    stepper.server_app.world_mut().get_mut::<LifePool>(server_player).unwrap().set_current(Life(10.));
+    stepper.frame_step();
+
+    for _ in 0..30 {
+        let world = stepper.client_app.world_mut();
+        world.get_mut::<Position>(client_player).unwrap().0 = Vec3::new(500., 0., 500.);
+        world.get_mut::<LifePool>(client_player).unwrap().set_current(Life(100.));
+        stepper.frame_step();
+    }
+
+    let world = stepper.server_app.world();
+    let position = world.get::<Position>(server_player).unwrap().0;
+    assert!(position.distance(start) < 1., "the client teleported its player from {start} to {position}");
+    // a few points of regeneration at most
+    let life = world.get::<LifePool>(server_player).unwrap().current();
+    assert!(life.0 < 20., "the client healed its player to {}", life.0);
+}
+
+#[test]
+fn repeated_invalid_cursors_get_the_client_kicked() {
+    let mut stepper = GameStepper::new(None);
+    stepper.start_round();
+
+    let server_cursor = |stepper: &mut GameStepper| {
+        stepper
+            .server_app
+            .world_mut()
+            .query_filtered::<&CursorPosition, With<Replicated>>()
+            .get_single(stepper.server_app.world())
+            .ok()
+            .map(|cursor| cursor.0)
+    };
+    let received = stepper.step_until(100, |stepper| server_cursor(stepper).is_some());
+    assert!(received, "the server never received the client's cursor");
+
+    let send_invalid_cursor = |stepper: &mut GameStepper| {
+        let world = stepper.client_app.world_mut();
+        let mut cursor = world.query_filtered::<&mut CursorPosition, With<HasAuthority>>().single_mut(world);
+        cursor.0 = Vec3::NAN;
+    };
+
+    send_invalid_cursor(&mut stepper);
+    for _ in 0..10 {
+        stepper.frame_step();
+        let cursor = server_cursor(&mut stepper).unwrap();
+        assert!(cursor.is_finite(), "the server kept the invalid cursor {cursor}");
+    }
+    assert!(stepper.client_connected(), "a single invalid cursor got the client kicked");
+
+    let max_strikes = test_settings().validation.max_strikes as usize;
+    let kicked = stepper.step_until(max_strikes * 10, |stepper| {
+        if !stepper.client_connected() {
+            return true;
+        }
+        send_invalid_cursor(stepper);
+        false
+    });
+    assert!(kicked, "the client was never kicked after repeatedly sending invalid cursors");
+}
@@ -0,0 +1,220 @@
+use std::time::Duration;
+
+use avian3d::prelude::{AngularVelocity, LinearVelocity, Position, Rotation};
+use bevy::{prelude::*, utils::HashMap};
+use lightyear::prelude::{server::{ServerConnections, ServerReplicationSet}, ClientId, MainSet, Replicated};
+use lightyear::server::events::DisconnectEvent;
+
+use crate::{ability_framework::{pools::{heat::HeatPool, life::LifePool, mana::ManaPool}, status_effect::StatusEffects}, combat::Dead, player::{CursorPosition, MoveSpeed, PlayerId}, projectile::Projectile, settings::ValidationSettings, team::Team};
+
+/// Checks what clients replicate to the server before the rest of the server uses or rebroadcasts it.
+/// Gameplay components are registered `ServerToClient`, so clients only author their cursor. Invalid
+/// updates are reverted or dropped and earn the client a strike, clients with too many strikes are kicked.
+pub struct ClientValidationPlugin {
+    pub settings: ValidationSettings,
+}
+
+impl Plugin for ClientValidationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.settings.clone());
+        app.init_resource::<Strikes>();
+        app.add_event::<Violation>();
+
+        app.configure_sets(
+            PreUpdate,
+            ValidationSet
+                .after(MainSet::EmitEvents)
+                .before(ServerReplicationSet::ClientReplication)
+        );
+        app.add_systems(PreUpdate, (
+                (
+                    reject_server_components,
+                    reject_extra_cursors,
+                    validate_cursors,
+                ),
+                punish_violations,
+            )
+            .chain()
+            .in_set(ValidationSet)
+        );
+        app.add_systems(Update, forget_disconnected_clients);
+    }
+}
+
+/// Client-authored components are validated in this set, after they are received
+#[derive(SystemSet, Hash, PartialEq, Eq, Clone, Copy, Debug)]
+pub struct ValidationSet;
+
+/// An update from a client that was rejected or reverted
+#[derive(Event, Debug)]
+pub struct Violation {
+    pub client_id: ClientId,
+    pub entity: Entity,
+    pub component: &'static str,
+}
+
+#[derive(Resource, Default)]
+struct Strikes {
+    clients: HashMap<ClientId, StrikeRecord>,
+}
+
+struct StrikeRecord {
+    strikes: u32,
+    last: Duration,
+}
+
+/// Components only the server writes. An unmodified client never sends them, but nothing stops a
+/// modified one from attaching them to the entities it spawns.
+type ServerAuthored = Or<(
+    With<PlayerId>,
+    With<Position>,
+    With<Rotation>,
+    With<LinearVelocity>,
+    With<AngularVelocity>,
+    With<MoveSpeed>,
+    With<LifePool>,
+    With<ManaPool>,
+    With<HeatPool>,
+    With<StatusEffects>,
+    With<Team>,
+    With<Dead>,
+    With<Projectile>,
+)>;
+
+fn reject_server_components(
+    mut commands: Commands,
+    mut violations: EventWriter<Violation>,
+    query: Query<(Entity, &Replicated), ServerAuthored>,
+) {
+    for (entity, replicated) in query.iter() {
+        violations.send(Violation { client_id: replicated.client_id(), entity, component: "server-authored component" });
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Aiming reads the client's cursor, a client spawning several could pick whichever suits it
+fn reject_extra_cursors(
+    mut commands: Commands,
+    mut violations: EventWriter<Violation>,
+    added_query: Query<(Entity, &Replicated), Added<CursorPosition>>,
+    cursor_query: Query<(Entity, &Replicated), With<CursorPosition>>,
+) {
+    for (entity, replicated) in added_query.iter() {
+        let client_id = replicated.client_id();
+        let duplicate = cursor_query
+            .iter()
+            .any(|(other, other_replicated)| other != entity && other_replicated.client_id() == client_id);
+        if duplicate {
+            violations.send(Violation { client_id, entity, component: "CursorPosition" });
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Cursor accepted from a client, `None` when it isn't a point in the world. Legitimate cursors can
+/// land far away when aiming towards the horizon, so distant ones are only brought closer to the player.
+fn check_cursor(cursor: Vec3, player: Option<Vec3>, max_distance: f32) -> Option<Vec3> {
+    if !cursor.is_finite() {
+        return None;
+    }
+    let Some(player) = player else {
+        return Some(cursor);
+    };
+    Some(player + (cursor - player).clamp_length_max(max_distance))
+}
+
+/// Every update is checked, the one spawning the cursor included
+fn validate_cursors(
+    settings: Res<ValidationSettings>,
+    mut violations: EventWriter<Violation>,
+    mut query: Query<(Entity, &Replicated, &mut CursorPosition), Changed<CursorPosition>>,
+    player_query: Query<(&PlayerId, &Position), Without<Replicated>>,
+) {
+    for (entity, replicated, mut cursor) in query.iter_mut() {
+        let client_id = replicated.client_id();
+        let player = player_query
+            .iter()
+            .find(|(player_id, _)| player_id.0 == client_id)
+            .map(|(_, position)| position.0);
+
+        match check_cursor(cursor.0, player, settings.max_cursor_distance) {
+            Some(checked) => {
+                if checked != cursor.0 {
+                    cursor.0 = checked;
+                }
+            },
+            None => {
+                // aiming at the player itself fires nowhere
+                cursor.0 = player.unwrap_or_default();
+                violations.send(Violation { client_id, entity, component: "CursorPosition" });
+            },
+        }
+    }
+}
+
+fn punish_violations(
+    time: Res<Time<Real>>,
+    settings: Res<ValidationSettings>,
+    mut strikes: ResMut<Strikes>,
+    mut connections: ResMut<ServerConnections>,
+    mut violations: EventReader<Violation>,
+) {
+    let now = time.elapsed();
+
+    for violation in violations.read() {
+        let client_id = violation.client_id;
+        let record = strikes.clients.entry(client_id).or_insert(StrikeRecord {
+            strikes: 0,
+            last: now,
+        });
+        // strikes are forgiven over time so that a few glitches don't add up to a kick
+        let forgiven = ((now - record.last).as_secs_f32() / settings.strike_decay_seconds) as u32;
+        record.strikes = record.strikes.saturating_sub(forgiven) + 1;
+        record.last = now;
+
+        warn!(
+            "Rejected {} update on {:?} from client {client_id:?}, strike {}/{}",
+            violation.component, violation.entity, record.strikes, settings.max_strikes,
+        );
+        if record.strikes < settings.max_strikes {
+            continue;
+        }
+
+        warn!("Kicking client {client_id:?} after {} strikes", record.strikes);
+        strikes.clients.remove(&client_id);
+        if let Err(e) = connections.disconnect(client_id) {
+            error!("Could not kick client {client_id:?}: {e:?}");
+        }
+    }
+}
+
+fn forget_disconnected_clients(
+    mut strikes: ResMut<Strikes>,
+    mut events: EventReader<DisconnectEvent>,
+) {
+    for event in events.read() {
+        strikes.clients.remove(&event.client_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursors_must_be_points_in_the_world() {
+        assert_eq!(check_cursor(Vec3::NAN, Some(Vec3::ZERO), 10.), None);
+        assert_eq!(check_cursor(Vec3::new(f32::INFINITY, 0., 0.), None, 10.), None);
+        assert_eq!(check_cursor(Vec3::new(3., 0., 4.), Some(Vec3::ZERO), 10.), Some(Vec3::new(3., 0., 4.)));
+    }
+
+    #[test]
+    fn distant_cursors_are_brought_closer() {
+        let player = Vec3::new(1., 0., 1.);
+        let checked = check_cursor(Vec3::new(101., 0., 1.), Some(player), 10.).unwrap();
+        assert!(checked.abs_diff_eq(Vec3::new(11., 0., 1.), 1e-4));
+
+        // without a player there is nothing to measure from
+        assert_eq!(check_cursor(Vec3::splat(1000.), None, 10.), Some(Vec3::splat(1000.)));
+    }
+}